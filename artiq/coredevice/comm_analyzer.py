@@ -34,6 +34,76 @@ class ExceptionType(Enum):
     i_overflow = 0b100001
 
 
+class AnalyzerRequest(Enum):
+    dump = 0x01
+    stream = 0x02
+
+    set_channel_filter = 0x10
+    set_trigger = 0x11
+    set_trigger_depth = 0x12
+    arm = 0x13
+
+
+class AnalyzerConfig:
+    """Selection of the RTIO events captured by the core device analyzer.
+
+    Triggers are evaluated on every event, including events on channels
+    that are not captured. The event that fires a trigger is included in
+    the capture if its channel is selected.
+
+    :param channels: list of channels to capture, or ``None`` for all.
+    :param start_channel: start capturing at the first event on this channel.
+    :param start_timestamp: start capturing at the first event with a
+        timestamp (in machine units) at or after this value.
+    :param stop_channel: stop capturing at the first event on this channel.
+    :param stop_timestamp: stop capturing at the first event with a
+        timestamp (in machine units) at or after this value.
+    :param pre_trigger: number of events to keep from before the start
+        trigger.
+    :param post_trigger: number of events to keep after the stop trigger.
+
+    The core device holds up to 16384 events, which also limits
+    ``pre_trigger`` and ``post_trigger``.
+    """
+    def __init__(self, channels=None,
+                 start_channel=None, start_timestamp=None,
+                 stop_channel=None, stop_timestamp=None,
+                 pre_trigger=0, post_trigger=0):
+        if start_channel is not None and start_timestamp is not None:
+            raise ValueError("only one start trigger may be given")
+        if stop_channel is not None and stop_timestamp is not None:
+            raise ValueError("only one stop trigger may be given")
+        self.channels = channels
+        self.start_channel = start_channel
+        self.start_timestamp = start_timestamp
+        self.stop_channel = stop_channel
+        self.stop_timestamp = stop_timestamp
+        self.pre_trigger = pre_trigger
+        self.post_trigger = post_trigger
+
+    @staticmethod
+    def _encode_trigger(channel, timestamp):
+        if channel is not None:
+            return struct.pack(">BI", 1, channel)
+        elif timestamp is not None:
+            return struct.pack(">Bq", 2, timestamp)
+        else:
+            return struct.pack(">B", 0)
+
+    def encode(self):
+        # Requests are always big endian.
+        channels = self.channels or []
+        r = struct.pack(">BI", AnalyzerRequest.set_channel_filter.value,
+                        len(channels))
+        r += b"".join(struct.pack(">I", channel) for channel in channels)
+        r += struct.pack(">B", AnalyzerRequest.set_trigger.value)
+        r += self._encode_trigger(self.start_channel, self.start_timestamp)
+        r += self._encode_trigger(self.stop_channel, self.stop_timestamp)
+        r += struct.pack(">BII", AnalyzerRequest.set_trigger_depth.value,
+                         self.pre_trigger, self.post_trigger)
+        return r
+
+
 def get_analyzer_dump(host, port=1382):
     sock = socket.create_connection((host, port))
     try:
@@ -48,6 +118,107 @@ def get_analyzer_dump(host, port=1382):
     return r
 
 
+def arm_analyzer(host, config, port=1382):
+    """Re-arms the core device analyzer with the given ``AnalyzerConfig``.
+
+    The configuration remains in effect for subsequent dumps (including
+    those obtained with ``get_analyzer_dump``) until the analyzer is armed
+    again. Arming with a default ``AnalyzerConfig`` restores capturing of
+    all events."""
+    sock = socket.create_connection((host, port))
+    try:
+        sock.sendall(config.encode() +
+                     struct.pack("B", AnalyzerRequest.arm.value))
+        reply = _recv_exactly(sock, 1)
+        if reply == b"\x02":
+            raise IOError("Core device does not have enough memory for the "
+                          "analyzer configuration; the previous one remains "
+                          "in effect")
+        elif reply != b"\x01":
+            raise IOError("Incorrect reply from device")
+    finally:
+        sock.close()
+
+
+StreamOverflow = namedtuple(
+    "StreamOverflow", "lost_bytes encoder_overflow")
+
+
+def _recv_exactly(sock, length):
+    r = bytes()
+    while len(r) < length:
+        buf = sock.recv(min(8192, length - len(r)))
+        if not buf:
+            raise ConnectionResetError("Connection closed")
+        r += buf
+    return r
+
+
+class AnalyzerStream:
+    """Connection to the core device analyzer in streaming mode.
+
+    Iterating over the stream yields decoded messages as they are recorded
+    by the core device. Whenever records were lost because the client could
+    not keep up with the ring buffer, a ``StreamOverflow`` is yielded in
+    their place; records immediately preceding an overflow marker may be
+    inconsistent. Iteration ends when the connection is closed, which
+    happens after the stop trigger if ``config`` defines one.
+
+    :param config: optional ``AnalyzerConfig`` applied to this stream only.
+    """
+    def __init__(self, host, port=1382, config=None):
+        self.sock = socket.create_connection((host, port))
+        request = b""
+        if config is not None:
+            request += config.encode()
+        request += struct.pack("B", AnalyzerRequest.stream.value)
+        self.sock.sendall(request)
+        endian_byte = _recv_exactly(self.sock, 1)
+        if endian_byte == b"E":
+            self.endian = ">"
+        elif endian_byte == b"e":
+            self.endian = "<"
+        else:
+            raise ValueError
+        header = _recv_exactly(self.sock, 17)
+        (_, _, _, log_channel, dds_onehot_sel,
+         version, mode) = struct.unpack(self.endian + "IQbbbBB", header)
+        if version != 1 or mode != 1:
+            raise ValueError("unsupported analyzer stream "
+                             "(version {}, mode {})".format(version, mode))
+        self.log_channel = log_channel
+        self.dds_onehot_sel = bool(dds_onehot_sel)
+
+    def close(self):
+        self.sock.close()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, type, value, traceback):
+        self.close()
+
+    def __iter__(self):
+        while True:
+            try:
+                tag = _recv_exactly(self.sock, 1)[0]
+            except ConnectionResetError:
+                return
+            if tag == 0:
+                length = struct.unpack(self.endian + "I",
+                                       _recv_exactly(self.sock, 4))[0]
+                data = _recv_exactly(self.sock, length)
+                for position in range(0, length, 32):
+                    yield decode_message(data[position:position+32])
+            elif tag == 1:
+                lost_bytes, encoder_overflow = struct.unpack(
+                    self.endian + "Qb", _recv_exactly(self.sock, 9))
+                yield StreamOverflow(lost_bytes, bool(encoder_overflow))
+            else:
+                raise ValueError("unknown analyzer stream record {}"
+                                 .format(tag))
+
+
 OutputMessage = namedtuple(
     "OutputMessage", "channel timestamp rtio_counter address data")
 
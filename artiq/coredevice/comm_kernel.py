@@ -23,6 +23,9 @@ class Request(Enum):
     RPCReply = 7
     RPCException = 8
 
+    AbortKernel = 9
+    RunKernelWatchdog = 10
+
 
 class Reply(Enum):
     SystemInfo = 2
@@ -38,6 +41,34 @@ class Reply(Enum):
 
     ClockFailure = 15
 
+    KernelAborted = 16
+
+    WatchdogExpired = 17
+
+    RTIOEvents = 18
+
+
+class KernelState(Enum):
+    Absent = 0
+    Loaded = 1
+    Running = 2
+    RPCWait = 3
+
+
+class RTIOEventKind(Enum):
+    DestinationUp = 0
+    DestinationDown = 1
+    AsyncError = 2
+
+
+#: A change of state of a DRTIO destination, or an RTIO async error, reported
+#: by the core device while a kernel runs. ``timestamp_ms`` is the time since
+#: the core device started. ``errors`` (a bit mask as in the kernel finished
+#: message: collision, busy error, sequence error) and ``channel`` are set
+#: for async errors only.
+RTIOEvent = namedtuple("RTIOEvent", ["timestamp_ms", "destination", "kind",
+                                     "errors", "channel"])
+
 
 class UnsupportedDevice(Exception):
     pass
@@ -130,6 +161,15 @@ def _receive_keyword(kernel, embedding_map):
     return RPCKeyword(name, value)
 
 
+class _DiscardingEmbeddingMap:
+    # Stands in for the embedding map when skipping over RPC arguments.
+    def retrieve_object(self, key):
+        return None
+
+
+_discarding_embedding_map = _DiscardingEmbeddingMap()
+
+
 receivers = {
     "\x00": lambda kernel, embedding_map: kernel._rpc_sentinel,
     "t": lambda kernel, embedding_map:
@@ -160,9 +200,12 @@ class CommKernelDummy:
     def load(self, kernel_library):
         pass
 
-    def run(self):
+    def run(self, watchdog_ms=None):
         pass
 
+    def abort(self):
+        return KernelState.Absent
+
     def serve(self, embedding_map, symbolizer, demangler):
         pass
 
@@ -189,6 +232,9 @@ class CommKernel:
         self.port = port
         self.read_buffer = bytearray()
         self.write_buffer = bytearray()
+        # Called with each RTIOEvent received while serving a kernel, e.g.
+        # to pause experiments when a satellite goes down.
+        self.rtio_event_handler = None
 
 
     def open(self):
@@ -382,11 +428,78 @@ class CommKernel:
         else:
             self._read_expect(Reply.LoadCompleted)
 
-    def run(self):
-        self._write_empty(Request.RunKernel)
+    def run(self, watchdog_ms=None):
+        """Start the loaded kernel.
+
+        :param watchdog_ms: stop the kernel if it does not communicate with
+            the runtime for this many milliseconds (0 disables the watchdog).
+            If None, the ``kernel_watchdog_ms`` configuration key of the core
+            device is used."""
+        if watchdog_ms is None:
+            self._write_empty(Request.RunKernel)
+        else:
+            self._write_header(Request.RunKernelWatchdog)
+            self._write_int32(watchdog_ms)
         self._flush()
         logger.debug("running kernel")
 
+    def abort(self):
+        """Stop the kernel currently loaded or running on the core device,
+        keeping the connection open. Must not be called while an RPC request
+        from the kernel is being served. Messages sent by the kernel before
+        it was stopped, including its result, are discarded.
+
+        Returns the state the kernel was in before it was aborted."""
+        self._write_empty(Request.AbortKernel)
+        self._flush()
+
+        # The kernel may have sent messages, or finished, before the request
+        # reached it.
+        self._read_header()
+        while self._read_type != Reply.KernelAborted:
+            self._discard_reply()
+            self._read_header()
+        state = KernelState(self._read_int8())
+        logger.debug("kernel aborted in state %s", state)
+        return state
+
+    def _discard_reply(self):
+        logger.debug("discarding %s received while aborting the kernel",
+                     self._read_type)
+        if self._read_type == Reply.RTIOEvents:
+            self._process_rtio_events()
+        elif self._read_type == Reply.RPCRequest:
+            self._read_bool()  # is_async
+            self._read_int32()  # service_id
+            self._receive_rpc_args(_discarding_embedding_map)
+            self._read_bytes()  # return_tags
+        elif self._read_type == Reply.KernelException:
+            self._discard_exception()
+        elif self._read_type == Reply.WatchdogExpired:
+            self._read_int32()  # timeout_ms
+            self._read_string()  # last_message
+        elif self._read_type == Reply.KernelFinished:
+            self._process_async_error()
+        elif self._read_type != Reply.ClockFailure:
+            self._read_expect(Reply.KernelAborted)
+
+    def _discard_exception(self):
+        def skip_exception_string():
+            length = self._read_int32()
+            self._read(4 if length == -1 else length)
+
+        exception_count = self._read_int32()
+        for _ in range(exception_count):
+            self._read_int32()  # name
+            skip_exception_string()  # message
+            self._read(3 * 8)  # params
+            skip_exception_string()  # filename
+            self._read(2 * 4)  # line, column
+            skip_exception_string()  # function
+        self._read(exception_count * 3 * 4)  # exception_info
+        self._read(self._read_int32() * 2 * 4)  # backtrace, stack_pointers
+        self._process_async_error()
+
     _rpc_sentinel = object()
 
     # See rpc_proto.rs and compiler/ir.py:rpc_tag.
@@ -707,15 +820,46 @@ class CommKernel:
             logger.warning(f"{(', '.join(errors[:-1]) + ' and ') if len(errors) > 1 else ''}{errors[-1]} "
                            f"reported during kernel execution")
 
+    def _process_rtio_events(self):
+        dropped = self._read_int32()
+        if dropped:
+            logger.warning("%d RTIO event(s) lost by the core device", dropped)
+        for _ in range(self._read_int32()):
+            event = RTIOEvent(
+                timestamp_ms=self._read_int64(),
+                destination=self._read_int8(),
+                kind=RTIOEventKind(self._read_int8()),
+                errors=self._read_int8(),
+                channel=self._read_int32())
+            if event.kind == RTIOEventKind.DestinationUp:
+                logger.info("destination %d is up", event.destination)
+            elif event.kind == RTIOEventKind.DestinationDown:
+                logger.warning("destination %d is down", event.destination)
+            else:
+                logger.warning("RTIO async error(s) 0x%02x involving channel "
+                               "0x%04x of destination %d", event.errors,
+                               event.channel, event.destination)
+            if self.rtio_event_handler is not None:
+                self.rtio_event_handler(event)
+
     def serve(self, embedding_map, symbolizer, demangler):
         while True:
             self._read_header()
             if self._read_type == Reply.RPCRequest:
                 self._serve_rpc(embedding_map)
+            elif self._read_type == Reply.RTIOEvents:
+                self._process_rtio_events()
             elif self._read_type == Reply.KernelException:
                 self._serve_exception(embedding_map, symbolizer, demangler)
             elif self._read_type == Reply.ClockFailure:
                 raise exceptions.ClockFailure
+            elif self._read_type == Reply.WatchdogExpired:
+                timeout_ms = self._read_int32()
+                last_message = self._read_string()
+                raise exceptions.WatchdogExpired(
+                    "kernel stopped by watchdog after {} ms without activity; "
+                    "last message from kernel CPU: {}".format(
+                        timeout_ms, last_message or "none"))
             else:
                 self._read_expect(Reply.KernelFinished)
                 self._process_async_error()
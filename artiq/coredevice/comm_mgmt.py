@@ -18,6 +18,32 @@ class Request(Enum):
     ConfigWrite = 13
     ConfigRemove = 14
     ConfigErase = 15
+    ConfigList = 16
+    ConfigDump = 17
+    ConfigRestore = 18
+    ConfigStats = 19
+
+    DmaTraceList = 20
+    DmaTraceExport = 21
+    DmaTraceImport = 22
+    DmaTraceDelete = 23
+
+    DmaList = 24
+    DmaErase = 25
+    DmaClear = 26
+    CacheList = 27
+    CacheErase = 28
+    CacheClear = 29
+
+    DrtioLinkStats = 30
+    DrtioStatus = 31
+    DrtioFirmwareUpdate = 32
+    DrtioReboot = 33
+    DrtioTopology = 34
+    DrtioSetRoutingTable = 35
+    DrtioConfigRead = 36
+    DrtioConfigWrite = 37
+    DrtioConfigRemove = 38
 
     Reboot = 5
 
@@ -32,6 +58,20 @@ class Reply(Enum):
     LogContent = 2
 
     ConfigData = 7
+    ConfigKeys = 12
+    ConfigDump = 13
+    ConfigRejected = 15
+    ConfigStats = 14
+
+    DmaTraceList = 8
+    DmaTrace = 9
+
+    DmaList = 10
+    CacheList = 11
+
+    DrtioLinkStats = 16
+    DrtioStatus = 17
+    DrtioTopology = 18
 
     RebootImminent = 3
 
@@ -87,6 +127,9 @@ class CommMgmt:
     def _write_int32(self, value):
         self._write(struct.pack(self.endian + "l", value))
 
+    def _write_int64(self, value):
+        self._write(struct.pack(self.endian + "q", value))
+
     def _write_bytes(self, value):
         self._write_int32(len(value))
         self._write(value)
@@ -115,10 +158,17 @@ class CommMgmt:
             raise IOError("Incorrect reply from device: {} (expected {})".
                           format(header, ty))
 
+    def _read_bool(self):
+        return self._read(1)[0] != 0
+
     def _read_int32(self):
         (value, ) = struct.unpack(self.endian + "l", self._read(4))
         return value
 
+    def _read_int64(self):
+        (value, ) = struct.unpack(self.endian + "q", self._read(8))
+        return value
+
     def _read_bytes(self):
         return self._read(self._read_int32())
 
@@ -157,8 +207,14 @@ class CommMgmt:
         self._write_int8(getattr(LogLevel, level).value)
         self._read_expect(Reply.Success)
 
-    def config_read(self, key):
-        self._write_header(Request.ConfigRead)
+    def config_read(self, key, destination=0):
+        """Reads a key from the config of the core device, or of the DRTIO
+        satellite ``destination``."""
+        if destination:
+            self._write_header(Request.DrtioConfigRead)
+            self._write_int8(destination)
+        else:
+            self._write_header(Request.ConfigRead)
         self._write_string(key)
         ty = self._read_header()
         if ty == Reply.Error:
@@ -168,19 +224,29 @@ class CommMgmt:
                           format(ty, Reply.ConfigData))
         return self._read_string()
 
-    def config_write(self, key, value):
-        self._write_header(Request.ConfigWrite)
+    def config_write(self, key, value, destination=0):
+        if destination:
+            self._write_header(Request.DrtioConfigWrite)
+            self._write_int8(destination)
+        else:
+            self._write_header(Request.ConfigWrite)
         self._write_string(key)
         self._write_bytes(value)
         ty = self._read_header()
-        if ty == Reply.Error:
+        if ty == Reply.ConfigRejected:
+            raise ValueError(self._read_string())
+        elif ty == Reply.Error:
             raise IOError("Device failed to write config. More information may be available in the log.")
         elif ty != Reply.Success:
             raise IOError("Incorrect reply from device: {} (expected {})".
                           format(ty, Reply.Success))
 
-    def config_remove(self, key):
-        self._write_header(Request.ConfigRemove)
+    def config_remove(self, key, destination=0):
+        if destination:
+            self._write_header(Request.DrtioConfigRemove)
+            self._write_int8(destination)
+        else:
+            self._write_header(Request.ConfigRemove)
         self._write_string(key)
         self._read_expect(Reply.Success)
 
@@ -188,6 +254,269 @@ class CommMgmt:
         self._write_header(Request.ConfigErase)
         self._read_expect(Reply.Success)
 
+    def config_list(self):
+        self._write_header(Request.ConfigList)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Device failed to read config. It may be corrupted.")
+        elif ty != Reply.ConfigKeys:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.ConfigKeys))
+        return [self._read_string() for _ in range(self._read_int32())]
+
+    def config_dump(self):
+        """Returns a dictionary of all keys and values in the core device
+        config."""
+        self._write_header(Request.ConfigDump)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Device failed to read config. It may be corrupted.")
+        elif ty != Reply.ConfigDump:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.ConfigDump))
+        entries = dict()
+        for _ in range(self._read_int32()):
+            key = self._read_string()
+            entries[key] = self._read_bytes()
+        return entries
+
+    def config_restore(self, entries):
+        """Replaces the whole core device config with the keys and values
+        of the ``entries`` dictionary. The config is left untouched if the
+        entries do not fit, or if a value is invalid for its key, in which
+        case ``ValueError`` is raised."""
+        self._write_header(Request.ConfigRestore)
+        self._write_int32(len(entries))
+        for key, value in entries.items():
+            self._write_string(key)
+            self._write_bytes(value)
+        ty = self._read_header()
+        if ty == Reply.ConfigRejected:
+            raise ValueError(self._read_string())
+        elif ty == Reply.Error:
+            raise IOError("Device failed to restore config. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def config_stats(self):
+        """Returns a dictionary describing the space usage of the core
+        device config sector. ``corruption`` is the offset of the first
+        corrupted record, or ``None``."""
+        self._write_header(Request.ConfigStats)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Device failed to read config. More information may be available in the log.")
+        elif ty != Reply.ConfigStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.ConfigStats))
+        stats = dict()
+        for field in ("capacity", "used", "live_bytes", "live_records",
+                      "dead_records"):
+            stats[field] = self._read_int32()
+        corrupted = self._read_bool()
+        corruption = self._read_int32()
+        stats["corruption"] = corruption if corrupted else None
+        return stats
+
+    def _read_dma_reply(self, ty, action):
+        reply = self._read_header()
+        if reply == Reply.Error:
+            raise IOError("Device failed to {}. More information may be "
+                          "available in the log.".format(action))
+        elif reply != ty:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(reply, ty))
+
+    def dma_list(self):
+        """Returns a list of ``(name, duration_mu, size)`` tuples describing
+        the DMA traces stored in the core device flash."""
+        self._write_header(Request.DmaTraceList)
+        self._read_dma_reply(Reply.DmaTraceList, "list DMA traces")
+        traces = []
+        for _ in range(self._read_int32()):
+            name = self._read_string()
+            duration = self._read_int64()
+            size = self._read_int32()
+            traces.append((name, duration, size))
+        return traces
+
+    def dma_export(self, name):
+        """Returns the duration (in machine units) and the contents of the
+        stored DMA trace ``name``."""
+        self._write_header(Request.DmaTraceExport)
+        self._write_string(name)
+        self._read_dma_reply(Reply.DmaTrace, "export DMA trace")
+        duration = self._read_int64()
+        trace = self._read_bytes()
+        return duration, trace
+
+    def dma_import(self, name, duration, trace):
+        self._write_header(Request.DmaTraceImport)
+        self._write_string(name)
+        self._write_int64(duration)
+        self._write_bytes(trace)
+        self._read_dma_reply(Reply.Success, "import DMA trace")
+
+    def dma_delete(self, name):
+        self._write_header(Request.DmaTraceDelete)
+        self._write_string(name)
+        self._read_dma_reply(Reply.Success, "delete DMA trace")
+
+    def _read_memory_reply(self, ty, action):
+        reply = self._read_header()
+        if reply == Reply.Error:
+            raise IOError("Device failed to {}. The entry may be in use by "
+                          "the kernel.".format(action))
+        elif reply != ty:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(reply, ty))
+
+    def dma_loaded(self):
+        """Returns a list of ``(name, size, duration_mu, borrowed)`` tuples
+        describing the DMA traces held in the core device memory."""
+        self._write_header(Request.DmaList)
+        self._read_expect(Reply.DmaList)
+        traces = []
+        for _ in range(self._read_int32()):
+            name = self._read_string()
+            size = self._read_int32()
+            duration = self._read_int64()
+            borrowed = self._read_bool()
+            traces.append((name, size, duration, borrowed))
+        return traces
+
+    def dma_erase(self, name):
+        self._write_header(Request.DmaErase)
+        self._write_string(name)
+        self._read_memory_reply(Reply.Success, "erase DMA trace")
+
+    def dma_clear(self):
+        self._write_header(Request.DmaClear)
+        self._read_memory_reply(Reply.Success, "erase all DMA traces")
+
+    def cache_list(self):
+        """Returns a list of ``(key, length, borrowed)`` tuples describing
+        the entries of the core device cache."""
+        self._write_header(Request.CacheList)
+        self._read_expect(Reply.CacheList)
+        entries = []
+        for _ in range(self._read_int32()):
+            key = self._read_string()
+            length = self._read_int32()
+            borrowed = self._read_bool()
+            entries.append((key, length, borrowed))
+        return entries
+
+    def cache_erase(self, key):
+        self._write_header(Request.CacheErase)
+        self._write_string(key)
+        self._read_memory_reply(Reply.Success, "erase cache entry")
+
+    def cache_clear(self):
+        self._write_header(Request.CacheClear)
+        self._read_memory_reply(Reply.Success, "erase all cache entries")
+
+    def drtio_link_stats(self, destination=0):
+        """Returns a list of dictionaries with the counters of each DRTIO
+        link of a destination. For destination 0, these are the links of the
+        master; for a satellite, its uplink followed by its repeater links.
+        ``ping_latency_us`` is the round trip of the last aux echo, and
+        ``uptime_ms`` is zero if the link is down."""
+        self._write_header(Request.DrtioLinkStats)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Device failed to read link statistics. More information may be available in the log.")
+        elif ty != Reply.DrtioLinkStats:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.DrtioLinkStats))
+        links = []
+        for _ in range(self._read_int32()):
+            stats = dict()
+            for field in ("crc_errors", "unknown_packets", "truncated_packets",
+                          "buffer_space_timeouts", "link_down_events",
+                          "retransmissions", "ping_latency_us"):
+                stats[field] = self._read_int32()
+            stats["uptime_ms"] = self._read_int64()
+            links.append(stats)
+        return links
+
+    def drtio_status(self, destination):
+        """Returns a text report of the state of a DRTIO satellite: its
+        identifiers, rank, links and routing table."""
+        self._write_header(Request.DrtioStatus)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Device failed to read satellite status. More information may be available in the log.")
+        elif ty != Reply.DrtioStatus:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.DrtioStatus))
+        return self._read_string()
+
+    def drtio_topology(self):
+        """Discovers the satellites that are up and returns a dictionary
+        mapping the destination numbers given to them by the master to the
+        hops that lead to them. The rank of a destination is its number of
+        hops; the local RTIO core is destination 0, with no hops."""
+        self._write_header(Request.DrtioTopology)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Device failed to discover the DRTIO topology. More information may be available in the log.")
+        elif ty != Reply.DrtioTopology:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.DrtioTopology))
+        topology = dict()
+        for _ in range(self._read_int32()):
+            destination = self._read(1)[0]
+            topology[destination] = list(self._read_bytes())
+        return topology
+
+    def drtio_set_routing_table(self, table):
+        """Installs a routing table (in the format of the ``routing_table``
+        config key) on the running master, which pushes it to the satellites
+        that are up. The config is left unchanged.
+
+        The table is pushed asynchronously: success only means that the master
+        has accepted the table. Failures to load it on a satellite are
+        reported in the core device log."""
+        self._write_header(Request.DrtioSetRoutingTable)
+        self._write_bytes(table)
+        ty = self._read_header()
+        if ty == Reply.ConfigRejected:
+            raise ValueError(self._read_string())
+        elif ty == Reply.Error:
+            raise IOError("Device failed to set the routing table. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def drtio_firmware_update(self, destination, image):
+        """Writes a firmware image (the contents of ``satman.fbi``) to the
+        boot flash of a DRTIO satellite. The image is checked before the flash
+        is erased; the new firmware starts at the next reboot of the
+        satellite."""
+        self._write_header(Request.DrtioFirmwareUpdate)
+        self._write_int8(destination)
+        self._write_bytes(image)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Device failed to write satellite firmware. More information may be available in the log.")
+        elif ty != Reply.Success:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.Success))
+
+    def drtio_reboot(self, destination):
+        self._write_header(Request.DrtioReboot)
+        self._write_int8(destination)
+        ty = self._read_header()
+        if ty == Reply.Error:
+            raise IOError("Device failed to reboot satellite. More information may be available in the log.")
+        elif ty != Reply.RebootImminent:
+            raise IOError("Incorrect reply from device: {} (expected {})".
+                          format(ty, Reply.RebootImminent))
+
     def reboot(self):
         self._write_header(Request.Reboot)
         self._read_expect(Reply.RebootImminent)
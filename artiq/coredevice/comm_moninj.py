@@ -23,10 +23,14 @@ class TTLOverride(Enum):
 
 
 class CommMonInj:
-    def __init__(self, monitor_cb, injection_status_cb, disconnect_cb=None):
+    def __init__(self, monitor_cb, injection_status_cb, disconnect_cb=None,
+                 snapshot_cb=None, history_cb=None, injection_changed_cb=None):
         self.monitor_cb = monitor_cb
         self.injection_status_cb = injection_status_cb
         self.disconnect_cb = disconnect_cb
+        self.snapshot_cb = snapshot_cb
+        self.history_cb = history_cb
+        self.injection_changed_cb = injection_changed_cb
 
     async def connect(self, host, port=1383):
         self._reader, self._writer = await async_open_connection(
@@ -78,6 +82,54 @@ class CommMonInj:
         packet = struct.pack("<blb", 2, channel, override)
         self._writer.write(packet)
 
+    def set_poll_interval(self, interval_ms):
+        """Sets how often the core device checks the watched probes and
+        injection statuses for changes (200 ms by default)."""
+        packet = struct.pack("<bl", 4, interval_ms)
+        self._writer.write(packet)
+
+    def batch_updates(self, enable=True):
+        """Makes the core device send all changes found during a poll in
+        a single message. The callbacks are still called once per change."""
+        packet = struct.pack("<bb", 5, enable)
+        self._writer.write(packet)
+
+    def lease_overrides(self, enable=True, timeout_ms=0):
+        """Makes the core device release the overrides enabled through this
+        connection when it closes. If ``timeout_ms`` is non-zero, they are
+        also released when nothing has been sent for that long."""
+        packet = struct.pack("<bbl", 6, enable, timeout_ms)
+        self._writer.write(packet)
+
+    def snapshot(self, destination=0):
+        """Requests the values of all probes and overrides of a destination.
+        The reply is passed to ``snapshot_cb`` as ``(destination, probes,
+        overrides)``, where ``probes`` and ``overrides`` are lists with one
+        list of values per channel. Channels with fewer probes or overrides
+        than the others are padded with zeros. The lists are empty if the
+        destination is unreachable."""
+        packet = struct.pack("<bb", 8, destination)
+        self._writer.write(packet)
+
+    def set_history_interval(self, interval_ms):
+        """Makes the core device sample the monitored probes every
+        ``interval_ms`` milliseconds, keeping the last 64 samples of each.
+        Zero stops sampling and discards the samples."""
+        packet = struct.pack("<bl", 9, interval_ms)
+        self._writer.write(packet)
+
+    def get_history(self, channel, probe):
+        """Requests the samples of a monitored probe. They are passed to
+        ``history_cb`` as ``(channel, probe, samples)``, where ``samples`` is
+        a list of ``(rtio_counter, value)`` pairs, oldest first."""
+        packet = struct.pack("<blb", 10, channel, probe)
+        self._writer.write(packet)
+
+    def release_all_overrides(self):
+        """Releases the overrides enabled by all connections."""
+        packet = struct.pack("<b", 7)
+        self._writer.write(packet)
+
     async def _receive_cr(self):
         try:
             while True:
@@ -92,6 +144,38 @@ class CommMonInj:
                     payload = await self._reader.readexactly(6)
                     channel, override, value = struct.unpack("<lbb", payload)
                     self.injection_status_cb(channel, override, value)
+                elif ty == b"\x02":
+                    (count, ) = struct.unpack("<l", await self._reader.readexactly(4))
+                    payload = await self._reader.readexactly(13*count)
+                    for update in struct.iter_unpack("<lbq", payload):
+                        self.monitor_cb(*update)
+                    (count, ) = struct.unpack("<l", await self._reader.readexactly(4))
+                    payload = await self._reader.readexactly(6*count)
+                    for update in struct.iter_unpack("<lbb", payload):
+                        self.injection_status_cb(*update)
+                elif ty == b"\x03":
+                    payload = await self._reader.readexactly(5)
+                    destination, channels, probes, overrides = struct.unpack("<BHBB", payload)
+                    payload = await self._reader.readexactly(8*channels*probes)
+                    probe_values = [v for (v, ) in struct.iter_unpack("<Q", payload)]
+                    override_values = await self._reader.readexactly(channels*overrides)
+                    if self.snapshot_cb is not None:
+                        self.snapshot_cb(destination,
+                            [probe_values[i*probes:(i+1)*probes] for i in range(channels)],
+                            [list(override_values[i*overrides:(i+1)*overrides]) for i in range(channels)])
+                elif ty == b"\x04":
+                    payload = await self._reader.readexactly(9)
+                    channel, probe, count = struct.unpack("<lbl", payload)
+                    payload = await self._reader.readexactly(16*count)
+                    samples = list(struct.iter_unpack("<QQ", payload))
+                    if self.history_cb is not None:
+                        self.history_cb(channel, probe, samples)
+                elif ty == b"\x05":
+                    payload = await self._reader.readexactly(10)
+                    channel, override, value, length = struct.unpack("<lbbl", payload)
+                    origin = (await self._reader.readexactly(length)).decode()
+                    if self.injection_changed_cb is not None:
+                        self.injection_changed_cb(channel, override, value, origin)
                 else:
                     raise ValueError("Unknown packet type", ty)
         except asyncio.CancelledError:
@@ -152,6 +152,11 @@ class ClockFailure(Exception):
     """Raised when RTIO PLL has lost lock."""
 
 
+class WatchdogExpired(Exception):
+    """Raised when the kernel CPU stopped communicating with the runtime for
+    longer than the watchdog timeout and was stopped."""
+
+
 class I2CError(Exception):
     """Raised when a I2C transaction fails."""
     pass
@@ -11,7 +11,7 @@ extern crate riscv;
 use core::{ptr, slice, convert::TryFrom};
 use crc::crc32;
 use byteorder::{ByteOrder, LittleEndian};
-use board_misoc::{ident, cache, sdram, config, boot, mem as board_mem};
+use board_misoc::{ident, cache, sdram, config, boot, spiflash, mem as board_mem};
 #[cfg(has_slave_fpga_cfg)]
 use board_misoc::slave_fpga;
 #[cfg(has_ethmac)]
@@ -165,7 +165,7 @@ fn flash_boot() {
     if length == 0 || length == 0xffffffff {
         println!("No firmware present");
         return
-    } else if length > 4 * 1024 * 1024 {
+    } else if length > spiflash::FIRMWARE_MAX {
         println!("Firmware too large (is it corrupted?)");
         return
     }
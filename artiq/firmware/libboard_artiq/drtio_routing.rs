@@ -2,13 +2,14 @@ use board_misoc::config;
 #[cfg(has_drtio_routing)]
 use board_misoc::csr;
 use core::fmt;
+use proto_artiq::drtio_routing_proto as proto;
 
 #[cfg(has_drtio_routing)]
 pub const DEST_COUNT: usize = 256;
 #[cfg(not(has_drtio_routing))]
 pub const DEST_COUNT: usize = 0;
-pub const MAX_HOPS: usize = 32;
-pub const INVALID_HOP: u8 = 0xff;
+
+pub use proto_artiq::drtio_routing_proto::{MAX_HOPS, INVALID_HOP, FORMAT_VERSION, Error};
 
 pub struct RoutingTable(pub [[u8; MAX_HOPS]; DEST_COUNT]);
 
@@ -31,6 +32,43 @@ impl RoutingTable {
     pub fn default_empty() -> RoutingTable {
         RoutingTable([[INVALID_HOP; MAX_HOPS]; DEST_COUNT])
     }
+
+    // Parses a routing table in either format, and validates it for a master
+    // with `n_links` DRTIO links.
+    pub fn parse(data: &[u8], n_links: usize) -> Result<RoutingTable, Error> {
+        let mut ret = RoutingTable::default_empty();
+        proto::read(&mut ret.0, data)?;
+        ret.validate(n_links)?;
+        Ok(ret)
+    }
+
+    // Reads entries in the sparse format (without the version byte) into
+    // the table, which must not already route their destinations.
+    pub fn read_entries(&mut self, data: &[u8]) -> Result<(), Error> {
+        proto::read_entries(&mut self.0, data)
+    }
+
+    // Writes the routed entries, starting from `destination`, in the format
+    // read by `read_entries` for as long as they fit in `buffer`. Returns the
+    // number of bytes written and the first destination left out.
+    pub fn write_entries(&self, destination: usize, buffer: &mut [u8]) -> (usize, usize) {
+        proto::write_entries(&self.0, destination, buffer)
+    }
+
+    pub fn validate(&self, n_links: usize) -> Result<(), Error> {
+        proto::validate(&self.0, n_links)
+    }
+
+    // Hops to a destination, without the terminating zero; empty for the
+    // local RTIO core, and None if the destination is not routed.
+    pub fn path(&self, destination: u8) -> Option<&[u8]> {
+        let hops = &self.0[destination as usize];
+        if hops[0] == INVALID_HOP {
+            return None
+        }
+        let rank = hops.iter().position(|&hop| hop == 0 || hop == INVALID_HOP).unwrap_or(MAX_HOPS);
+        Some(&hops[..rank])
+    }
 }
 
 impl fmt::Display for RoutingTable {
@@ -53,26 +91,27 @@ impl fmt::Display for RoutingTable {
     }
 }
 
+// An invalid routing table is not replaced by the default one, which could
+// send RTIO packets to the wrong devices; only the local RTIO core is routed.
 pub fn config_routing_table(default_n_links: usize) -> RoutingTable {
-    let mut ret = RoutingTable::default_master(default_n_links);
-    let ok = config::read("routing_table", |result| {
-        if let Ok(data) = result {
-            if data.len() == DEST_COUNT*MAX_HOPS {
-                for i in 0..DEST_COUNT {
-                    for j in 0..MAX_HOPS {
-                        ret.0[i][j] = data[i*MAX_HOPS+j];
-                    }
-                }
-                return true;
-            } else {
-                warn!("length of the configured routing table is incorrect");
+    let ret = config::read("routing_table", |result| {
+        match result {
+            Ok(data) => RoutingTable::parse(data, default_n_links).unwrap_or_else(|err| {
+                error!("invalid routing table in configuration ({}), \
+                        only the local RTIO core is reachable", err);
+                RoutingTable::default_master(0)
+            }),
+            Err(config::Error::KeyNotFound) | Err(config::Error::NoFlash) => {
+                info!("no routing table in configuration, using default");
+                RoutingTable::default_master(default_n_links)
+            },
+            Err(err) => {
+                error!("cannot read routing table from configuration ({}), \
+                        only the local RTIO core is reachable", err);
+                RoutingTable::default_master(0)
             }
         }
-        false
     });
-    if !ok {
-        info!("could not read routing table from configuration, using default");
-    }
     info!("routing table: {}", ret);
     ret
 }
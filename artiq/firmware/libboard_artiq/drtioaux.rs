@@ -3,9 +3,13 @@ use crc;
 
 use io::{ProtoRead, ProtoWrite, Cursor, Error as IoError};
 use board_misoc::{csr::DRTIOAUX, mem::DRTIOAUX_MEM, clock};
-use proto_artiq::drtioaux_proto::Error as ProtocolError;
+use proto_artiq::drtioaux_proto::{Frame, Error as ProtocolError};
+use proto_artiq::drtioaux_reliable::{self as reliable, Link};
 
-pub use proto_artiq::drtioaux_proto::Packet;
+pub use proto_artiq::drtioaux_proto::{Packet, LinkStats, BulkStatus, MONITOR_BATCH_MAX, BULK_CHUNK_MAX,
+                                      ROUTING_CHUNK_MAX,
+                                      BULK_ECHO, BULK_STATUS, BULK_CONFIG_READ, BULK_CONFIG_WRITE,
+                                      BULK_CONFIG_REMOVE, BULK_FIRMWARE, BULK_END_TIMEOUT_MS};
 
 // this is parametric over T because there's no impl Fail for !.
 #[derive(Fail, Debug)]
@@ -21,6 +25,8 @@ pub enum Error<T> {
     TimedOut,
     #[fail(display = "unexpected reply")]
     UnexpectedReply,
+    #[fail(display = "packet not acknowledged")]
+    NotAcknowledged,
 
     #[fail(display = "routing error")]
     RoutingError,
@@ -41,6 +47,57 @@ impl<T> From<IoError<T>> for Error<T> {
     }
 }
 
+static mut STATS: [LinkStats; DRTIOAUX.len()] = [LinkStats::new(); DRTIOAUX.len()];
+static mut UP_SINCE: [Option<u64>; DRTIOAUX.len()] = [None; DRTIOAUX.len()];
+
+const SEQUENCING_DISABLED: reliable::State = reliable::State::new();
+static mut SEQUENCING: [reliable::State; DRTIOAUX.len()] = [SEQUENCING_DISABLED; DRTIOAUX.len()];
+
+pub fn stats(linkno: u8) -> LinkStats {
+    let linkno = linkno as usize;
+    unsafe {
+        let mut stats = STATS[linkno];
+        stats.retransmissions = SEQUENCING[linkno].retransmissions;
+        stats.uptime_ms = UP_SINCE[linkno].map(|since| clock::get_ms() - since).unwrap_or(0);
+        stats
+    }
+}
+
+pub fn update_stats<F: FnOnce(&mut LinkStats)>(linkno: u8, f: F) {
+    unsafe { f(&mut STATS[linkno as usize]) }
+}
+
+// Called by the link management code when the link goes up or down, to
+// track uptime and count link losses. On the runtime, the caller must hold
+// the aux mutex, so that no send_waiting is in progress on the link.
+pub fn set_link_up(linkno: u8, up: bool) {
+    let linkno = linkno as usize;
+    unsafe {
+        match (UP_SINCE[linkno], up) {
+            (None, true) => UP_SINCE[linkno] = Some(clock::get_ms()),
+            (Some(_), false) => {
+                UP_SINCE[linkno] = None;
+                STATS[linkno].link_down_events += 1;
+            },
+            _ => ()
+        }
+        if !up {
+            SEQUENCING[linkno].set_enabled(false);
+        }
+    }
+}
+
+// Enables acknowledgement and retransmission of the packets sent on the link,
+// once the peer has answered an AuxSequencingRequest. On the runtime, the
+// caller must hold the aux mutex.
+pub fn set_sequencing(linkno: u8, enabled: bool) {
+    unsafe { SEQUENCING[linkno as usize].set_enabled(enabled) }
+}
+
+pub fn sequencing_enabled(linkno: u8) -> bool {
+    unsafe { SEQUENCING[linkno as usize].is_enabled() }
+}
+
 pub fn reset(linkno: u8) {
     let linkno = linkno as usize;
     unsafe {
@@ -81,27 +138,7 @@ fn receive<F, T>(linkno: u8, f: F) -> Result<Option<T>, Error<!>>
 }
 
 pub fn recv(linkno: u8) -> Result<Option<Packet>, Error<!>> {
-    if has_rx_error(linkno) {
-        return Err(Error::GatewareError)
-    }
-
-    receive(linkno, |buffer| {
-        if buffer.len() < 8 {
-            return Err(IoError::UnexpectedEnd.into())
-        }
-
-        let mut reader = Cursor::new(buffer);
-
-        let checksum_at = buffer.len() - 4;
-        let checksum = crc::crc32::checksum_ieee(&reader.get_ref()[0..checksum_at]);
-        reader.set_position(checksum_at);
-        if reader.read_u32()? != checksum {
-            return Err(Error::CorruptedPacket)
-        }
-        reader.set_position(0);
-
-        Ok(Packet::read_from(&mut reader)?)
-    })
+    unsafe { SEQUENCING[linkno as usize].recv(&mut Hardware(linkno, || ())) }
 }
 
 pub fn recv_timeout(linkno: u8, timeout_ms: Option<u64>) -> Result<Packet, Error<!>> {
@@ -132,20 +169,77 @@ fn transmit<F>(linkno: u8, f: F) -> Result<(), Error<!>>
 }
 
 pub fn send(linkno: u8, packet: &Packet) -> Result<(), Error<!>> {
-    transmit(linkno, |buffer| {
-        let mut writer = Cursor::new(buffer);
+    send_waiting(linkno, packet, || ())
+}
+
+// Like `send`, but calls `wait` while the packet is not acknowledged, e.g. to
+// let other threads run.
+pub fn send_waiting<F: FnMut()>(linkno: u8, packet: &Packet, wait: F) -> Result<(), Error<!>> {
+    let result = unsafe { SEQUENCING[linkno as usize].send(&mut Hardware(linkno, wait), packet) };
+    result.map_err(|err| match err {
+        reliable::Error::NotAcknowledged => Error::NotAcknowledged,
+        reliable::Error::Link(err) => err
+    })
+}
+
+struct Hardware<F: FnMut()>(u8, F);
+
+impl<F: FnMut()> Link for Hardware<F> {
+    type Error = Error<!>;
 
-        packet.write_to(&mut writer)?;
+    fn send_frame(&mut self, frame: &Frame<&Packet>) -> Result<(), Error<!>> {
+        transmit(self.0, |buffer| {
+            let mut writer = Cursor::new(buffer);
 
-        // Pad till offset 4, insert checksum there
-        let padding = (12 - (writer.position() % 8)) % 8;
-        for _ in 0..padding {
-            writer.write_u8(0)?;
+            frame.write_to(&mut writer)?;
+
+            // Pad till offset 4, insert checksum there
+            let padding = (12 - (writer.position() % 8)) % 8;
+            for _ in 0..padding {
+                writer.write_u8(0)?;
+            }
+
+            let checksum = crc::crc32::checksum_ieee(&writer.get_ref()[0..writer.position()]);
+            writer.write_u32(checksum)?;
+
+            Ok(writer.position())
+        })
+    }
+
+    fn recv_frame(&mut self) -> Result<Option<Frame<Packet>>, Error<!>> {
+        let linkno = self.0;
+        if has_rx_error(linkno) {
+            return Err(Error::GatewareError)
         }
 
-        let checksum = crc::crc32::checksum_ieee(&writer.get_ref()[0..writer.position()]);
-        writer.write_u32(checksum)?;
+        let result = receive(linkno, |buffer| {
+            if buffer.len() < 8 {
+                return Err(IoError::UnexpectedEnd.into())
+            }
+
+            let mut reader = Cursor::new(buffer);
+
+            let checksum_at = buffer.len() - 4;
+            let checksum = crc::crc32::checksum_ieee(&reader.get_ref()[0..checksum_at]);
+            reader.set_position(checksum_at);
+            if reader.read_u32()? != checksum {
+                return Err(Error::CorruptedPacket)
+            }
+            reader.set_position(0);
+
+            Ok(Frame::<Packet>::read_from(&mut reader)?)
+        });
+        if let Err(Error::CorruptedPacket) = result {
+            update_stats(linkno, |stats| stats.crc_errors += 1);
+        }
+        result
+    }
 
-        Ok(writer.position())
-    })
+    fn now_us(&mut self) -> u64 {
+        clock::get_us()
+    }
+
+    fn wait(&mut self) {
+        (self.1)()
+    }
 }
@@ -1,47 +1,80 @@
-use core::{str, fmt};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Error {
-    AlreadyLocked,
-    SpaceExhausted,
-    Truncated { offset: usize },
-    InvalidSize { offset: usize, size: usize },
-    MissingSeparator { offset: usize },
-    Utf8Error(str::Utf8Error),
-    NoFlash,
-    KeyNotFound
+use core::str;
+
+pub use config_store::{Error, Kind, Stats, Flash, Store};
+
+/// Keys read by the firmware, and the format of their values. Values of
+/// other keys (e.g. kernels) are not interpreted by this module.
+pub const SCHEMA: &'static [(&'static str, Kind)] = &[
+    ("mac",                Kind::Mac),
+    ("ip",                 Kind::Ipv4),
+    ("ip6",                Kind::Ip),
+    ("net_trace",          Kind::Flag),
+    ("log_level",          Kind::LogLevel),
+    ("uart_log_level",     Kind::LogLevel),
+    ("panic_reset",        Kind::Flag),
+    ("no_flash_boot",      Kind::Flag),
+    ("rtio_clock",         Kind::OneOf(&[
+        "int_125", "int_100", "int_150",
+        "ext0_bypass", "ext0_bypass_125", "ext0_bypass_100",
+        "ext0_synth0_10to125", "ext0_synth0_100to125", "ext0_synth0_125to125",
+        // legacy
+        "i", "e"])),
+    ("routing_table",      Kind::RoutingTable),
+    ("kernel_watchdog_ms", Kind::U32),
+    ("dma_persist",        Kind::Flag),
+];
+
+pub fn kind(key: &str) -> Option<Kind> {
+    SCHEMA.iter()
+        .find(|&&(known_key, _)| known_key == key)
+        .map(|&(_, kind)| kind)
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &Error::AlreadyLocked =>
-                write!(f, "attempt at reentrant access"),
-            &Error::SpaceExhausted =>
-                write!(f, "space exhausted"),
-            &Error::Truncated { offset }=>
-                write!(f, "truncated record at offset {}", offset),
-            &Error::InvalidSize { offset, size } =>
-                write!(f, "invalid record size {} at offset {}", size, offset),
-            &Error::MissingSeparator { offset } =>
-                write!(f, "missing separator at offset {}", offset),
-            &Error::Utf8Error(err) =>
-                write!(f, "{}", err),
-            &Error::NoFlash =>
-                write!(f, "flash memory is not present"),
-            &Error::KeyNotFound =>
-                write!(f, "key not found")
-        }
+fn accepts(kind: Kind, value: &[u8]) -> bool {
+    if let Kind::RoutingTable = kind {
+        return value.len() == 256 * 32 || value[0] == 1
+    }
+    let value = match str::from_utf8(value) {
+        Ok(value) => value,
+        Err(_) => return false
+    };
+    match kind {
+        Kind::OneOf(values) => values.contains(&value),
+        Kind::Flag => value == "0" || value == "1",
+        Kind::LogLevel =>
+            ["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"].iter()
+                .any(|level| level.eq_ignore_ascii_case(value)),
+        Kind::U32 => value.parse::<u32>().is_ok(),
+        #[cfg(feature = "smoltcp")]
+        Kind::Ipv4 =>
+            value == "use_dhcp" || value.parse::<::smoltcp::wire::Ipv4Address>().is_ok(),
+        #[cfg(feature = "smoltcp")]
+        Kind::Ip => value.parse::<::smoltcp::wire::IpAddress>().is_ok(),
+        #[cfg(feature = "smoltcp")]
+        Kind::Mac => value.parse::<::smoltcp::wire::EthernetAddress>().is_ok(),
+        // Addresses cannot be parsed without the network stack.
+        #[cfg(not(feature = "smoltcp"))]
+        Kind::Ipv4 | Kind::Ip | Kind::Mac => true,
+        Kind::RoutingTable => unreachable!()
+    }
+}
+
+/// Checks `value` against the schema. Empty values, which remove the key,
+/// and values of unknown keys are always accepted.
+pub fn validate(key: &str, value: &[u8]) -> Result<(), Error> {
+    match kind(key) {
+        Some(kind) if !value.is_empty() && !accepts(kind, value) =>
+            Err(Error::InvalidValue(kind)),
+        _ => Ok(())
     }
 }
 
 #[cfg(has_spiflash)]
 mod imp {
-    use core::str;
-    use byteorder::{ByteOrder, BigEndian};
+    use core::{str, slice};
     use cache;
     use spiflash;
-    use super::Error;
+    use super::{Error, Flash, Stats, Store};
     use core::fmt;
     use core::fmt::Write;
 
@@ -74,12 +107,34 @@ mod imp {
         }
     }
 
-    // One flash sector immediately before the firmware.
-    const ADDR: usize = ::mem::FLASH_BOOT_ADDRESS - spiflash::SECTOR_SIZE;
+    // The flash sector immediately before the firmware, written by `artiq_flash storage`,
+    // and the sector following the DMA trace store of the runtime.
+    // The sectors before the storage sector belong to the bootloader.
+    const ADDRS: [usize; 2] = [
+        ::mem::FLASH_BOOT_ADDRESS - spiflash::SECTOR_SIZE,
+        spiflash::DMA_STORE_ADDRESS + spiflash::DMA_STORE_SIZE
+    ];
     const SIZE: usize = spiflash::SECTOR_SIZE;
 
+    struct SpiFlash;
+
+    impl Flash for SpiFlash {
+        fn sector(&self, index: usize) -> &[u8] {
+            unsafe { slice::from_raw_parts(ADDRS[index] as *const u8, SIZE) }
+        }
+
+        unsafe fn erase(&self, index: usize) {
+            spiflash::erase_sector(ADDRS[index]);
+            cache::flush_l2_cache();
+        }
+
+        unsafe fn program(&self, index: usize, offset: usize, data: &[u8]) {
+            spiflash::write(ADDRS[index] + offset, data);
+            cache::flush_l2_cache();
+        }
+    }
+
     mod lock {
-        use core::slice;
         use core::sync::atomic::{AtomicUsize, Ordering};
         use super::Error;
 
@@ -95,10 +150,6 @@ mod imp {
                     Ok(Lock)
                 }
             }
-
-            pub fn data(&self) -> &'static [u8] {
-                unsafe { slice::from_raw_parts(super::ADDR as *const u8, super::SIZE) }
-            }
         }
 
         impl Drop for Lock {
@@ -110,66 +161,23 @@ mod imp {
 
     use self::lock::Lock;
 
-    #[derive(Clone)]
-    struct Iter<'a> {
-        data:   &'a [u8],
-        offset: usize
-    }
-
-    impl<'a> Iter<'a> {
-        fn new(data: &'a [u8]) -> Iter<'a> {
-            Iter { data: data, offset: 0 }
-        }
-    }
-
-    impl<'a> Iterator for Iter<'a> {
-        type Item = Result<(&'a [u8], &'a [u8]), Error>;
-
-        fn next(&mut self) -> Option<Self::Item> {
-            let data = &self.data[self.offset..];
-
-            if data.len() < 4 {
-                // error!("offset {}: truncated record", self.offset);
-                return Some(Err(Error::Truncated { offset: self.offset }))
-            }
-
-            let record_size = BigEndian::read_u32(data) as usize;
-            if record_size == !0 /* all ones; erased flash */ {
-                return None
-            } else if record_size < 4 || record_size > data.len() {
-                return Some(Err(Error::InvalidSize { offset: self.offset, size: record_size }))
-            }
-
-            let record_body = &data[4..record_size];
-            match record_body.iter().position(|&x| x == 0) {
-                None => {
-                    return Some(Err(Error::MissingSeparator { offset: self.offset }))
-                }
-                Some(pos) => {
-                    self.offset += record_size;
-
-                    let (key, zero_and_value) = record_body.split_at(pos);
-                    Some(Ok((key, &zero_and_value[1..])))
-                }
-            }
-        }
+    // `f` must not access the configuration.
+    pub fn for_each<F: FnMut(&str, &[u8])>(mut f: F) -> Result<(), Error> {
+        let _lock = Lock::take()?;
+        Store::new(SpiFlash).for_each(|key, value| {
+            f(str::from_utf8(key).map_err(Error::Utf8Error)?, value);
+            Ok(())
+        })
     }
 
     pub fn read<F: FnOnce(Result<&[u8], Error>) -> R, R>(key: &str, f: F) -> R {
-        f(Lock::take().and_then(|lock| {
-            let mut iter = Iter::new(lock.data());
-            let mut value = &[][..];
-            let mut found = false;
-            while let Some(result) = iter.next() {
-                let (record_key, record_value) = result?;
-                if key.as_bytes() == record_key {
-                    found = true;
-                    // last write wins
-                    value = record_value
-                }
+        match Lock::take() {
+            Ok(_lock) => {
+                let store = Store::new(SpiFlash);
+                f(store.read(key.as_bytes()))
             }
-            if found { Ok(value) } else { Err(Error::KeyNotFound) }
-        }))
+            Err(err) => f(Err(err))
+        }
     }
 
     pub fn read_str<F: FnOnce(Result<&str, Error>) -> R, R>(key: &str, f: F) -> R {
@@ -178,94 +186,21 @@ mod imp {
         })
     }
 
-    unsafe fn append_at(data: &[u8], mut offset: usize,
-                        key: &[u8], value: &[u8]) -> Result<usize, Error> {
-        let record_size = 4 + key.len() + 1 + value.len();
-        if offset + record_size > data.len() {
-            return Err(Error::SpaceExhausted)
-        }
-
-        let mut record_size_bytes = [0u8; 4];
-        BigEndian::write_u32(&mut record_size_bytes[..], record_size as u32);
-
-        {
-            let mut write = |payload| {
-                spiflash::write(data.as_ptr().offset(offset as isize) as usize, payload);
-                offset += payload.len();
-            };
-
-            write(&record_size_bytes[..]);
-            write(key);
-            write(&[0]);
-            write(value);
-            cache::flush_l2_cache();
-        }
-
-        Ok(offset)
-    }
-
-    fn compact() -> Result<(), Error> {
-        let lock = Lock::take()?;
-        let data = lock.data();
-
-        static mut OLD_DATA: [u8; SIZE] = [0; SIZE];
-        let old_data = unsafe {
-            OLD_DATA.copy_from_slice(data);
-            &OLD_DATA[..]
-        };
-
-        unsafe { spiflash::erase_sector(data.as_ptr() as usize) };
-
-        // This is worst-case quadratic, but we're limited by a small SPI flash sector size,
-        // so it does not really matter.
-        let mut offset = 0;
-        let mut iter = Iter::new(old_data);
-        'iter: while let Some(result) = iter.next() {
-            let (key, value) = result?;
-            if value.is_empty() {
-                // This is a removed entry, ignore it.
-                continue
-            }
-
-            let mut next_iter = iter.clone();
-            while let Some(next_result) = next_iter.next() {
-                let (next_key, _) = next_result?;
-                if key == next_key {
-                    // There's another entry that overwrites this one, ignore this one.
-                    continue 'iter
-                }
-            }
-            offset = unsafe { append_at(data, offset, key, value)? };
-        }
-
+    // Calls `f` for every corrupted record.
+    pub fn check<F: FnMut(Error)>(f: F) -> Result<(), Error> {
+        let _lock = Lock::take()?;
+        Store::new(SpiFlash).check(f);
         Ok(())
     }
 
-    fn append(key: &str, value: &[u8]) -> Result<(), Error> {
-        let lock = Lock::take()?;
-        let data = lock.data();
-
-        let free_offset = {
-            let mut iter = Iter::new(data);
-            while let Some(result) = iter.next() {
-                let _ = result?;
-            }
-            iter.offset
-        };
-
-        unsafe { append_at(data, free_offset, key.as_bytes(), value)? };
-
-        Ok(())
+    pub fn stats() -> Result<Stats, Error> {
+        let _lock = Lock::take()?;
+        Ok(Store::new(SpiFlash).stats())
     }
 
     pub fn write(key: &str, value: &[u8]) -> Result<(), Error> {
-        match append(key, value) {
-            Err(Error::SpaceExhausted) => {
-                compact()?;
-                append(key, value)
-            }
-            res => res
-        }
+        let _lock = Lock::take()?;
+        Store::new(SpiFlash).write(key.as_bytes(), value)
     }
 
     pub fn write_int(key: &str, value: u32) -> Result<(), Error> {
@@ -279,20 +214,28 @@ mod imp {
         write(key, &[])
     }
 
-    pub fn erase() -> Result<(), Error> {
-        let lock = Lock::take()?;
-        let data = lock.data();
-
-        unsafe { spiflash::erase_sector(data.as_ptr() as usize) };
-        cache::flush_l2_cache();
+    // Replaces the whole configuration with `entries`. Nothing is erased unless
+    // all of them fit in the sector.
+    pub fn replace_all<'a, I>(entries: I) -> Result<(), Error>
+            where I: Iterator<Item=(&'a str, &'a [u8])> + Clone {
+        let _lock = Lock::take()?;
+        Store::new(SpiFlash).replace_all(entries.map(|(key, value)| (key.as_bytes(), value)))
+    }
 
+    pub fn erase() -> Result<(), Error> {
+        let _lock = Lock::take()?;
+        Store::new(SpiFlash).erase();
         Ok(())
     }
 }
 
 #[cfg(not(has_spiflash))]
 mod imp {
-    use super::Error;
+    use super::{Error, Stats};
+
+    pub fn for_each<F: FnMut(&str, &[u8])>(_f: F) -> Result<(), Error> {
+        Err(Error::NoFlash)
+    }
 
     pub fn read<F: FnOnce(Result<&[u8], Error>) -> R, R>(_key: &str, f: F) -> R {
         f(Err(Error::NoFlash))
@@ -302,6 +245,14 @@ mod imp {
         f(Err(Error::NoFlash))
     }
 
+    pub fn check<F: FnMut(Error)>(_f: F) -> Result<(), Error> {
+        Err(Error::NoFlash)
+    }
+
+    pub fn stats() -> Result<Stats, Error> {
+        Err(Error::NoFlash)
+    }
+
     pub fn write(_key: &str, _value: &[u8]) -> Result<(), Error> {
         Err(Error::NoFlash)
     }
@@ -310,6 +261,11 @@ mod imp {
         Err(Error::NoFlash)
     }
 
+    pub fn replace_all<'a, I>(_entries: I) -> Result<(), Error>
+            where I: Iterator<Item=(&'a str, &'a [u8])> + Clone {
+        Err(Error::NoFlash)
+    }
+
     pub fn erase() -> Result<(), Error> {
         Err(Error::NoFlash)
     }
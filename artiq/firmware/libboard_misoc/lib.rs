@@ -2,6 +2,7 @@
 #![feature(llvm_asm)]
 
 extern crate byteorder;
+extern crate config_store;
 #[cfg(feature = "log")]
 extern crate log;
 #[cfg(feature = "smoltcp")]
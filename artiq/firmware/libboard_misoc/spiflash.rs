@@ -1,9 +1,20 @@
 use core::cmp;
 use csr;
+use mem;
 
 pub const SECTOR_SIZE: usize = csr::CONFIG_SPIFLASH_SECTOR_SIZE as usize;
 pub const PAGE_SIZE:   usize = csr::CONFIG_SPIFLASH_PAGE_SIZE   as usize;
 
+// The firmware at FLASH_BOOT_ADDRESS is preceded by its length and CRC32, and
+// is at most FIRMWARE_MAX bytes long. The runtime stores DMA traces from the
+// first sector after the largest firmware; the second config sector follows
+// them. artiq_flash.py lists the resulting addresses.
+pub const FIRMWARE_HEADER_SIZE: usize = 8;
+pub const FIRMWARE_MAX:         usize = 4 * 1024 * 1024;
+pub const DMA_STORE_ADDRESS:    usize = (mem::FLASH_BOOT_ADDRESS + FIRMWARE_HEADER_SIZE + FIRMWARE_MAX +
+                                         SECTOR_SIZE - 1) & !(SECTOR_SIZE - 1);
+pub const DMA_STORE_SIZE:       usize = 512 * 1024;
+
 const PAGE_MASK: usize = PAGE_SIZE - 1;
 
 const CMD_PP:   u8 = 0x02;
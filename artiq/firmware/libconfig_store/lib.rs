@@ -0,0 +1,758 @@
+//! Journaled key-value storage of the core device configuration in two
+//! flash sectors, independent of the flash hardware.
+
+#![no_std]
+
+extern crate byteorder;
+extern crate crc;
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+use core::{str, fmt, slice};
+use core::cell::Cell;
+use byteorder::{ByteOrder, BigEndian};
+use crc::{crc32, Hasher32};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    AlreadyLocked,
+    SpaceExhausted,
+    Truncated { offset: usize },
+    InvalidSize { offset: usize, size: usize },
+    MissingSeparator { offset: usize },
+    ChecksumMismatch { offset: usize },
+    Utf8Error(str::Utf8Error),
+    NoFlash,
+    KeyNotFound,
+    InvalidValue(Kind)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::AlreadyLocked =>
+                write!(f, "attempt at reentrant access"),
+            &Error::SpaceExhausted =>
+                write!(f, "space exhausted"),
+            &Error::Truncated { offset }=>
+                write!(f, "truncated record at offset {}", offset),
+            &Error::InvalidSize { offset, size } =>
+                write!(f, "invalid record size {} at offset {}", size, offset),
+            &Error::MissingSeparator { offset } =>
+                write!(f, "missing separator at offset {}", offset),
+            &Error::ChecksumMismatch { offset } =>
+                write!(f, "checksum mismatch in record at offset {}", offset),
+            &Error::Utf8Error(err) =>
+                write!(f, "{}", err),
+            &Error::NoFlash =>
+                write!(f, "flash memory is not present"),
+            &Error::KeyNotFound =>
+                write!(f, "key not found"),
+            &Error::InvalidValue(kind) =>
+                write!(f, "invalid value, expected {}", kind)
+        }
+    }
+}
+
+/// Format of the value of a known key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// One of the listed strings.
+    OneOf(&'static [&'static str]),
+    /// `0` or `1`.
+    Flag,
+    /// A log level filter name, in any case.
+    LogLevel,
+    /// A decimal integer that fits in 32 bits.
+    U32,
+    /// An IPv4 address, or `use_dhcp`.
+    Ipv4,
+    /// An IPv4 or IPv6 address.
+    Ip,
+    /// An Ethernet MAC address.
+    Mac,
+    /// A DRTIO routing table, in the sparse format (starting with its version)
+    /// or the legacy one (256 * 32 bytes); see `board_artiq::drtio_routing`,
+    /// which validates the routes.
+    RoutingTable
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Kind::OneOf(values) => {
+                write!(f, "one of")?;
+                for (i, value) in values.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { "" } else { "," }, value)?;
+                }
+                Ok(())
+            }
+            &Kind::Flag =>
+                write!(f, "0 or 1"),
+            &Kind::LogLevel =>
+                write!(f, "one of OFF, ERROR, WARN, INFO, DEBUG, TRACE"),
+            &Kind::U32 =>
+                write!(f, "a decimal integer below 2^32"),
+            &Kind::Ipv4 =>
+                write!(f, "an IPv4 address or use_dhcp"),
+            &Kind::Ip =>
+                write!(f, "an IP address"),
+            &Kind::Mac =>
+                write!(f, "a MAC address"),
+            &Kind::RoutingTable =>
+                write!(f, "a routing table (see artiq_route)")
+        }
+    }
+}
+
+/// Space usage of the configuration sector in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Size of the sector.
+    pub capacity:     usize,
+    /// Bytes up to the end of the last record, including the sector header.
+    pub used:         usize,
+    /// Bytes taken by the records holding the current value of a key.
+    pub live_bytes:   usize,
+    pub live_records: usize,
+    /// Records that are overwritten, removals, or corrupted; compaction reclaims them.
+    pub dead_records: usize,
+    /// Offset of the first corrupted record.
+    pub corruption:   Option<usize>
+}
+
+/// Two erasable flash sectors holding the configuration.
+///
+/// Sector 0 is the original location of the configuration, which may still hold
+/// records in the legacy format (e.g. an image written by `artiq_flash`).
+pub trait Flash {
+    fn sector(&self, index: usize) -> &[u8];
+
+    /// Sets every byte of the sector to 0xff.
+    ///
+    /// No slice returned by `sector(index)` may be alive.
+    unsafe fn erase(&self, index: usize);
+
+    /// Clears the bits of the sector at `offset` that are clear in `data`.
+    ///
+    /// No slice returned by `sector(index)` may be alive.
+    unsafe fn program(&self, index: usize, offset: usize, data: &[u8]);
+}
+
+/// In-memory model of the flash, for exercising `Store` on the host.
+pub struct MemoryFlash<'a> {
+    sectors: [&'a [Cell<u8>]; 2]
+}
+
+impl<'a> MemoryFlash<'a> {
+    pub fn new(primary: &'a mut [u8], secondary: &'a mut [u8]) -> MemoryFlash<'a> {
+        MemoryFlash {
+            sectors: [Cell::from_mut(primary).as_slice_of_cells(),
+                      Cell::from_mut(secondary).as_slice_of_cells()]
+        }
+    }
+}
+
+impl<'a> Flash for MemoryFlash<'a> {
+    fn sector(&self, index: usize) -> &[u8] {
+        let cells = self.sectors[index];
+        unsafe { slice::from_raw_parts(cells.as_ptr() as *const u8, cells.len()) }
+    }
+
+    unsafe fn erase(&self, index: usize) {
+        for cell in self.sectors[index] {
+            cell.set(0xff)
+        }
+    }
+
+    unsafe fn program(&self, index: usize, offset: usize, data: &[u8]) {
+        for (cell, &byte) in self.sectors[index][offset..offset + data.len()].iter().zip(data) {
+            cell.set(cell.get() & byte)
+        }
+    }
+}
+
+// Sectors written by this code start with a header: magic, generation, and CRC32 of both.
+// The header is programmed last, so that a sector is only used once completely written,
+// and the valid sector with the highest generation is the active one.
+const MAGIC: &'static [u8] = b"ACFG";
+const HEADER_SIZE: usize = 12;
+
+// Records are: size (including the record header), [CRC32 of the rest,] key, 0, value.
+// Legacy records have no CRC. A record with an empty value removes the key.
+const LEGACY_RECORD_HEADER_SIZE: usize = 4;
+const RECORD_HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Legacy,
+    Journal { generation: u32 }
+}
+
+#[derive(Clone)]
+struct Iter<'a> {
+    data:   &'a [u8],
+    offset: usize,
+    checksummed: bool,
+    done:   bool
+}
+
+impl<'a> Iter<'a> {
+    fn new(data: &'a [u8], format: Format) -> Iter<'a> {
+        match format {
+            Format::Legacy =>
+                Iter { data: data, offset: 0, checksummed: false, done: false },
+            Format::Journal { .. } =>
+                Iter { data: data, offset: HEADER_SIZE, checksummed: true, done: false }
+        }
+    }
+
+    fn empty() -> Iter<'a> {
+        Iter { data: &[], offset: 0, checksummed: false, done: true }
+    }
+
+    fn record_size(&self, key: &[u8], value: &[u8]) -> usize {
+        let header_size = if self.checksummed { RECORD_HEADER_SIZE }
+                          else { LEGACY_RECORD_HEADER_SIZE };
+        header_size + key.len() + 1 + value.len()
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(&'a [u8], &'a [u8]), Error>;
+
+    // Records with a valid size are skipped when corrupted, since the next record
+    // can still be found. Otherwise, iteration stops at the error.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+
+        let offset = self.offset;
+        let data = &self.data[offset..];
+
+        if data.len() < 4 {
+            self.done = true;
+            if data.iter().all(|&x| x == 0xff) {
+                return None
+            } else {
+                return Some(Err(Error::Truncated { offset: offset }))
+            }
+        }
+
+        let header_size = if self.checksummed { RECORD_HEADER_SIZE }
+                          else { LEGACY_RECORD_HEADER_SIZE };
+        let record_size = BigEndian::read_u32(data);
+        if record_size == !0 /* all ones; erased flash */ {
+            self.done = true;
+            return None
+        }
+        let record_size = record_size as usize;
+        if record_size < header_size || record_size > data.len() {
+            self.done = true;
+            return Some(Err(Error::InvalidSize { offset: offset, size: record_size }))
+        }
+        self.offset += record_size;
+
+        let record_body = &data[header_size..record_size];
+        if self.checksummed && crc32::checksum_ieee(record_body) != BigEndian::read_u32(&data[4..]) {
+            return Some(Err(Error::ChecksumMismatch { offset: offset }))
+        }
+        match record_body.iter().position(|&x| x == 0) {
+            None => Some(Err(Error::MissingSeparator { offset: offset })),
+            Some(pos) => {
+                let (key, zero_and_value) = record_body.split_at(pos);
+                Some(Ok((key, &zero_and_value[1..])))
+            }
+        }
+    }
+}
+
+// Yields the current value of every key that has not been removed, ignoring
+// corrupted records.
+#[derive(Clone)]
+struct Live<'a> {
+    iter: Iter<'a>
+}
+
+impl<'a> Iterator for Live<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // This is worst-case quadratic, but we're limited by a small SPI flash sector size,
+        // so it does not really matter.
+        'iter: while let Some(result) = self.iter.next() {
+            let (key, value) = match result {
+                Ok(record) => record,
+                Err(_) => continue
+            };
+            if value.is_empty() {
+                // This is a removed entry, ignore it.
+                continue
+            }
+
+            let mut next_iter = self.iter.clone();
+            while let Some(next_result) = next_iter.next() {
+                match next_result {
+                    Ok((next_key, _)) if next_key == key =>
+                        // There's another entry that overwrites this one, ignore this one.
+                        continue 'iter,
+                    _ => ()
+                }
+            }
+            return Some((key, value))
+        }
+        None
+    }
+}
+
+fn record_size(key: &[u8], value: &[u8]) -> usize {
+    RECORD_HEADER_SIZE + key.len() + 1 + value.len()
+}
+
+pub struct Store<F: Flash> {
+    flash: F
+}
+
+impl<F: Flash> Store<F> {
+    pub fn new(flash: F) -> Store<F> {
+        Store { flash: flash }
+    }
+
+    fn format(&self, index: usize) -> Option<Format> {
+        let data = self.flash.sector(index);
+        if &data[..4] == MAGIC {
+            let checksum = BigEndian::read_u32(&data[8..]);
+            if crc32::checksum_ieee(&data[..8]) == checksum {
+                Some(Format::Journal { generation: BigEndian::read_u32(&data[4..]) })
+            } else {
+                None
+            }
+        } else if &data[..4] == &[0xff; 4] {
+            None
+        } else if index == 0 {
+            Some(Format::Legacy)
+        } else {
+            None
+        }
+    }
+
+    fn legacy_intact(&self) -> bool {
+        Iter::new(self.flash.sector(0), Format::Legacy).all(|result| result.is_ok())
+    }
+
+    fn active(&self) -> Option<(usize, Format)> {
+        match (self.format(0), self.format(1)) {
+            // A legacy sector is only written by this code when it is erased,
+            // so one that parses cleanly was flashed after the last compaction.
+            (Some(Format::Legacy), Some(Format::Journal { .. })) if !self.legacy_intact() =>
+                self.format(1).map(|format| (1, format)),
+            (Some(Format::Legacy), _) =>
+                Some((0, Format::Legacy)),
+            (Some(Format::Journal { generation: generation0 }),
+             Some(Format::Journal { generation: generation1 })) => {
+                if (generation1.wrapping_sub(generation0) as i32) > 0 {
+                    Some((1, Format::Journal { generation: generation1 }))
+                } else {
+                    Some((0, Format::Journal { generation: generation0 }))
+                }
+            }
+            // Only sector 0 can hold the legacy format.
+            (Some(format), _) => Some((0, format)),
+            (None, Some(format)) => Some((1, format)),
+            (None, None) => None
+        }
+    }
+
+    fn records(&self) -> Iter {
+        match self.active() {
+            Some((index, format)) => Iter::new(self.flash.sector(index), format),
+            None => Iter::empty()
+        }
+    }
+
+    fn live(&self) -> Live {
+        Live { iter: self.records() }
+    }
+
+    /// Calls `f` with the current value of every key that has not been removed.
+    pub fn for_each<G>(&self, mut f: G) -> Result<(), Error>
+            where G: FnMut(&[u8], &[u8]) -> Result<(), Error> {
+        for (key, value) in self.live() {
+            f(key, value)?
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, key: &[u8]) -> Result<&[u8], Error> {
+        let mut value = None;
+        for result in self.records() {
+            match result {
+                // last write wins
+                Ok((record_key, record_value)) if record_key == key =>
+                    value = Some(record_value),
+                _ => ()
+            }
+        }
+        match value {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ => Err(Error::KeyNotFound)
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            capacity: self.flash.sector(0).len(),
+            used: 0,
+            live_bytes: 0,
+            live_records: 0,
+            dead_records: 0,
+            corruption: None
+        };
+
+        let mut records = 0;
+        let mut iter = self.records();
+        while let Some(result) = iter.next() {
+            match result {
+                Ok(_) => records += 1,
+                Err(err) => {
+                    if stats.corruption.is_none() {
+                        stats.corruption = Some(match err {
+                            Error::Truncated { offset } |
+                            Error::InvalidSize { offset, .. } |
+                            Error::MissingSeparator { offset } |
+                            Error::ChecksumMismatch { offset } => offset,
+                            _ => unreachable!()
+                        })
+                    }
+                    // Corrupted records that could be skipped still take up space.
+                    if !iter.done {
+                        records += 1
+                    }
+                }
+            }
+        }
+        // Nothing can be appended after a record whose end cannot be found.
+        stats.used = match stats.corruption {
+            Some(_) if iter.done && iter.offset < iter.data.len() &&
+                       !iter.data[iter.offset..].iter().all(|&x| x == 0xff) =>
+                iter.data.len(),
+            _ => iter.offset
+        };
+
+        let live = self.live();
+        for (key, value) in live.clone() {
+            stats.live_records += 1;
+            stats.live_bytes += live.iter.record_size(key, value);
+        }
+        stats.dead_records = records - stats.live_records;
+        stats
+    }
+
+    /// Calls `f` for every corrupted record in the active sector.
+    pub fn check<G: FnMut(Error)>(&self, mut f: G) {
+        for result in self.records() {
+            if let Err(err) = result {
+                f(err)
+            }
+        }
+    }
+
+    unsafe fn program_record(&self, index: usize, offset: usize,
+                             key: &[u8], value: &[u8]) -> usize {
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        digest.write(key);
+        digest.write(&[0]);
+        digest.write(value);
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        BigEndian::write_u32(&mut header[0..], record_size(key, value) as u32);
+        BigEndian::write_u32(&mut header[4..], digest.sum32());
+
+        let mut offset = offset;
+        for payload in [&header[..], key, &[0u8][..], value].iter() {
+            self.flash.program(index, offset, payload);
+            offset += payload.len();
+        }
+        offset
+    }
+
+    // Writes `entries` into the inactive sector, then makes it the active one.
+    // Nothing is erased unless all the entries fit.
+    fn rewrite<'a, I>(&self, entries: I) -> Result<(), Error>
+            where I: Iterator<Item=(&'a [u8], &'a [u8])> + Clone {
+        let (target, generation, legacy) = match self.active() {
+            Some((index, Format::Journal { generation })) =>
+                (1 - index, generation.wrapping_add(1), false),
+            Some((index, Format::Legacy)) =>
+                (1 - index, 1, true),
+            None =>
+                (0, 1, false)
+        };
+
+        let size = HEADER_SIZE + entries.clone()
+            .map(|(key, value)| record_size(key, value))
+            .sum::<usize>();
+        if size > self.flash.sector(target).len() {
+            return Err(Error::SpaceExhausted)
+        }
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(MAGIC);
+        BigEndian::write_u32(&mut header[4..], generation);
+        let checksum = crc32::checksum_ieee(&header[..8]);
+        BigEndian::write_u32(&mut header[8..], checksum);
+
+        unsafe {
+            self.flash.erase(target);
+            let mut offset = HEADER_SIZE;
+            for (key, value) in entries {
+                offset = self.program_record(target, offset, key, value);
+            }
+            self.flash.program(target, 0, &header);
+
+            if legacy {
+                // Otherwise, the legacy records would take precedence.
+                self.flash.erase(1 - target);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if let Some((index, format @ Format::Journal { .. })) = self.active() {
+            // Append to the active sector, unless the end of its records cannot be found.
+            let mut iter = Iter::new(self.flash.sector(index), format);
+            let mut intact = true;
+            while let Some(result) = iter.next() {
+                match result {
+                    Err(Error::Truncated { .. }) | Err(Error::InvalidSize { .. }) =>
+                        intact = false,
+                    _ => ()
+                }
+            }
+            if intact && iter.offset + record_size(key, value) <= iter.data.len() {
+                unsafe { self.program_record(index, iter.offset, key, value) };
+                return Ok(())
+            }
+        }
+
+        let live = self.live().filter(|&(live_key, _)| live_key != key);
+        let new = if value.is_empty() { None } else { Some((key, value)) };
+        self.rewrite(live.chain(new))
+    }
+
+    pub fn replace_all<'a, I>(&mut self, entries: I) -> Result<(), Error>
+            where I: Iterator<Item=(&'a [u8], &'a [u8])> + Clone {
+        self.rewrite(entries.filter(|&(_, value)| !value.is_empty()))
+    }
+
+    pub fn erase(&mut self) {
+        unsafe {
+            self.flash.erase(0);
+            self.flash.erase(1);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use super::*;
+
+    const SIZE: usize = 256;
+
+    fn legacy_sector(records: &[(&str, &str)]) -> [u8; SIZE] {
+        let mut sector = [0xff; SIZE];
+        let mut offset = 0;
+        for &(key, value) in records {
+            let size = LEGACY_RECORD_HEADER_SIZE + key.len() + 1 + value.len();
+            BigEndian::write_u32(&mut sector[offset..], size as u32);
+            let body = &mut sector[offset + LEGACY_RECORD_HEADER_SIZE..offset + size];
+            body[..key.len()].copy_from_slice(key.as_bytes());
+            body[key.len()] = 0;
+            body[key.len() + 1..].copy_from_slice(value.as_bytes());
+            offset += size;
+        }
+        sector
+    }
+
+    // Drops every erase or program operation after the first `budget` ones,
+    // as a power loss would.
+    struct Interrupted<'a> {
+        flash:  MemoryFlash<'a>,
+        budget: Cell<usize>
+    }
+
+    impl<'a> Interrupted<'a> {
+        fn spend(&self) -> bool {
+            match self.budget.get() {
+                0 => false,
+                budget => { self.budget.set(budget - 1); true }
+            }
+        }
+    }
+
+    impl<'a> Flash for Interrupted<'a> {
+        fn sector(&self, index: usize) -> &[u8] {
+            self.flash.sector(index)
+        }
+
+        unsafe fn erase(&self, index: usize) {
+            if self.spend() { self.flash.erase(index) }
+        }
+
+        unsafe fn program(&self, index: usize, offset: usize, data: &[u8]) {
+            if self.spend() { self.flash.program(index, offset, data) }
+        }
+    }
+
+    #[test]
+    fn write_read_remove() {
+        let (mut sector0, mut sector1) = ([0xff; SIZE], [0xff; SIZE]);
+        let mut store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+        assert_eq!(store.read(b"a"), Err(Error::KeyNotFound));
+        store.write(b"a", b"1").unwrap();
+        store.write(b"b", b"2").unwrap();
+        store.write(b"a", b"3").unwrap();
+        assert_eq!(store.read(b"a"), Ok(&b"3"[..]));
+        store.write(b"a", b"").unwrap();
+        assert_eq!(store.read(b"a"), Err(Error::KeyNotFound));
+        assert_eq!(store.read(b"b"), Ok(&b"2"[..]));
+    }
+
+    #[test]
+    fn power_loss_before_header() {
+        // Erasing the inactive sector, and programming the two records (four operations
+        // each) and then the header.
+        const OPERATIONS: usize = 1 + 2 * 4 + 1;
+
+        for budget in 0..OPERATIONS + 1 {
+            let (mut sector0, mut sector1) = ([0xff; SIZE], [0xff; SIZE]);
+            {
+                let mut store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+                store.write(b"a", b"1").unwrap();
+                store.write(b"b", b"2").unwrap();
+            }
+            {
+                let mut store = Store::new(Interrupted {
+                    flash:  MemoryFlash::new(&mut sector0, &mut sector1),
+                    budget: Cell::new(budget)
+                });
+                let entries = vec![(&b"a"[..], &b"3"[..]), (&b"b"[..], &b"4"[..])];
+                store.replace_all(entries.into_iter()).unwrap();
+            }
+
+            let store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+            if budget < OPERATIONS {
+                assert_eq!(store.read(b"a"), Ok(&b"1"[..]));
+                assert_eq!(store.read(b"b"), Ok(&b"2"[..]));
+            } else {
+                assert_eq!(store.read(b"a"), Ok(&b"3"[..]));
+                assert_eq!(store.read(b"b"), Ok(&b"4"[..]));
+            }
+            assert_eq!(store.stats().corruption, None);
+        }
+    }
+
+    #[test]
+    fn legacy_sector_is_migrated() {
+        let mut sector0 = legacy_sector(&[("a", "1"), ("b", "2")]);
+        let mut sector1 = [0xff; SIZE];
+        {
+            let mut store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+            assert_eq!(store.read(b"a"), Ok(&b"1"[..]));
+            store.write(b"c", b"3").unwrap();
+            assert_eq!(store.active(), Some((1, Format::Journal { generation: 1 })));
+            assert!(store.flash.sector(0).iter().all(|&x| x == 0xff));
+        }
+
+        let store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+        assert_eq!(store.read(b"a"), Ok(&b"1"[..]));
+        assert_eq!(store.read(b"b"), Ok(&b"2"[..]));
+        assert_eq!(store.read(b"c"), Ok(&b"3"[..]));
+    }
+
+    #[test]
+    fn legacy_sector_takes_precedence() {
+        let mut sector0 = legacy_sector(&[("a", "1")]);
+        let mut sector1 = [0xff; SIZE];
+        {
+            let mut store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+            store.write(b"b", b"2").unwrap();
+        }
+
+        // An image written by `artiq_flash storage` replaces the journal...
+        sector0 = legacy_sector(&[("a", "3")]);
+        {
+            let store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+            assert_eq!(store.read(b"a"), Ok(&b"3"[..]));
+            assert_eq!(store.read(b"b"), Err(Error::KeyNotFound));
+        }
+
+        // ... unless it does not parse, e.g. when erasing it after a migration
+        // was interrupted.
+        sector0[0] = 0x7f;
+        let store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+        assert_eq!(store.read(b"a"), Ok(&b"1"[..]));
+        assert_eq!(store.read(b"b"), Ok(&b"2"[..]));
+    }
+
+    #[test]
+    fn corrupted_record_is_skipped() {
+        let (mut sector0, mut sector1) = ([0xff; SIZE], [0xff; SIZE]);
+        let offset;
+        {
+            let mut store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+            store.write(b"a", b"1").unwrap();
+            offset = store.stats().used;
+            store.write(b"b", b"2").unwrap();
+            store.write(b"c", b"3").unwrap();
+        }
+
+        // Flip a bit in the value of `b`.
+        sector0[offset + RECORD_HEADER_SIZE + 2] ^= 1;
+
+        let mut store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+        assert_eq!(store.read(b"a"), Ok(&b"1"[..]));
+        assert_eq!(store.read(b"b"), Err(Error::KeyNotFound));
+        assert_eq!(store.read(b"c"), Ok(&b"3"[..]));
+
+        let mut errors = Vec::new();
+        store.check(|err| errors.push(err));
+        assert_eq!(errors, vec![Error::ChecksumMismatch { offset: offset }]);
+        let stats = store.stats();
+        assert_eq!(stats.corruption, Some(offset));
+        assert_eq!((stats.live_records, stats.dead_records), (2, 1));
+
+        // Records following the corrupted one can still be appended.
+        store.write(b"b", b"4").unwrap();
+        assert_eq!(store.read(b"b"), Ok(&b"4"[..]));
+    }
+
+    #[test]
+    fn compaction() {
+        let (mut sector0, mut sector1) = ([0xff; SIZE], [0xff; SIZE]);
+        let mut store = Store::new(MemoryFlash::new(&mut sector0, &mut sector1));
+        store.write(b"a", b"1").unwrap();
+        for i in 0..100 {
+            store.write(b"b", &[b'0' + i % 10]).unwrap();
+        }
+        assert_eq!(store.read(b"a"), Ok(&b"1"[..]));
+        assert_eq!(store.read(b"b"), Ok(&b"9"[..]));
+        match store.active() {
+            Some((_, Format::Journal { generation })) => assert!(generation > 1),
+            active => panic!("unexpected active sector {:?}", active)
+        }
+
+        // A configuration that does not fit is rejected, keeping the previous one.
+        assert_eq!(store.write(b"c", &[0x55; SIZE]), Err(Error::SpaceExhausted));
+        assert_eq!(store.read(b"a"), Ok(&b"1"[..]));
+        assert_eq!(store.read(b"b"), Ok(&b"9"[..]));
+        assert_eq!(store.stats().live_records, 2);
+    }
+}
@@ -1,4 +1,137 @@
-use io::{Write, ProtoWrite, Error as IoError};
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, BigEndian};
+
+use io::{Read, ProtoRead, Write, ProtoWrite, Error as IoError};
+
+#[derive(Fail, Debug)]
+pub enum Error<T> {
+    #[fail(display = "unknown packet {:#02x}", _0)]
+    UnknownPacket(u8),
+    #[fail(display = "unknown trigger type {:#02x}", _0)]
+    UnknownTrigger(u8),
+    #[fail(display = "too many channels in filter ({})", _0)]
+    TooManyChannels(u32),
+    #[fail(display = "{}", _0)]
+    Io(#[cause] IoError<T>)
+}
+
+impl<T> From<IoError<T>> for Error<T> {
+    fn from(value: IoError<T>) -> Error<T> {
+        Error::Io(value)
+    }
+}
+
+// Version of the extended header sent in streaming mode.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    OneShot,
+    Streaming
+}
+
+pub const MAX_FILTER_CHANNELS: u32 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    None,
+    Channel(u32),
+    Timestamp(u64)
+}
+
+#[derive(Debug)]
+pub enum Request {
+    Dump,
+    Stream,
+
+    SetChannelFilter { channels: Vec<u32> },
+    SetTrigger { start: Trigger, stop: Trigger },
+    SetTriggerDepth { pre_trigger: u32, post_trigger: u32 },
+    Arm
+}
+
+// Requests are sent before the device has announced its endianness,
+// so their fields are always big endian.
+fn read_be_u32<R>(reader: &mut R) -> Result<u32, IoError<R::ReadError>>
+    where R: Read + ?Sized
+{
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(BigEndian::read_u32(&bytes))
+}
+
+fn read_be_u64<R>(reader: &mut R) -> Result<u64, IoError<R::ReadError>>
+    where R: Read + ?Sized
+{
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(BigEndian::read_u64(&bytes))
+}
+
+fn read_trigger<R>(reader: &mut R) -> Result<Trigger, Error<R::ReadError>>
+    where R: Read + ?Sized
+{
+    Ok(match reader.read_u8()? {
+        0 => Trigger::None,
+        1 => Trigger::Channel(read_be_u32(reader)?),
+        2 => Trigger::Timestamp(read_be_u64(reader)?),
+        ty => return Err(Error::UnknownTrigger(ty))
+    })
+}
+
+impl Request {
+    pub fn read_from<R>(reader: &mut R) -> Result<Self, Error<R::ReadError>>
+        where R: Read + ?Sized
+    {
+        Ok(match reader.read_u8()? {
+            0x01 => Request::Dump,
+            0x02 => Request::Stream,
+
+            0x10 => {
+                let count = read_be_u32(reader)?;
+                if count > MAX_FILTER_CHANNELS {
+                    return Err(Error::TooManyChannels(count))
+                }
+                let mut channels = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    channels.push(read_be_u32(reader)?);
+                }
+                Request::SetChannelFilter { channels: channels }
+            },
+            0x11 => Request::SetTrigger {
+                start: read_trigger(reader)?,
+                stop: read_trigger(reader)?
+            },
+            0x12 => Request::SetTriggerDepth {
+                pre_trigger: read_be_u32(reader)?,
+                post_trigger: read_be_u32(reader)?
+            },
+            0x13 => Request::Arm,
+
+            ty => return Err(Error::UnknownPacket(ty))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Reply {
+    Armed,
+    // The capture buffers could not be allocated; the previous
+    // configuration remains in effect.
+    OutOfMemory
+}
+
+impl Reply {
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        match *self {
+            Reply::Armed => writer.write_u8(0x01)?,
+            Reply::OutOfMemory => writer.write_u8(0x02)?
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
 pub struct Header {
@@ -6,7 +139,8 @@ pub struct Header {
     pub total_byte_count: u64,
     pub overflow_occurred: bool,
     pub log_channel: u8,
-    pub dds_onehot_sel: bool
+    pub dds_onehot_sel: bool,
+    pub mode: Mode
 }
 
 impl Header {
@@ -18,6 +152,40 @@ impl Header {
         writer.write_u8(self.overflow_occurred as u8)?;
         writer.write_u8(self.log_channel)?;
         writer.write_u8(self.dds_onehot_sel as u8)?;
+        // One-shot dumps keep the original header layout, so that clients
+        // which never send a request can still decode them.
+        match self.mode {
+            Mode::OneShot => (),
+            Mode::Streaming => {
+                writer.write_u8(PROTOCOL_VERSION)?;
+                writer.write_u8(1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamRecord<'a> {
+    Data(&'a [u8]),
+    Overflow { lost_bytes: u64, encoder_overflow: bool }
+}
+
+impl<'a> StreamRecord<'a> {
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        match *self {
+            StreamRecord::Data(data) => {
+                writer.write_u8(0)?;
+                writer.write_bytes(data)?;
+            },
+            StreamRecord::Overflow { lost_bytes, encoder_overflow } => {
+                writer.write_u8(1)?;
+                writer.write_u64(lost_bytes)?;
+                writer.write_bool(encoder_overflow)?;
+            }
+        }
         Ok(())
     }
 }
@@ -0,0 +1,326 @@
+// Encoding and validation of DRTIO routing tables, shared by the master, which
+// reads them from its configuration, and the satellites, which receive them
+// over aux packets. A table has one entry of MAX_HOPS hops per destination.
+
+use core::fmt;
+
+pub const MAX_HOPS: usize = 32;
+pub const INVALID_HOP: u8 = 0xff;
+
+// First byte of a routing table in the sparse format. It is followed by
+// entries made of the destination, the number of hops, and the hops, the
+// last of which is 0 (the local RTIO core of the destination). The legacy
+// format, a table of DEST_COUNT * MAX_HOPS bytes, is recognized by its length.
+pub const FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Truncated,
+    UnsupportedVersion(u8),
+    InvalidDestination(u8),
+    DuplicateDestination(u8),
+    // The route does not end with a local RTIO core, so packets would be
+    // forwarded until they run out of hops.
+    Unterminated(u8),
+    // The route goes through an invalid hop.
+    Unreachable(u8),
+    InvalidLink { destination: u8, hop: u8 },
+    // Both destinations lead to the same RTIO core.
+    DuplicatePath { destination: u8, other: u8 }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Truncated =>
+                write!(f, "truncated entry"),
+            &Error::UnsupportedVersion(version) =>
+                write!(f, "unsupported format version {}", version),
+            &Error::InvalidDestination(destination) =>
+                write!(f, "destination {} is out of range", destination),
+            &Error::DuplicateDestination(destination) =>
+                write!(f, "destination {} has several routes", destination),
+            &Error::Unterminated(destination) =>
+                write!(f, "route to destination {} does not end with a local RTIO core", destination),
+            &Error::Unreachable(destination) =>
+                write!(f, "route to destination {} goes through an invalid hop", destination),
+            &Error::InvalidLink { destination, hop } =>
+                write!(f, "route to destination {} uses link {}, which does not exist", destination, hop),
+            &Error::DuplicatePath { destination, other } =>
+                write!(f, "destinations {} and {} have the same route", other, destination)
+        }
+    }
+}
+
+// Reads a routing table in either format into `table`, which must not route
+// any destination.
+pub fn read(table: &mut [[u8; MAX_HOPS]], data: &[u8]) -> Result<(), Error> {
+    let legacy = data.len() == table.len() * MAX_HOPS;
+    match data.first() {
+        Some(&FORMAT_VERSION) => {
+            let result = read_entries(table, &data[1..]);
+            // A legacy table may start with the same byte.
+            if result.is_ok() || !legacy {
+                return result
+            }
+            for entry in table.iter_mut() {
+                *entry = [INVALID_HOP; MAX_HOPS];
+            }
+        },
+        Some(&version) if !legacy => return Err(Error::UnsupportedVersion(version)),
+        None if !legacy => return Err(Error::Truncated),
+        _ => ()
+    }
+    for (entry, hops) in table.iter_mut().zip(data.chunks(MAX_HOPS)) {
+        entry.copy_from_slice(hops);
+    }
+    Ok(())
+}
+
+// Reads entries in the sparse format (without the version byte) into
+// `table`, which must not already route their destinations.
+pub fn read_entries(table: &mut [[u8; MAX_HOPS]], mut data: &[u8]) -> Result<(), Error> {
+    while !data.is_empty() {
+        if data.len() < 2 {
+            return Err(Error::Truncated)
+        }
+        let (destination, count) = (data[0], data[1] as usize);
+        let hops = data.get(2..2 + count).ok_or(Error::Truncated)?;
+        if destination as usize >= table.len() {
+            return Err(Error::InvalidDestination(destination))
+        }
+        if count > MAX_HOPS {
+            return Err(Error::Unterminated(destination))
+        }
+        let entry = &mut table[destination as usize];
+        if entry[0] != INVALID_HOP {
+            return Err(Error::DuplicateDestination(destination))
+        }
+        // An entry leaving the destination unrouted could not be told apart
+        // from a missing one, which would hide duplicates.
+        if count == 0 || hops[0] == INVALID_HOP {
+            return Err(Error::Unreachable(destination))
+        }
+        entry[..count].copy_from_slice(hops);
+        data = &data[2 + count..];
+    }
+    Ok(())
+}
+
+// Writes the routed entries, starting from `destination`, in the format
+// read by `read_entries` for as long as they fit in `buffer`. Returns the
+// number of bytes written and the first destination left out.
+pub fn write_entries(table: &[[u8; MAX_HOPS]], mut destination: usize, buffer: &mut [u8])
+        -> (usize, usize) {
+    let mut length = 0;
+    while destination < table.len() {
+        let hops = &table[destination];
+        if hops[0] != INVALID_HOP {
+            let count = hops.iter().position(|&hop| hop == 0).map_or(MAX_HOPS, |rank| rank + 1);
+            if length + 2 + count > buffer.len() {
+                break
+            }
+            buffer[length] = destination as u8;
+            buffer[length + 1] = count as u8;
+            buffer[length + 2..length + 2 + count].copy_from_slice(&hops[..count]);
+            length += 2 + count;
+        }
+        destination += 1;
+    }
+    (length, destination)
+}
+
+// Checks the routes of a master with `n_links` DRTIO links.
+pub fn validate(table: &[[u8; MAX_HOPS]], n_links: usize) -> Result<(), Error> {
+    for (i, hops) in table.iter().enumerate() {
+        let destination = i as u8;
+        if hops[0] == INVALID_HOP {
+            continue
+        }
+        let rank = hops.iter().position(|&hop| hop == 0)
+            .ok_or(Error::Unterminated(destination))?;
+        if hops[..rank].contains(&INVALID_HOP) {
+            return Err(Error::Unreachable(destination))
+        }
+        if rank > 0 && hops[0] as usize > n_links {
+            return Err(Error::InvalidLink { destination: destination, hop: hops[0] })
+        }
+    }
+    for (i, hops) in table.iter().enumerate() {
+        if hops[0] == INVALID_HOP {
+            continue
+        }
+        let path = &hops[..hops.iter().position(|&hop| hop == 0).unwrap() + 1];
+        let other = table[..i].iter().position(|other| other.starts_with(path));
+        if let Some(other) = other {
+            return Err(Error::DuplicatePath { destination: i as u8, other: other as u8 })
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEST_COUNT: usize = 256;
+
+    fn empty() -> [[u8; MAX_HOPS]; DEST_COUNT] {
+        [[INVALID_HOP; MAX_HOPS]; DEST_COUNT]
+    }
+
+    fn route(table: &mut [[u8; MAX_HOPS]], destination: usize, hops: &[u8]) {
+        table[destination][..hops.len()].copy_from_slice(hops);
+    }
+
+    #[test]
+    fn sparse_round_trip() {
+        let mut table = empty();
+        route(&mut table, 0, &[0]);
+        route(&mut table, 1, &[1, 0]);
+        route(&mut table, 2, &[1, 2, 0]);
+        route(&mut table, 255, &[2, 0]);
+
+        let mut buffer = [0; 64];
+        buffer[0] = FORMAT_VERSION;
+        let (length, next) = write_entries(&table, 0, &mut buffer[1..]);
+        assert_eq!(next, DEST_COUNT);
+        assert_eq!(&buffer[1..1 + length],
+                   &[0, 1, 0,  1, 2, 1, 0,  2, 3, 1, 2, 0,  255, 2, 2, 0][..]);
+
+        let mut read_table = empty();
+        assert_eq!(read(&mut read_table, &buffer[..1 + length]), Ok(()));
+        assert!(read_table[..] == table[..]);
+        assert_eq!(validate(&read_table, 2), Ok(()));
+    }
+
+    #[test]
+    fn write_entries_stops_when_full() {
+        let mut table = empty();
+        route(&mut table, 1, &[1, 0]);
+        route(&mut table, 2, &[2, 0]);
+        let mut buffer = [0; 6];
+        assert_eq!(write_entries(&table, 0, &mut buffer), (4, 2));
+        assert_eq!(write_entries(&table, 2, &mut buffer), (4, DEST_COUNT));
+    }
+
+    #[test]
+    fn legacy() {
+        let mut data = [INVALID_HOP; DEST_COUNT * MAX_HOPS];
+        data[0] = 0;
+        data[MAX_HOPS..MAX_HOPS + 2].copy_from_slice(&[1, 0]);
+        let mut table = empty();
+        assert_eq!(read(&mut table, &data), Ok(()));
+        assert_eq!(&table[0][..2], &[0, INVALID_HOP]);
+        assert_eq!(&table[1][..3], &[1, 0, INVALID_HOP]);
+        assert_eq!(table[2][0], INVALID_HOP);
+    }
+
+    #[test]
+    fn legacy_starting_with_version() {
+        // Destination 0 routed through link 1.
+        let mut data = [INVALID_HOP; DEST_COUNT * MAX_HOPS];
+        data[..2].copy_from_slice(&[FORMAT_VERSION, 0]);
+        let mut table = empty();
+        assert_eq!(read(&mut table, &data), Ok(()));
+        assert_eq!(&table[0][..3], &[1, 0, INVALID_HOP]);
+        assert!(table[1..].iter().all(|hops| hops[0] == INVALID_HOP));
+    }
+
+    #[test]
+    fn sparse_of_legacy_length() {
+        // 240 entries of 32 hops and one of 29, for 8191 bytes after the version.
+        let mut data = [0; DEST_COUNT * MAX_HOPS];
+        data[0] = FORMAT_VERSION;
+        let mut offset = 1;
+        for destination in 0..241 {
+            let count = if destination < 240 { MAX_HOPS } else { 29 };
+            data[offset] = destination as u8;
+            data[offset + 1] = count as u8;
+            for hop in &mut data[offset + 2..offset + 1 + count] {
+                *hop = 1;
+            }
+            // Distinct routes, through link 1.
+            data[offset + 3] = destination as u8 + 1;
+            offset += 2 + count;
+        }
+        assert_eq!(offset, data.len());
+
+        let mut table = empty();
+        assert_eq!(read(&mut table, &data), Ok(()));
+        assert_eq!(&table[0][MAX_HOPS - 2..], &[1, 0]);
+        assert_eq!(&table[240][27..30], &[1, 0, INVALID_HOP]);
+        assert_eq!(table[241][0], INVALID_HOP);
+        assert_eq!(validate(&table, 1), Ok(()));
+    }
+
+    #[test]
+    fn invalid_sparse() {
+        let mut table = empty();
+        assert_eq!(read(&mut table, &[]), Err(Error::Truncated));
+        assert_eq!(read(&mut table, &[2]), Err(Error::UnsupportedVersion(2)));
+        assert_eq!(read(&mut table, &[1, 3]), Err(Error::Truncated));
+        assert_eq!(read(&mut table, &[1, 3, 2, 1]), Err(Error::Truncated));
+        assert_eq!(read(&mut table, &[1, 3, 0]), Err(Error::Unreachable(3)));
+        assert_eq!(read(&mut table, &[1, 3, 33]), Err(Error::Truncated));
+
+        let mut data = [1; 2 + 33];
+        data[0] = 3;
+        data[1] = 33;
+        assert_eq!(read_entries(&mut table, &data), Err(Error::Unterminated(3)));
+
+        let mut small = [[INVALID_HOP; MAX_HOPS]; 4];
+        assert_eq!(read_entries(&mut small, &[4, 1, 0]), Err(Error::InvalidDestination(4)));
+    }
+
+    #[test]
+    fn duplicate_destinations() {
+        let mut table = empty();
+        assert_eq!(read_entries(&mut table, &[3, 2, 1, 0,  3, 2, 2, 0]),
+                   Err(Error::DuplicateDestination(3)));
+
+        // Entries are read from several chunks on satellites.
+        let mut table = empty();
+        assert_eq!(read_entries(&mut table, &[3, 2, 1, 0]), Ok(()));
+        assert_eq!(read_entries(&mut table, &[3, 1, 0]), Err(Error::DuplicateDestination(3)));
+
+        // An entry starting with an invalid hop would leave the destination unrouted,
+        // so that a later entry for it would not be detected as a duplicate.
+        let mut table = empty();
+        assert_eq!(read_entries(&mut table, &[3, 2, INVALID_HOP, 0,  3, 2, 1, 0]),
+                   Err(Error::Unreachable(3)));
+    }
+
+    #[test]
+    fn validation() {
+        let mut table = empty();
+        route(&mut table, 1, &[1, 0]);
+        assert_eq!(validate(&table, 1), Ok(()));
+        assert_eq!(validate(&table, 0), Err(Error::InvalidLink { destination: 1, hop: 1 }));
+
+        route(&mut table, 2, &[1, INVALID_HOP, 0]);
+        assert_eq!(validate(&table, 1), Err(Error::Unreachable(2)));
+
+        let mut table = empty();
+        route(&mut table, 2, &[1; MAX_HOPS]);
+        assert_eq!(validate(&table, 1), Err(Error::Unterminated(2)));
+    }
+
+    #[test]
+    fn duplicate_paths() {
+        let mut table = empty();
+        route(&mut table, 0, &[0]);
+        route(&mut table, 1, &[1, 0]);
+        route(&mut table, 2, &[1, 2, 0]);
+        route(&mut table, 3, &[1, 3, 0]);
+        assert_eq!(validate(&table, 1), Ok(()));
+
+        route(&mut table, 4, &[1, 2, 0]);
+        assert_eq!(validate(&table, 1), Err(Error::DuplicatePath { destination: 4, other: 2 }));
+
+        let mut table = empty();
+        route(&mut table, 3, &[0]);
+        route(&mut table, 7, &[0]);
+        assert_eq!(validate(&table, 0), Err(Error::DuplicatePath { destination: 7, other: 3 }));
+    }
+}
@@ -1,3 +1,5 @@
+use core::borrow::Borrow;
+
 use io::{Read, ProtoRead, Write, ProtoWrite, Error as IoError};
 
 #[derive(Fail, Debug)]
@@ -14,6 +16,159 @@ impl<T> From<IoError<T>> for Error<T> {
     }
 }
 
+// Largest number of probes or overrides read by a single MonitorBatchRequest
+// or InjectionStatusBatchRequest; the reply must fit in an aux packet.
+pub const MONITOR_BATCH_MAX: usize = 64;
+
+// Largest number of bytes carried by a BulkChunk or BulkReadReply packet.
+pub const BULK_CHUNK_MAX: usize = 512;
+
+// Largest number of bytes of routing table entries carried by a
+// RoutingTableChunk packet; with the frame header and CRC, it fills an aux
+// packet.
+pub const ROUTING_CHUNK_MAX: usize = 960;
+
+// Kinds of bulk transfers. The request is uploaded to the destination with
+// BulkStart, BulkChunk and BulkEnd; the destination then processes it and
+// makes its response available to BulkReadRequest.
+pub const BULK_ECHO: u8 = 0;
+pub const BULK_STATUS: u8 = 1;
+// Requests on the config of the destination: the key, followed for writes by
+// a zero byte and the value. Reads respond with the value.
+pub const BULK_CONFIG_READ: u8 = 2;
+pub const BULK_CONFIG_WRITE: u8 = 3;
+pub const BULK_CONFIG_REMOVE: u8 = 4;
+// A firmware image with the header checked by the bootloader (length and CRC32
+// of the image, little-endian). Once checked, it is written to the boot flash
+// of the destination in the background; see FirmwareStatusRequest.
+pub const BULK_FIRMWARE: u8 = 5;
+
+// Time to wait for the reply to BulkEnd, which the destination only sends
+// once the request is processed; config writes may erase flash sectors.
+pub const BULK_END_TIMEOUT_MS: u32 = 10_000;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BulkStatus {
+    Ok,
+    TooLarge,
+    OutOfOrder,
+    ChecksumMismatch,
+    UnknownKind,
+    NoTransfer,
+    NotFound,
+    Rejected,
+    Busy,
+    Failed
+}
+
+impl BulkStatus {
+    fn from_u8(status: u8) -> BulkStatus {
+        match status {
+            0 => BulkStatus::Ok,
+            1 => BulkStatus::TooLarge,
+            2 => BulkStatus::OutOfOrder,
+            3 => BulkStatus::ChecksumMismatch,
+            4 => BulkStatus::UnknownKind,
+            5 => BulkStatus::NoTransfer,
+            7 => BulkStatus::NotFound,
+            8 => BulkStatus::Rejected,
+            9 => BulkStatus::Busy,
+            _ => BulkStatus::Failed
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            BulkStatus::Ok => 0,
+            BulkStatus::TooLarge => 1,
+            BulkStatus::OutOfOrder => 2,
+            BulkStatus::ChecksumMismatch => 3,
+            BulkStatus::UnknownKind => 4,
+            BulkStatus::NoTransfer => 5,
+            BulkStatus::Failed => 6,
+            BulkStatus::NotFound => 7,
+            BulkStatus::Rejected => 8,
+            BulkStatus::Busy => 9
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            BulkStatus::Ok => "success",
+            BulkStatus::TooLarge => "transfer too large",
+            BulkStatus::OutOfOrder => "chunk out of order",
+            BulkStatus::ChecksumMismatch => "checksum mismatch",
+            BulkStatus::UnknownKind => "unknown transfer kind",
+            BulkStatus::NoTransfer => "no transfer in progress",
+            BulkStatus::NotFound => "not found",
+            BulkStatus::Rejected => "request rejected",
+            BulkStatus::Busy => "destination busy",
+            BulkStatus::Failed => "request failed"
+        }
+    }
+}
+
+// Counters of a DRTIO link, kept by the master for each of its links and by
+// satellites for their uplink and repeater links.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct LinkStats {
+    pub crc_errors:            u32,
+    pub unknown_packets:       u32,
+    pub truncated_packets:     u32,
+    pub buffer_space_timeouts: u32,
+    pub link_down_events:      u32,
+    // Aux packets sent again for lack of acknowledgement.
+    pub retransmissions:       u32,
+    // Round trip of the last aux echo, in microseconds; zero if never measured.
+    pub ping_latency_us:       u32,
+    // Time since the link last came up, in milliseconds; zero if it is down.
+    pub uptime_ms:             u64
+}
+
+impl LinkStats {
+    pub const fn new() -> LinkStats {
+        LinkStats {
+            crc_errors:            0,
+            unknown_packets:       0,
+            truncated_packets:     0,
+            buffer_space_timeouts: 0,
+            link_down_events:      0,
+            retransmissions:       0,
+            ping_latency_us:       0,
+            uptime_ms:             0
+        }
+    }
+
+    pub fn read_from<R>(reader: &mut R) -> Result<Self, IoError<R::ReadError>>
+        where R: Read + ?Sized
+    {
+        Ok(LinkStats {
+            crc_errors:            reader.read_u32()?,
+            unknown_packets:       reader.read_u32()?,
+            truncated_packets:     reader.read_u32()?,
+            buffer_space_timeouts: reader.read_u32()?,
+            link_down_events:      reader.read_u32()?,
+            retransmissions:       reader.read_u32()?,
+            ping_latency_us:       reader.read_u32()?,
+            uptime_ms:             reader.read_u64()?
+        })
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_u32(self.crc_errors)?;
+        writer.write_u32(self.unknown_packets)?;
+        writer.write_u32(self.truncated_packets)?;
+        writer.write_u32(self.buffer_space_timeouts)?;
+        writer.write_u32(self.link_down_events)?;
+        writer.write_u32(self.retransmissions)?;
+        writer.write_u32(self.ping_latency_us)?;
+        writer.write_u64(self.uptime_ms)?;
+        Ok(())
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum Packet {
     EchoRequest,
@@ -21,6 +176,8 @@ pub enum Packet {
     ResetRequest,
     ResetAck,
     TSCAck,
+    AuxSequencingRequest,
+    AuxSequencingAck,
 
     DestinationStatusRequest { destination: u8 },
     DestinationDownReply,
@@ -28,16 +185,59 @@ pub enum Packet {
     DestinationSequenceErrorReply { channel: u16 },
     DestinationCollisionReply { channel: u16 },
     DestinationBusyReply { channel: u16 },
+    LinkStatsRequest { destination: u8, linkno: u8 },
+    // `links` is the number of aux links of the destination; `stats` is
+    // meaningful only if the requested link is one of them.
+    LinkStatsReply { links: u8, stats: LinkStats },
+    RebootRequest { destination: u8 },
+    RebootReply { succeeded: bool },
 
     RoutingSetPath { destination: u8, hops: [u8; 32] },
     RoutingSetRank { rank: u8 },
     RoutingAck,
+    // Replaces the routing table of the satellite: RoutingTableStart clears a
+    // new table, RoutingTableChunk adds entries to it in the sparse format of
+    // drtio_routing (without the version byte), and RoutingTableCommit makes it
+    // current and loads it into the repeaters. The rank must be set again for
+    // the interconnect to use it. Each is answered by RoutingAck, or by
+    // RoutingNack if the entries are invalid or no table was started.
+    RoutingTableStart,
+    RoutingTableChunk { length: u16, data: [u8; ROUTING_CHUNK_MAX] },
+    RoutingTableCommit,
+    RoutingNack,
+    // Routed along `hops`, in the format of a routing table entry, rather than
+    // by destination, so that satellites can be reached before they have one.
+    TopologyRequest { hops: [u8; 32] },
+    // `up` has a bit set for each repeater link that is up.
+    TopologyReply { repeaters: u8, up: u32 },
 
     MonitorRequest { destination: u8, channel: u16, probe: u8 },
     MonitorReply { value: u64 },
+    MonitorBatchRequest { destination: u8, count: u8,
+                          channels: [u16; MONITOR_BATCH_MAX], probes: [u8; MONITOR_BATCH_MAX] },
+    MonitorBatchReply { count: u8, values: [u64; MONITOR_BATCH_MAX] },
+    MoninjInfoRequest { destination: u8 },
+    MoninjInfoReply { channels: u16, probes: u8, overrides: u8 },
     InjectionRequest { destination: u8, channel: u16, overrd: u8, value: u8 },
     InjectionStatusRequest { destination: u8, channel: u16, overrd: u8 },
     InjectionStatusReply { value: u8 },
+    InjectionStatusBatchRequest { destination: u8, count: u8,
+                                  channels: [u16; MONITOR_BATCH_MAX], overrds: [u8; MONITOR_BATCH_MAX] },
+    InjectionStatusBatchReply { count: u8, values: [u8; MONITOR_BATCH_MAX] },
+
+    BulkStart { destination: u8, kind: u8, length: u32 },
+    BulkChunk { destination: u8, offset: u32, length: u16, data: [u8; BULK_CHUNK_MAX] },
+    // `crc` is the CRC32 of the whole request.
+    BulkEnd { destination: u8, crc: u32 },
+    // Reply to BulkStart, BulkChunk and BulkEnd; `length` and `crc` describe
+    // the response, once BulkEnd has been processed.
+    BulkReply { status: BulkStatus, length: u32, crc: u32 },
+    BulkReadRequest { destination: u8, offset: u32 },
+    BulkReadReply { length: u16, data: [u8; BULK_CHUNK_MAX] },
+    FirmwareStatusRequest { destination: u8 },
+    // `status` is Busy while the image is being written, and `written` the
+    // number of bytes programmed so far.
+    FirmwareStatusReply { status: BulkStatus, written: u32 },
 
     I2cStartRequest { destination: u8, busno: u8 },
     I2cRestartRequest { destination: u8, busno: u8 },
@@ -63,12 +263,21 @@ impl Packet {
     pub fn read_from<R>(reader: &mut R) -> Result<Self, Error<R::ReadError>>
         where R: Read + ?Sized
     {
-        Ok(match reader.read_u8()? {
+        let ty = reader.read_u8()?;
+        Packet::read_body(ty, reader)
+    }
+
+    fn read_body<R>(ty: u8, reader: &mut R) -> Result<Self, Error<R::ReadError>>
+        where R: Read + ?Sized
+    {
+        Ok(match ty {
             0x00 => Packet::EchoRequest,
             0x01 => Packet::EchoReply,
             0x02 => Packet::ResetRequest,
             0x03 => Packet::ResetAck,
             0x04 => Packet::TSCAck,
+            0x05 => Packet::AuxSequencingRequest,
+            0x06 => Packet::AuxSequencingAck,
 
             0x20 => Packet::DestinationStatusRequest {
                 destination: reader.read_u8()?
@@ -84,6 +293,20 @@ impl Packet {
             0x25 => Packet::DestinationBusyReply {
                 channel: reader.read_u16()?
             },
+            0x26 => Packet::LinkStatsRequest {
+                destination: reader.read_u8()?,
+                linkno: reader.read_u8()?
+            },
+            0x27 => Packet::LinkStatsReply {
+                links: reader.read_u8()?,
+                stats: LinkStats::read_from(reader)?
+            },
+            0x28 => Packet::RebootRequest {
+                destination: reader.read_u8()?
+            },
+            0x29 => Packet::RebootReply {
+                succeeded: reader.read_bool()?
+            },
 
             0x30 => {
                 let destination = reader.read_u8()?;
@@ -98,6 +321,29 @@ impl Packet {
                 rank: reader.read_u8()?
             },
             0x32 => Packet::RoutingAck,
+            0x33 => {
+                let mut hops = [0; 32];
+                reader.read_exact(&mut hops)?;
+                Packet::TopologyRequest {
+                    hops: hops
+                }
+            },
+            0x34 => Packet::TopologyReply {
+                repeaters: reader.read_u8()?,
+                up: reader.read_u32()?
+            },
+            0x35 => Packet::RoutingTableStart,
+            0x36 => {
+                let length = reader.read_u16()?.min(ROUTING_CHUNK_MAX as u16);
+                let mut data = [0; ROUTING_CHUNK_MAX];
+                reader.read_exact(&mut data[..length as usize])?;
+                Packet::RoutingTableChunk {
+                    length: length,
+                    data: data
+                }
+            },
+            0x37 => Packet::RoutingTableCommit,
+            0x38 => Packet::RoutingNack,
 
             0x40 => Packet::MonitorRequest {
                 destination: reader.read_u8()?,
@@ -107,6 +353,41 @@ impl Packet {
             0x41 => Packet::MonitorReply {
                 value: reader.read_u64()?
             },
+            0x42 => {
+                let destination = reader.read_u8()?;
+                let count = reader.read_u8()?;
+                let mut channels = [0; MONITOR_BATCH_MAX];
+                let mut probes = [0; MONITOR_BATCH_MAX];
+                for i in 0..(count as usize).min(MONITOR_BATCH_MAX) {
+                    channels[i] = reader.read_u16()?;
+                    probes[i] = reader.read_u8()?;
+                }
+                Packet::MonitorBatchRequest {
+                    destination: destination,
+                    count: count.min(MONITOR_BATCH_MAX as u8),
+                    channels: channels,
+                    probes: probes
+                }
+            },
+            0x43 => {
+                let count = reader.read_u8()?;
+                let mut values = [0; MONITOR_BATCH_MAX];
+                for i in 0..(count as usize).min(MONITOR_BATCH_MAX) {
+                    values[i] = reader.read_u64()?;
+                }
+                Packet::MonitorBatchReply {
+                    count: count.min(MONITOR_BATCH_MAX as u8),
+                    values: values
+                }
+            },
+            0x44 => Packet::MoninjInfoRequest {
+                destination: reader.read_u8()?
+            },
+            0x45 => Packet::MoninjInfoReply {
+                channels: reader.read_u16()?,
+                probes: reader.read_u8()?,
+                overrides: reader.read_u8()?
+            },
             0x50 => Packet::InjectionRequest {
                 destination: reader.read_u8()?,
                 channel: reader.read_u16()?,
@@ -121,6 +402,81 @@ impl Packet {
             0x52 => Packet::InjectionStatusReply {
                 value: reader.read_u8()?
             },
+            0x53 => {
+                let destination = reader.read_u8()?;
+                let count = reader.read_u8()?;
+                let mut channels = [0; MONITOR_BATCH_MAX];
+                let mut overrds = [0; MONITOR_BATCH_MAX];
+                for i in 0..(count as usize).min(MONITOR_BATCH_MAX) {
+                    channels[i] = reader.read_u16()?;
+                    overrds[i] = reader.read_u8()?;
+                }
+                Packet::InjectionStatusBatchRequest {
+                    destination: destination,
+                    count: count.min(MONITOR_BATCH_MAX as u8),
+                    channels: channels,
+                    overrds: overrds
+                }
+            },
+            0x54 => {
+                let count = reader.read_u8()?;
+                let mut values = [0; MONITOR_BATCH_MAX];
+                for i in 0..(count as usize).min(MONITOR_BATCH_MAX) {
+                    values[i] = reader.read_u8()?;
+                }
+                Packet::InjectionStatusBatchReply {
+                    count: count.min(MONITOR_BATCH_MAX as u8),
+                    values: values
+                }
+            },
+
+            0x60 => Packet::BulkStart {
+                destination: reader.read_u8()?,
+                kind: reader.read_u8()?,
+                length: reader.read_u32()?
+            },
+            0x61 => {
+                let destination = reader.read_u8()?;
+                let offset = reader.read_u32()?;
+                let length = reader.read_u16()?.min(BULK_CHUNK_MAX as u16);
+                let mut data = [0; BULK_CHUNK_MAX];
+                reader.read_exact(&mut data[..length as usize])?;
+                Packet::BulkChunk {
+                    destination: destination,
+                    offset: offset,
+                    length: length,
+                    data: data
+                }
+            },
+            0x62 => Packet::BulkEnd {
+                destination: reader.read_u8()?,
+                crc: reader.read_u32()?
+            },
+            0x63 => Packet::BulkReply {
+                status: BulkStatus::from_u8(reader.read_u8()?),
+                length: reader.read_u32()?,
+                crc: reader.read_u32()?
+            },
+            0x64 => Packet::BulkReadRequest {
+                destination: reader.read_u8()?,
+                offset: reader.read_u32()?
+            },
+            0x65 => {
+                let length = reader.read_u16()?.min(BULK_CHUNK_MAX as u16);
+                let mut data = [0; BULK_CHUNK_MAX];
+                reader.read_exact(&mut data[..length as usize])?;
+                Packet::BulkReadReply {
+                    length: length,
+                    data: data
+                }
+            },
+            0x66 => Packet::FirmwareStatusRequest {
+                destination: reader.read_u8()?
+            },
+            0x67 => Packet::FirmwareStatusReply {
+                status: BulkStatus::from_u8(reader.read_u8()?),
+                written: reader.read_u32()?
+            },
 
             0x80 => Packet::I2cStartRequest {
                 destination: reader.read_u8()?,
@@ -217,6 +573,10 @@ impl Packet {
                 writer.write_u8(0x03)?,
             Packet::TSCAck =>
                 writer.write_u8(0x04)?,
+            Packet::AuxSequencingRequest =>
+                writer.write_u8(0x05)?,
+            Packet::AuxSequencingAck =>
+                writer.write_u8(0x06)?,
 
             Packet::DestinationStatusRequest { destination } => {
                 writer.write_u8(0x20)?;
@@ -238,6 +598,24 @@ impl Packet {
                 writer.write_u8(0x25)?;
                 writer.write_u16(channel)?;
             },
+            Packet::LinkStatsRequest { destination, linkno } => {
+                writer.write_u8(0x26)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(linkno)?;
+            },
+            Packet::LinkStatsReply { links, ref stats } => {
+                writer.write_u8(0x27)?;
+                writer.write_u8(links)?;
+                stats.write_to(writer)?;
+            },
+            Packet::RebootRequest { destination } => {
+                writer.write_u8(0x28)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::RebootReply { succeeded } => {
+                writer.write_u8(0x29)?;
+                writer.write_bool(succeeded)?;
+            },
 
             Packet::RoutingSetPath { destination, hops } => {
                 writer.write_u8(0x30)?;
@@ -250,6 +628,26 @@ impl Packet {
             },
             Packet::RoutingAck =>
                 writer.write_u8(0x32)?,
+            Packet::TopologyRequest { hops } => {
+                writer.write_u8(0x33)?;
+                writer.write_all(&hops)?;
+            },
+            Packet::TopologyReply { repeaters, up } => {
+                writer.write_u8(0x34)?;
+                writer.write_u8(repeaters)?;
+                writer.write_u32(up)?;
+            },
+            Packet::RoutingTableStart =>
+                writer.write_u8(0x35)?,
+            Packet::RoutingTableChunk { length, ref data } => {
+                writer.write_u8(0x36)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[..length as usize])?;
+            },
+            Packet::RoutingTableCommit =>
+                writer.write_u8(0x37)?,
+            Packet::RoutingNack =>
+                writer.write_u8(0x38)?,
 
             Packet::MonitorRequest { destination, channel, probe } => {
                 writer.write_u8(0x40)?;
@@ -261,6 +659,32 @@ impl Packet {
                 writer.write_u8(0x41)?;
                 writer.write_u64(value)?;
             },
+            Packet::MonitorBatchRequest { destination, count, ref channels, ref probes } => {
+                writer.write_u8(0x42)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(count)?;
+                for i in 0..count as usize {
+                    writer.write_u16(channels[i])?;
+                    writer.write_u8(probes[i])?;
+                }
+            },
+            Packet::MonitorBatchReply { count, ref values } => {
+                writer.write_u8(0x43)?;
+                writer.write_u8(count)?;
+                for i in 0..count as usize {
+                    writer.write_u64(values[i])?;
+                }
+            },
+            Packet::MoninjInfoRequest { destination } => {
+                writer.write_u8(0x44)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::MoninjInfoReply { channels, probes, overrides } => {
+                writer.write_u8(0x45)?;
+                writer.write_u16(channels)?;
+                writer.write_u8(probes)?;
+                writer.write_u8(overrides)?;
+            },
             Packet::InjectionRequest { destination, channel, overrd, value } => {
                 writer.write_u8(0x50)?;
                 writer.write_u8(destination)?;
@@ -278,6 +702,66 @@ impl Packet {
                 writer.write_u8(0x52)?;
                 writer.write_u8(value)?;
             },
+            Packet::InjectionStatusBatchRequest { destination, count, ref channels, ref overrds } => {
+                writer.write_u8(0x53)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(count)?;
+                for i in 0..count as usize {
+                    writer.write_u16(channels[i])?;
+                    writer.write_u8(overrds[i])?;
+                }
+            },
+            Packet::InjectionStatusBatchReply { count, ref values } => {
+                writer.write_u8(0x54)?;
+                writer.write_u8(count)?;
+                for i in 0..count as usize {
+                    writer.write_u8(values[i])?;
+                }
+            },
+
+            Packet::BulkStart { destination, kind, length } => {
+                writer.write_u8(0x60)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(kind)?;
+                writer.write_u32(length)?;
+            },
+            Packet::BulkChunk { destination, offset, length, ref data } => {
+                writer.write_u8(0x61)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(offset)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[..length as usize])?;
+            },
+            Packet::BulkEnd { destination, crc } => {
+                writer.write_u8(0x62)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(crc)?;
+            },
+            Packet::BulkReply { status, length, crc } => {
+                writer.write_u8(0x63)?;
+                writer.write_u8(status.to_u8())?;
+                writer.write_u32(length)?;
+                writer.write_u32(crc)?;
+            },
+            Packet::BulkReadRequest { destination, offset } => {
+                writer.write_u8(0x64)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(offset)?;
+            },
+            Packet::BulkReadReply { length, ref data } => {
+                writer.write_u8(0x65)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[..length as usize])?;
+            },
+            Packet::FirmwareStatusRequest { destination } => {
+                writer.write_u8(0x66)?;
+                writer.write_u8(destination)?;
+            },
+            Packet::FirmwareStatusReply { status, written } => {
+                writer.write_u8(0x67)?;
+                writer.write_u8(status.to_u8())?;
+                writer.write_u32(written)?;
+            },
 
             Packet::I2cStartRequest { destination, busno } => {
                 writer.write_u8(0x80)?;
@@ -374,3 +858,54 @@ impl Packet {
         Ok(())
     }
 }
+
+const FRAME_SEQUENCED: u8 = 0xf0;
+const FRAME_ACK: u8 = 0xf1;
+
+// What is carried by an aux packet buffer. Plain frames are bare packets, as
+// understood by all firmware versions; sequenced frames are acknowledged by
+// the receiver and retransmitted by the sender until they are.
+#[derive(PartialEq, Debug)]
+pub enum Frame<P> {
+    Plain(P),
+    Sequenced { seq: u8, packet: P },
+    Ack { seq: u8 }
+}
+
+impl Frame<Packet> {
+    pub fn read_from<R>(reader: &mut R) -> Result<Self, Error<R::ReadError>>
+        where R: Read + ?Sized
+    {
+        Ok(match reader.read_u8()? {
+            FRAME_SEQUENCED => Frame::Sequenced {
+                seq: reader.read_u8()?,
+                packet: Packet::read_from(reader)?
+            },
+            FRAME_ACK => Frame::Ack {
+                seq: reader.read_u8()?
+            },
+            ty => Frame::Plain(Packet::read_body(ty, reader)?)
+        })
+    }
+}
+
+impl<P: Borrow<Packet>> Frame<P> {
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        match *self {
+            Frame::Plain(ref packet) =>
+                packet.borrow().write_to(writer)?,
+            Frame::Sequenced { seq, ref packet } => {
+                writer.write_u8(FRAME_SEQUENCED)?;
+                writer.write_u8(seq)?;
+                packet.borrow().write_to(writer)?;
+            },
+            Frame::Ack { seq } => {
+                writer.write_u8(FRAME_ACK)?;
+                writer.write_u8(seq)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+// Optional reliable delivery of aux packets over a DRTIO link.
+//
+// Once enabled on a link (after the peer answered an AuxSequencingRequest),
+// packets are sent as sequenced frames, which the receiver acknowledges;
+// unacknowledged frames are retransmitted a bounded number of times.
+// Sequenced frames are acknowledged and delivered whether sending is enabled
+// locally or not, and plain frames are always accepted, so that either side
+// can be running firmware without this layer.
+
+use io::Cursor;
+use drtioaux_proto::{Packet, Frame};
+
+// Time to wait for the acknowledgement of a frame before retransmitting it.
+// Frames are acknowledged by the main loop of the peer, which may be busy
+// (e.g. erasing flash), so a frame is only given up on after 250 ms, more than
+// the 200 ms for which replies to aux requests are awaited.
+pub const ACK_TIMEOUT_US: u64 = 50_000;
+pub const MAX_RETRANSMISSIONS: u32 = 4;
+
+// Packets received while waiting for an acknowledgement, which are kept until
+// `recv` is called. The last slot is reserved for plain frames, which are not
+// retransmitted if dropped.
+const PENDING_MAX: usize = 4;
+
+#[derive(Debug)]
+pub enum Error<T> {
+    NotAcknowledged,
+    Link(T)
+}
+
+impl<T> From<T> for Error<T> {
+    fn from(value: T) -> Error<T> {
+        Error::Link(value)
+    }
+}
+
+// Access to the aux packet buffers of one end of a link.
+pub trait Link {
+    type Error;
+
+    fn send_frame(&mut self, frame: &Frame<&Packet>) -> Result<(), Self::Error>;
+    // Returns an error if the received frame is corrupted or the receive
+    // buffer overflowed, i.e. if a frame was lost.
+    fn recv_frame(&mut self) -> Result<Option<Frame<Packet>>, Self::Error>;
+    fn now_us(&mut self) -> u64;
+    // Called while waiting for an acknowledgement, e.g. to let other threads run.
+    fn wait(&mut self) {}
+}
+
+pub struct State {
+    enabled: bool,
+    tx_seq:  u8,
+    rx_seq:  Option<u8>,
+    pending: [Option<Packet>; PENDING_MAX],
+    pending_len: usize,
+    pub retransmissions: u32
+}
+
+impl State {
+    pub const fn new() -> State {
+        State {
+            enabled: false,
+            tx_seq:  0,
+            rx_seq:  None,
+            pending: [None, None, None, None],
+            pending_len: 0,
+            retransmissions: 0
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Also forgets the sequence numbers, as when the link goes down.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.tx_seq = 0;
+        self.rx_seq = None;
+        while self.take_pending().is_some() {}
+    }
+
+    fn take_pending(&mut self) -> Option<Packet> {
+        if self.pending_len == 0 {
+            return None
+        }
+        let packet = self.pending[0].take();
+        for i in 1..self.pending_len {
+            self.pending.swap(i - 1, i);
+        }
+        self.pending_len -= 1;
+        packet
+    }
+
+    // Acknowledges sequenced frames, and returns the packet unless it is a
+    // retransmission of the last one delivered.
+    fn accept<L: Link>(&mut self, link: &mut L, frame: Frame<Packet>) -> Result<Option<Packet>, L::Error> {
+        match frame {
+            Frame::Plain(packet) => Ok(Some(packet)),
+            Frame::Sequenced { seq, packet } => {
+                link.send_frame(&Frame::Ack { seq: seq })?;
+                if self.rx_seq == Some(seq) {
+                    Ok(None)
+                } else {
+                    self.rx_seq = Some(seq);
+                    Ok(Some(packet))
+                }
+            },
+            Frame::Ack { .. } => Ok(None)
+        }
+    }
+
+    pub fn send<L: Link>(&mut self, link: &mut L, packet: &Packet) -> Result<(), Error<L::Error>> {
+        if !self.enabled {
+            return Ok(link.send_frame(&Frame::Plain(packet))?)
+        }
+
+        let seq = self.tx_seq;
+        self.tx_seq = seq.wrapping_add(1);
+        for attempt in 0..MAX_RETRANSMISSIONS + 1 {
+            if attempt > 0 {
+                self.retransmissions += 1;
+            }
+            link.send_frame(&Frame::Sequenced { seq: seq, packet: packet })?;
+
+            let deadline = link.now_us() + ACK_TIMEOUT_US;
+            while link.now_us() < deadline {
+                match link.recv_frame() {
+                    Ok(Some(Frame::Ack { seq: acked })) => {
+                        if acked == seq {
+                            return Ok(())
+                        }
+                    },
+                    Ok(Some(frame)) => {
+                        let room = PENDING_MAX - self.pending_len;
+                        let packet = match frame {
+                            // Unacknowledged frames that cannot be held are
+                            // retransmitted by the peer.
+                            Frame::Sequenced { .. } if room <= 1 => None,
+                            Frame::Plain(_) if room == 0 => None,
+                            frame => self.accept(link, frame)?
+                        };
+                        if let Some(packet) = packet {
+                            self.pending[self.pending_len] = Some(packet);
+                            self.pending_len += 1;
+                        }
+                    },
+                    Ok(None) => link.wait(),
+                    // Lost frames are retransmitted, by either side.
+                    Err(_) => ()
+                }
+            }
+        }
+        Err(Error::NotAcknowledged)
+    }
+
+    pub fn recv<L: Link>(&mut self, link: &mut L) -> Result<Option<Packet>, L::Error> {
+        if let Some(packet) = self.take_pending() {
+            return Ok(Some(packet))
+        }
+        match link.recv_frame()? {
+            Some(frame) => self.accept(link, frame),
+            None => Ok(None)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoopbackError {
+    Corrupted,
+    Overflow,
+    Malformed
+}
+
+// Model of the aux buffers of a link looped back onto itself, to exercise
+// `State` on a host. Each call to `now_us` advances time by one
+// microsecond; `corrupt` makes the next frames arrive corrupted.
+pub struct Loopback {
+    buffer:  [u8; 1024],
+    length:  Option<usize>,
+    error:   Option<LoopbackError>,
+    time_us: u64,
+    pub corrupt: u32
+}
+
+impl Loopback {
+    pub fn new() -> Loopback {
+        Loopback {
+            buffer:  [0; 1024],
+            length:  None,
+            error:   None,
+            time_us: 0,
+            corrupt: 0
+        }
+    }
+}
+
+impl Link for Loopback {
+    type Error = LoopbackError;
+
+    fn send_frame(&mut self, frame: &Frame<&Packet>) -> Result<(), LoopbackError> {
+        if self.length.is_some() {
+            self.error = Some(LoopbackError::Overflow);
+            return Ok(())
+        }
+        if self.corrupt > 0 {
+            self.corrupt -= 1;
+            self.error = Some(LoopbackError::Corrupted);
+            return Ok(())
+        }
+        let mut writer = Cursor::new(&mut self.buffer[..]);
+        frame.write_to(&mut writer).map_err(|_| LoopbackError::Overflow)?;
+        self.length = Some(writer.position());
+        Ok(())
+    }
+
+    fn recv_frame(&mut self) -> Result<Option<Frame<Packet>>, LoopbackError> {
+        if let Some(error) = self.error.take() {
+            return Err(error)
+        }
+        match self.length.take() {
+            Some(length) => {
+                let mut reader = Cursor::new(&self.buffer[..length]);
+                Frame::<Packet>::read_from(&mut reader)
+                    .map(Some)
+                    .map_err(|_| LoopbackError::Malformed)
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn now_us(&mut self) -> u64 {
+        self.time_us += 1;
+        self.time_us
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> State {
+        let mut state = State::new();
+        state.set_enabled(true);
+        state
+    }
+
+    #[test]
+    fn acknowledged() {
+        let mut link = Loopback::new();
+        let mut state = enabled();
+        // The frame is received by the sender itself, which acknowledges it.
+        state.send(&mut link, &Packet::EchoRequest).unwrap();
+        assert_eq!(state.retransmissions, 0);
+        assert_eq!(state.recv(&mut link), Ok(Some(Packet::EchoRequest)));
+        assert_eq!(state.recv(&mut link), Ok(None));
+    }
+
+    #[test]
+    fn retransmitted_after_corruption() {
+        let mut link = Loopback::new();
+        let mut state = enabled();
+        link.corrupt = 1;
+        state.send(&mut link, &Packet::EchoRequest).unwrap();
+        assert_eq!(state.retransmissions, 1);
+        assert_eq!(state.recv(&mut link), Ok(Some(Packet::EchoRequest)));
+        assert_eq!(state.recv(&mut link), Ok(None));
+    }
+
+    #[test]
+    fn duplicates_suppressed() {
+        let mut link = Loopback::new();
+        let mut state = enabled();
+        let frame = Frame::Sequenced { seq: 7, packet: &Packet::EchoRequest };
+
+        link.send_frame(&frame).unwrap();
+        assert_eq!(state.recv(&mut link), Ok(Some(Packet::EchoRequest)));
+        assert_eq!(link.recv_frame(), Ok(Some(Frame::Ack { seq: 7 })));
+
+        // e.g. after the acknowledgement was lost
+        link.send_frame(&frame).unwrap();
+        assert_eq!(state.recv(&mut link), Ok(None));
+        assert_eq!(link.recv_frame(), Ok(Some(Frame::Ack { seq: 7 })));
+
+        link.send_frame(&Frame::Sequenced { seq: 8, packet: &Packet::ResetAck }).unwrap();
+        assert_eq!(state.recv(&mut link), Ok(Some(Packet::ResetAck)));
+    }
+
+    #[test]
+    fn peer_without_sequencing() {
+        let mut link = Loopback::new();
+
+        // Sequencing is only enabled once the peer has answered an AuxSequencingRequest.
+        let mut state = State::new();
+        state.send(&mut link, &Packet::EchoRequest).unwrap();
+        assert_eq!(link.recv_frame(), Ok(Some(Frame::Plain(Packet::EchoRequest))));
+
+        // Plain frames are delivered without acknowledgement.
+        let mut state = enabled();
+        link.send_frame(&Frame::Plain(&Packet::ResetAck)).unwrap();
+        assert_eq!(state.recv(&mut link), Ok(Some(Packet::ResetAck)));
+        assert_eq!(link.recv_frame(), Ok(None));
+    }
+
+    // Delivers a plain frame from the peer before the frames looped back.
+    struct PlainFirst {
+        link:   Loopback,
+        packet: Option<Packet>
+    }
+
+    impl Link for PlainFirst {
+        type Error = LoopbackError;
+
+        fn send_frame(&mut self, frame: &Frame<&Packet>) -> Result<(), LoopbackError> {
+            self.link.send_frame(frame)
+        }
+
+        fn recv_frame(&mut self) -> Result<Option<Frame<Packet>>, LoopbackError> {
+            match self.packet.take() {
+                Some(packet) => Ok(Some(Frame::Plain(packet))),
+                None => self.link.recv_frame()
+            }
+        }
+
+        fn now_us(&mut self) -> u64 {
+            self.link.now_us()
+        }
+    }
+
+    #[test]
+    fn plain_frame_kept_while_sending() {
+        let mut link = PlainFirst { link: Loopback::new(), packet: Some(Packet::ResetAck) };
+        let mut state = enabled();
+        state.send(&mut link, &Packet::EchoRequest).unwrap();
+        assert_eq!(state.recv(&mut link), Ok(Some(Packet::ResetAck)));
+        assert_eq!(state.recv(&mut link), Ok(Some(Packet::EchoRequest)));
+    }
+
+    #[test]
+    fn not_acknowledged() {
+        let mut link = Loopback::new();
+        let mut state = enabled();
+        link.corrupt = MAX_RETRANSMISSIONS + 1;
+        match state.send(&mut link, &Packet::EchoRequest) {
+            Err(Error::NotAcknowledged) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+        assert_eq!(state.retransmissions, MAX_RETRANSMISSIONS);
+        assert_eq!(state.recv(&mut link), Ok(None));
+    }
+}
@@ -18,6 +18,8 @@ extern crate eh;
 // Internal protocols.
 pub mod kernel_proto;
 pub mod drtioaux_proto;
+pub mod drtioaux_reliable;
+pub mod drtio_routing_proto;
 
 // External protocols.
 #[cfg(feature = "alloc")]
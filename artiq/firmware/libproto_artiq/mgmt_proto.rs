@@ -4,6 +4,7 @@ use alloc::{vec::Vec, string::String};
 use log;
 
 use io::{Read, ProtoRead, Write, ProtoWrite, Error as IoError, ReadStringError};
+use drtioaux_proto::LinkStats;
 
 #[derive(Fail, Debug)]
 pub enum Error<T> {
@@ -58,10 +59,33 @@ pub enum Request {
     #[cfg(feature = "log")]
     SetUartLogFilter(log::LevelFilter),
 
-    ConfigRead   { key: String },
-    ConfigWrite  { key: String, value: Vec<u8> },
-    ConfigRemove { key: String },
+    ConfigRead   { destination: u8, key: String },
+    ConfigWrite  { destination: u8, key: String, value: Vec<u8> },
+    ConfigRemove { destination: u8, key: String },
     ConfigErase,
+    ConfigList,
+    ConfigDump,
+    ConfigRestore { entries: Vec<(String, Vec<u8>)> },
+    ConfigStats,
+
+    DmaTraceList,
+    DmaTraceExport { name: String },
+    DmaTraceImport { name: String, duration: u64, trace: Vec<u8> },
+    DmaTraceDelete { name: String },
+
+    DmaList,
+    DmaErase { name: String },
+    DmaClear,
+    CacheList,
+    CacheErase { key: String },
+    CacheClear,
+
+    DrtioLinkStats { destination: u8 },
+    DrtioStatus { destination: u8 },
+    DrtioFirmwareUpdate { destination: u8, image: Vec<u8> },
+    DrtioReboot { destination: u8 },
+    DrtioTopology,
+    DrtioSetRoutingTable { table: Vec<u8> },
 
     Reboot,
 
@@ -76,6 +100,28 @@ pub enum Reply<'a> {
     LogContent(&'a str),
 
     ConfigData(&'a [u8]),
+    ConfigKeys(&'a [String]),
+    ConfigDump(&'a [(String, Vec<u8>)]),
+    ConfigRejected(&'a str),
+    ConfigStats {
+        capacity:     usize,
+        used:         usize,
+        live_bytes:   usize,
+        live_records: usize,
+        dead_records: usize,
+        corruption:   Option<usize>
+    },
+
+    DmaTraceList(&'a [(String, u64, usize)]),
+    DmaTrace { duration: u64, trace: &'a [u8] },
+
+    DmaList(&'a [(String, usize, u64, bool)]),
+    CacheList(&'a [(String, usize, bool)]),
+
+    DrtioLinkStats(&'a [LinkStats]),
+    DrtioStatus(&'a str),
+    // Destinations and the hops to each of them.
+    DrtioTopology(&'a [(u8, Vec<u8>)]),
 
     RebootImminent,
 }
@@ -108,16 +154,89 @@ impl Request {
             6 => Request::SetUartLogFilter(read_log_level_filter(reader)?),
 
             12 => Request::ConfigRead {
-                key: reader.read_string()?
+                destination: 0,
+                key:         reader.read_string()?
             },
             13 => Request::ConfigWrite {
-                key:   reader.read_string()?,
-                value: reader.read_bytes()?
+                destination: 0,
+                key:         reader.read_string()?,
+                value:       reader.read_bytes()?
             },
             14 => Request::ConfigRemove {
-                key: reader.read_string()?
+                destination: 0,
+                key:         reader.read_string()?
             },
             15 => Request::ConfigErase,
+            16 => Request::ConfigList,
+            17 => Request::ConfigDump,
+            18 => {
+                let count = reader.read_u32()?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    let key = reader.read_string()?;
+                    let value = reader.read_bytes()?;
+                    entries.push((key, value));
+                }
+                Request::ConfigRestore { entries: entries }
+            },
+            19 => Request::ConfigStats,
+
+            20 => Request::DmaTraceList,
+            21 => Request::DmaTraceExport {
+                name: reader.read_string()?
+            },
+            22 => Request::DmaTraceImport {
+                name:     reader.read_string()?,
+                duration: reader.read_u64()?,
+                trace:    reader.read_bytes()?
+            },
+            23 => Request::DmaTraceDelete {
+                name: reader.read_string()?
+            },
+
+            24 => Request::DmaList,
+            25 => Request::DmaErase {
+                name: reader.read_string()?
+            },
+            26 => Request::DmaClear,
+            27 => Request::CacheList,
+            28 => Request::CacheErase {
+                key: reader.read_string()?
+            },
+            29 => Request::CacheClear,
+
+            30 => Request::DrtioLinkStats {
+                destination: reader.read_u8()?
+            },
+            31 => Request::DrtioStatus {
+                destination: reader.read_u8()?
+            },
+            32 => Request::DrtioFirmwareUpdate {
+                destination: reader.read_u8()?,
+                image:       reader.read_bytes()?
+            },
+            33 => Request::DrtioReboot {
+                destination: reader.read_u8()?
+            },
+            34 => Request::DrtioTopology,
+            35 => Request::DrtioSetRoutingTable {
+                table: reader.read_bytes()?
+            },
+            // Config requests to a DRTIO destination; 12 to 14 address the
+            // local config, as sent by older clients.
+            36 => Request::ConfigRead {
+                destination: reader.read_u8()?,
+                key:         reader.read_string()?
+            },
+            37 => Request::ConfigWrite {
+                destination: reader.read_u8()?,
+                key:         reader.read_string()?,
+                value:       reader.read_bytes()?
+            },
+            38 => Request::ConfigRemove {
+                destination: reader.read_u8()?,
+                key:         reader.read_string()?
+            },
 
             5 => Request::Reboot,
 
@@ -153,6 +272,91 @@ impl<'a> Reply<'a> {
                 writer.write_u8(7)?;
                 writer.write_bytes(bytes)?;
             },
+            Reply::ConfigKeys(keys) => {
+                writer.write_u8(12)?;
+                writer.write_u32(keys.len() as u32)?;
+                for key in keys.iter() {
+                    writer.write_string(key)?;
+                }
+            },
+            Reply::ConfigDump(entries) => {
+                writer.write_u8(13)?;
+                writer.write_u32(entries.len() as u32)?;
+                for &(ref key, ref value) in entries.iter() {
+                    writer.write_string(key)?;
+                    writer.write_bytes(value)?;
+                }
+            },
+            Reply::ConfigRejected(message) => {
+                writer.write_u8(15)?;
+                writer.write_string(message)?;
+            },
+            Reply::ConfigStats { capacity, used, live_bytes, live_records, dead_records,
+                                 corruption } => {
+                writer.write_u8(14)?;
+                writer.write_u32(capacity as u32)?;
+                writer.write_u32(used as u32)?;
+                writer.write_u32(live_bytes as u32)?;
+                writer.write_u32(live_records as u32)?;
+                writer.write_u32(dead_records as u32)?;
+                writer.write_bool(corruption.is_some())?;
+                writer.write_u32(corruption.unwrap_or(0) as u32)?;
+            },
+
+            Reply::DmaTraceList(traces) => {
+                writer.write_u8(8)?;
+                writer.write_u32(traces.len() as u32)?;
+                for &(ref name, duration, size) in traces.iter() {
+                    writer.write_string(name)?;
+                    writer.write_u64(duration)?;
+                    writer.write_u32(size as u32)?;
+                }
+            },
+            Reply::DmaTrace { duration, trace } => {
+                writer.write_u8(9)?;
+                writer.write_u64(duration)?;
+                writer.write_bytes(trace)?;
+            },
+
+            Reply::DmaList(traces) => {
+                writer.write_u8(10)?;
+                writer.write_u32(traces.len() as u32)?;
+                for &(ref name, size, duration, borrowed) in traces.iter() {
+                    writer.write_string(name)?;
+                    writer.write_u32(size as u32)?;
+                    writer.write_u64(duration)?;
+                    writer.write_bool(borrowed)?;
+                }
+            },
+            Reply::CacheList(entries) => {
+                writer.write_u8(11)?;
+                writer.write_u32(entries.len() as u32)?;
+                for &(ref key, length, borrowed) in entries.iter() {
+                    writer.write_string(key)?;
+                    writer.write_u32(length as u32)?;
+                    writer.write_bool(borrowed)?;
+                }
+            },
+
+            Reply::DrtioLinkStats(links) => {
+                writer.write_u8(16)?;
+                writer.write_u32(links.len() as u32)?;
+                for stats in links.iter() {
+                    stats.write_to(writer)?;
+                }
+            },
+            Reply::DrtioStatus(status) => {
+                writer.write_u8(17)?;
+                writer.write_string(status)?;
+            },
+            Reply::DrtioTopology(paths) => {
+                writer.write_u8(18)?;
+                writer.write_u32(paths.len() as u32)?;
+                for &(destination, ref hops) in paths.iter() {
+                    writer.write_u8(destination)?;
+                    writer.write_bytes(hops)?;
+                }
+            },
 
             Reply::RebootImminent => {
                 writer.write_u8(3)?;
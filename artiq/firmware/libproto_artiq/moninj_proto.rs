@@ -35,13 +35,28 @@ pub enum HostMessage {
     MonitorProbe { enable: bool, channel: u32, probe: u8 },
     MonitorInjection { enable: bool, channel: u32, overrd: u8 },
     Inject { channel: u32, overrd: u8, value: u8 },
-    GetInjectionStatus { channel: u32, overrd: u8 }
+    GetInjectionStatus { channel: u32, overrd: u8 },
+    SetPollInterval { interval_ms: u32 },
+    BatchUpdates { enable: bool },
+    SetLease { enable: bool, timeout_ms: u32 },
+    ReleaseAllOverrides,
+    Snapshot { destination: u8 },
+    SetHistoryInterval { interval_ms: u32 },
+    GetHistory { channel: u32, probe: u8 }
 }
 
 #[derive(Debug)]
-pub enum DeviceMessage {
+pub enum DeviceMessage<'a> {
     MonitorStatus { channel: u32, probe: u8, value: u64 },
-    InjectionStatus { channel: u32, overrd: u8, value: u8 }
+    InjectionStatus { channel: u32, overrd: u8, value: u8 },
+    Updates { monitor: &'a [(u32, u8, u64)], injection: &'a [(u32, u8, u8)] },
+    // `probes` values and `overrides` statuses for each channel, in channel order.
+    Snapshot { destination: u8, channels: u16, probes: u8, overrides: u8,
+               probe_values: &'a [u64], injection_values: &'a [u8] },
+    // (RTIO counter, value) pairs, oldest first.
+    History { channel: u32, probe: u8, samples: &'a [(u64, u64)] },
+    // An injection made by another connection, identified by its remote endpoint.
+    InjectionChanged { channel: u32, overrd: u8, value: u8, origin: &'a str }
 }
 
 impl HostMessage {
@@ -68,12 +83,33 @@ impl HostMessage {
                 channel: reader.read_u32()?,
                 overrd: reader.read_u8()?
             },
+            4 => HostMessage::SetPollInterval {
+                interval_ms: reader.read_u32()?
+            },
+            5 => HostMessage::BatchUpdates {
+                enable: if reader.read_u8()? == 0 { false } else { true }
+            },
+            6 => HostMessage::SetLease {
+                enable: if reader.read_u8()? == 0 { false } else { true },
+                timeout_ms: reader.read_u32()?
+            },
+            7 => HostMessage::ReleaseAllOverrides,
+            8 => HostMessage::Snapshot {
+                destination: reader.read_u8()?
+            },
+            9 => HostMessage::SetHistoryInterval {
+                interval_ms: reader.read_u32()?
+            },
+            10 => HostMessage::GetHistory {
+                channel: reader.read_u32()?,
+                probe: reader.read_u8()?
+            },
             ty => return Err(Error::UnknownPacket(ty))
         })
     }
 }
 
-impl DeviceMessage {
+impl<'a> DeviceMessage<'a> {
     pub fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
         where W: Write + ?Sized
     {
@@ -89,6 +125,52 @@ impl DeviceMessage {
                 writer.write_u32(channel)?;
                 writer.write_u8(overrd)?;
                 writer.write_u8(value)?;
+            },
+            DeviceMessage::Updates { monitor, injection } => {
+                writer.write_u8(2)?;
+                writer.write_u32(monitor.len() as u32)?;
+                for &(channel, probe, value) in monitor.iter() {
+                    writer.write_u32(channel)?;
+                    writer.write_u8(probe)?;
+                    writer.write_u64(value)?;
+                }
+                writer.write_u32(injection.len() as u32)?;
+                for &(channel, overrd, value) in injection.iter() {
+                    writer.write_u32(channel)?;
+                    writer.write_u8(overrd)?;
+                    writer.write_u8(value)?;
+                }
+            },
+            DeviceMessage::Snapshot { destination, channels, probes, overrides,
+                                      probe_values, injection_values } => {
+                writer.write_u8(3)?;
+                writer.write_u8(destination)?;
+                writer.write_u16(channels)?;
+                writer.write_u8(probes)?;
+                writer.write_u8(overrides)?;
+                for &value in probe_values.iter() {
+                    writer.write_u64(value)?;
+                }
+                for &value in injection_values.iter() {
+                    writer.write_u8(value)?;
+                }
+            },
+            DeviceMessage::History { channel, probe, samples } => {
+                writer.write_u8(4)?;
+                writer.write_u32(channel)?;
+                writer.write_u8(probe)?;
+                writer.write_u32(samples.len() as u32)?;
+                for &(timestamp, value) in samples.iter() {
+                    writer.write_u64(timestamp)?;
+                    writer.write_u64(value)?;
+                }
+            },
+            DeviceMessage::InjectionChanged { channel, overrd, value, origin } => {
+                writer.write_u8(5)?;
+                writer.write_u32(channel)?;
+                writer.write_u8(overrd)?;
+                writer.write_u8(value)?;
+                writer.write_string(origin)?;
             }
         }
         Ok(())
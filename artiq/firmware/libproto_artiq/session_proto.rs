@@ -63,12 +63,68 @@ fn write_sync<W>(writer: &mut W) -> Result<(), IoError<W::WriteError>>
     writer.write_all(&[0x5a; 4])
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelState {
+    Absent,
+    Loaded,
+    Running,
+    RpcWait
+}
+
+impl KernelState {
+    fn to_u8(self) -> u8 {
+        match self {
+            KernelState::Absent  => 0,
+            KernelState::Loaded  => 1,
+            KernelState::Running => 2,
+            KernelState::RpcWait => 3
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtioEventKind {
+    DestinationUp,
+    DestinationDown,
+    // `errors` has the bits of the kernel finished message (collision, busy,
+    // sequence error), `channel` is the channel involved.
+    AsyncError { errors: u8, channel: u16 }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RtioEvent {
+    // Time since the core device started.
+    pub timestamp_ms: u64,
+    pub destination:  u8,
+    pub kind:         RtioEventKind
+}
+
+impl RtioEvent {
+    fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        let (kind, errors, channel) = match self.kind {
+            RtioEventKind::DestinationUp => (0, 0, 0),
+            RtioEventKind::DestinationDown => (1, 0, 0),
+            RtioEventKind::AsyncError { errors, channel } => (2, errors, channel)
+        };
+        writer.write_u64(self.timestamp_ms)?;
+        writer.write_u8(self.destination)?;
+        writer.write_u8(kind)?;
+        writer.write_u8(errors)?;
+        writer.write_u32(channel as u32)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum Request {
     SystemInfo,
 
     LoadKernel(Vec<u8>),
     RunKernel,
+    RunKernelWatchdog { timeout_ms: u32 },
+    AbortKernel,
 
     RpcReply { tag: Vec<u8> },
     RpcException {
@@ -96,6 +152,9 @@ pub enum Reply<'a> {
         async_errors: u8
     },
     KernelStartupFailed,
+    KernelAborted {
+        state: KernelState
+    },
     KernelException {
         exceptions: &'a [Option<Exception<'a>>],
         stack_pointers: &'a [StackPointerBacktrace],
@@ -106,6 +165,18 @@ pub enum Reply<'a> {
     RpcRequest { async: bool },
 
     ClockFailure,
+
+    WatchdogExpired {
+        timeout_ms: u32,
+        last_message: &'a str
+    },
+
+    // Sent while a kernel runs; `dropped` events were lost because the
+    // host did not take them in time.
+    RtioEvents {
+        events: &'a [RtioEvent],
+        dropped: u32
+    },
 }
 
 impl Request {
@@ -118,6 +189,10 @@ impl Request {
 
             5  => Request::LoadKernel(reader.read_bytes()?),
             6  => Request::RunKernel,
+            9  => Request::AbortKernel,
+            10 => Request::RunKernelWatchdog {
+                timeout_ms: reader.read_u32()?
+            },
 
             7  => Request::RpcReply {
                 tag: reader.read_bytes()?
@@ -179,6 +254,10 @@ impl<'a> Reply<'a> {
             Reply::KernelStartupFailed => {
                 writer.write_u8(8)?;
             },
+            Reply::KernelAborted { state } => {
+                writer.write_u8(16)?;
+                writer.write_u8(state.to_u8())?;
+            },
             Reply::KernelException {
                 exceptions,
                 stack_pointers,
@@ -222,6 +301,21 @@ impl<'a> Reply<'a> {
             Reply::ClockFailure => {
                 writer.write_u8(15)?;
             },
+
+            Reply::WatchdogExpired { timeout_ms, last_message } => {
+                writer.write_u8(17)?;
+                writer.write_u32(timeout_ms)?;
+                writer.write_string(last_message)?;
+            },
+
+            Reply::RtioEvents { events, dropped } => {
+                writer.write_u8(18)?;
+                writer.write_u32(dropped)?;
+                writer.write_u32(events.len() as u32)?;
+                for event in events.iter() {
+                    event.write_to(writer)?;
+                }
+            },
         }
         Ok(())
     }
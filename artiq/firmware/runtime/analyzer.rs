@@ -1,9 +1,29 @@
+use alloc::{vec::Vec, collections::vec_deque::VecDeque};
+use byteorder::{ByteOrder, BigEndian};
+
 use io::{Write, Error as IoError};
-use board_misoc::{csr, cache};
+use board_misoc::{csr, cache, clock};
 use sched::{Io, TcpListener, TcpStream, Error as SchedError};
 use analyzer_proto::*;
 
 const BUFFER_SIZE: usize = 512 * 1024;
+const MESSAGE_SIZE: usize = 32;
+
+const MESSAGE_TYPE_OUTPUT: u32 = 0b00;
+const MESSAGE_TYPE_INPUT: u32 = 0b01;
+const MESSAGE_TYPE_STOPPED: u32 = 0b11;
+
+// Legacy clients never send a request and simply wait for the dump.
+const REQUEST_TIMEOUT_MS: u64 = 100;
+
+const POLL_MS: u64 = 10;
+const CHUNK_SIZE: usize = 16 * 1024;
+// Data closer than this to the DMA write pointer is considered lost, so that
+// records are not overwritten while they are being copied out.
+const GUARD_SIZE: usize = 64 * 1024;
+
+// Maximum number of messages retained by a triggered or filtered capture.
+const CAPTURE_DEPTH: usize = BUFFER_SIZE / MESSAGE_SIZE;
 
 #[repr(align(64))]
 struct Buffer {
@@ -14,6 +34,12 @@ static mut BUFFER: Buffer = Buffer {
     data: [0; BUFFER_SIZE]
 };
 
+impl From<SchedError> for Error<SchedError> {
+    fn from(value: SchedError) -> Error<SchedError> {
+        Error::Io(IoError::Other(value))
+    }
+}
+
 fn arm() {
     unsafe {
         let base_addr = &mut BUFFER.data[0] as *mut _ as usize;
@@ -35,7 +61,314 @@ fn disarm() {
     }
 }
 
-fn worker(stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
+// The byte count is a multi-word CSR; when the DMA engine is running it can
+// change between the reads of its halves.
+fn running_byte_count() -> u64 {
+    unsafe {
+        let mut byte_count = csr::rtio_analyzer::dma_byte_count_read();
+        loop {
+            let next_byte_count = csr::rtio_analyzer::dma_byte_count_read();
+            if next_byte_count == byte_count {
+                return byte_count
+            }
+            byte_count = next_byte_count
+        }
+    }
+}
+
+fn take_encoder_overflow() -> bool {
+    unsafe {
+        let overflow = csr::rtio_analyzer::message_encoder_overflow_read() != 0;
+        if overflow {
+            csr::rtio_analyzer::message_encoder_overflow_reset_write(1)
+        }
+        overflow
+    }
+}
+
+fn message_type(message: &[u8]) -> u32 {
+    BigEndian::read_u32(&message[28..32]) & 0b11
+}
+
+fn message_channel(message: &[u8]) -> Option<u32> {
+    match message_type(message) {
+        MESSAGE_TYPE_STOPPED => None,
+        _ => Some(BigEndian::read_u32(&message[28..32]) >> 2)
+    }
+}
+
+fn message_timestamp(message: &[u8]) -> Option<u64> {
+    match message_type(message) {
+        MESSAGE_TYPE_OUTPUT | MESSAGE_TYPE_INPUT => Some(BigEndian::read_u64(&message[20..28])),
+        _ => None
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    channels: Vec<u32>,
+    start: Trigger,
+    stop: Trigger,
+    pre_trigger: u32,
+    post_trigger: u32
+}
+
+impl Config {
+    fn new() -> Config {
+        Config {
+            channels: Vec::new(),
+            start: Trigger::None,
+            stop: Trigger::None,
+            pre_trigger: 0,
+            post_trigger: 0
+        }
+    }
+
+    // Without a filter or triggers, the raw ring buffer is used as is.
+    fn is_passthrough(&self) -> bool {
+        self.channels.is_empty() && self.start == Trigger::None && self.stop == Trigger::None
+    }
+
+    fn accepts(&self, message: &[u8]) -> bool {
+        if self.channels.is_empty() {
+            return true
+        }
+        match message_channel(message) {
+            Some(channel) => self.channels.binary_search(&channel).is_ok(),
+            None => true
+        }
+    }
+}
+
+fn trigger_fires(trigger: Trigger, message: &[u8]) -> bool {
+    match trigger {
+        Trigger::None => false,
+        Trigger::Channel(channel) => message_channel(message) == Some(channel),
+        Trigger::Timestamp(timestamp) => message_timestamp(message).map_or(false, |t| t >= timestamp)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SelectorState {
+    WaitStart,
+    Started,
+    PostTrigger { remaining: u32 },
+    Done
+}
+
+// The messages retained by a selector or a capture are allocated up front, so
+// that a lack of memory is reported when arming instead of aborting the runtime
+// during the capture.
+fn reserve_messages(count: usize) -> Option<VecDeque<[u8; MESSAGE_SIZE]>> {
+    let mut messages = VecDeque::new();
+    messages.try_reserve_exact(count).ok()?;
+    Some(messages)
+}
+
+// Applies the channel filter and the triggers to the sequence of recorded messages.
+// Triggers are evaluated on every message, including those rejected by the filter.
+struct Selector {
+    config: Config,
+    state: SelectorState,
+    pre_trigger: VecDeque<[u8; MESSAGE_SIZE]>
+}
+
+impl Selector {
+    fn new(config: &Config) -> Option<Selector> {
+        Some(Selector {
+            config: config.clone(),
+            state: Selector::initial_state(config),
+            pre_trigger: reserve_messages(config.pre_trigger as usize)?
+        })
+    }
+
+    fn initial_state(config: &Config) -> SelectorState {
+        if config.start == Trigger::None {
+            SelectorState::Started
+        } else {
+            SelectorState::WaitStart
+        }
+    }
+
+    fn restart(&mut self) {
+        self.state = Selector::initial_state(&self.config);
+        self.pre_trigger.clear()
+    }
+
+    fn done(&self) -> bool {
+        self.state == SelectorState::Done
+    }
+
+    fn stop(&mut self) {
+        self.state = match self.config.post_trigger {
+            0 => SelectorState::Done,
+            remaining => SelectorState::PostTrigger { remaining: remaining }
+        }
+    }
+
+    fn feed<F: FnMut(&[u8])>(&mut self, message: &[u8], mut emit: F) {
+        let accepted = self.config.accepts(message);
+        match self.state {
+            SelectorState::WaitStart => {
+                if trigger_fires(self.config.start, message) {
+                    for message in self.pre_trigger.drain(..) {
+                        emit(&message)
+                    }
+                    if accepted {
+                        emit(message)
+                    }
+                    self.state = SelectorState::Started;
+                    if trigger_fires(self.config.stop, message) {
+                        self.stop()
+                    }
+                } else if accepted && self.config.pre_trigger > 0 {
+                    if self.pre_trigger.len() == self.config.pre_trigger as usize {
+                        self.pre_trigger.pop_front();
+                    }
+                    let mut copy = [0; MESSAGE_SIZE];
+                    copy.copy_from_slice(message);
+                    self.pre_trigger.push_back(copy)
+                }
+            }
+            SelectorState::Started => {
+                if accepted {
+                    emit(message)
+                }
+                if trigger_fires(self.config.stop, message) {
+                    self.stop()
+                }
+            }
+            SelectorState::PostTrigger { remaining } => {
+                if accepted {
+                    emit(message);
+                    self.state = match remaining - 1 {
+                        0 => SelectorState::Done,
+                        remaining => SelectorState::PostTrigger { remaining: remaining }
+                    }
+                }
+            }
+            SelectorState::Done => ()
+        }
+    }
+}
+
+// Reads records from the ring buffer while the DMA engine may be running.
+struct RingReader {
+    byte_count: u64
+}
+
+impl RingReader {
+    fn new() -> RingReader {
+        RingReader { byte_count: 0 }
+    }
+
+    // Returns the number of bytes that were overwritten before they could be read,
+    // and the next contiguous chunk of unread records, which may be empty.
+    fn next_chunk(&mut self) -> (u64, &'static [u8]) {
+        let total_byte_count = running_byte_count();
+
+        let mut lost_bytes = 0;
+        let readable = (BUFFER_SIZE - GUARD_SIZE) as u64;
+        if total_byte_count - self.byte_count > readable {
+            lost_bytes = total_byte_count - readable - self.byte_count;
+            self.byte_count = total_byte_count - readable;
+        }
+        if total_byte_count == self.byte_count {
+            return (lost_bytes, &[])
+        }
+
+        // The DMA engine writes behind the back of the CPU caches.
+        cache::flush_cpu_dcache();
+        cache::flush_l2_cache();
+
+        let pointer = (self.byte_count % BUFFER_SIZE as u64) as usize;
+        let mut length = (total_byte_count - self.byte_count) as usize;
+        if length > CHUNK_SIZE {
+            length = CHUNK_SIZE
+        }
+        if pointer + length > BUFFER_SIZE {
+            // Stop at the end of the buffer; the rest is returned by the next call.
+            length = BUFFER_SIZE - pointer
+        }
+        self.byte_count += length as u64;
+        (lost_bytes, unsafe { &BUFFER.data[pointer..pointer + length] })
+    }
+}
+
+// Messages selected in the background while no client is connected.
+struct Capture {
+    selector: Selector,
+    reader: RingReader,
+    messages: VecDeque<[u8; MESSAGE_SIZE]>,
+    selected_byte_count: u64,
+    overflow_occurred: bool
+}
+
+impl Capture {
+    fn new(config: &Config) -> Option<Capture> {
+        Some(Capture {
+            selector: Selector::new(config)?,
+            reader: RingReader::new(),
+            messages: reserve_messages(CAPTURE_DEPTH)?,
+            selected_byte_count: 0,
+            overflow_occurred: false
+        })
+    }
+
+    // Starts over from a freshly armed buffer, keeping the allocated memory.
+    fn restart(&mut self) {
+        self.selector.restart();
+        self.reader = RingReader::new();
+        self.messages.clear();
+        self.selected_byte_count = 0;
+        self.overflow_occurred = false
+    }
+
+    fn done(&self) -> bool {
+        self.selector.done()
+    }
+
+    fn poll(&mut self) {
+        let Capture { ref mut selector, ref mut reader, ref mut messages,
+                      ref mut selected_byte_count, ref mut overflow_occurred } = *self;
+        if take_encoder_overflow() {
+            *overflow_occurred = true
+        }
+        while !selector.done() {
+            let (lost_bytes, chunk) = reader.next_chunk();
+            if lost_bytes != 0 {
+                *overflow_occurred = true
+            }
+            if chunk.is_empty() {
+                break
+            }
+            for message in chunk.chunks(MESSAGE_SIZE) {
+                selector.feed(message, |message| {
+                    if messages.len() == CAPTURE_DEPTH {
+                        messages.pop_front();
+                    }
+                    let mut copy = [0; MESSAGE_SIZE];
+                    copy.copy_from_slice(message);
+                    messages.push_back(copy);
+                    *selected_byte_count += MESSAGE_SIZE as u64
+                })
+            }
+        }
+    }
+}
+
+fn read_request(io: &Io, stream: &mut TcpStream) -> Result<Option<Request>, Error<SchedError>> {
+    let timeout = clock::get_ms() + REQUEST_TIMEOUT_MS;
+    while !stream.can_recv() {
+        if !stream.may_recv() || clock::get_ms() > timeout {
+            return Ok(None)
+        }
+        io.relinquish()?;
+    }
+    Ok(Some(Request::read_from(stream)?))
+}
+
+fn dump(stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
     let data = unsafe { &BUFFER.data[..] };
     let overflow_occurred = unsafe { csr::rtio_analyzer::message_encoder_overflow_read() != 0 };
     let total_byte_count = unsafe { csr::rtio_analyzer::dma_byte_count_read() };
@@ -47,7 +380,8 @@ fn worker(stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
         sent_bytes: if wraparound { BUFFER_SIZE as u32 } else { total_byte_count as u32 },
         overflow_occurred: overflow_occurred,
         log_channel: csr::CONFIG_RTIO_LOG_CHANNEL as u8,
-        dds_onehot_sel: true  // kept for backward compatibility of analyzer dumps
+        dds_onehot_sel: true,  // kept for backward compatibility of analyzer dumps
+        mode: Mode::OneShot
     };
     debug!("{:?}", header);
 
@@ -63,19 +397,191 @@ fn worker(stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
     Ok(())
 }
 
+fn dump_capture(stream: &mut TcpStream, capture: &Capture) -> Result<(), Error<SchedError>> {
+    let header = Header {
+        total_byte_count: capture.selected_byte_count,
+        sent_bytes: (capture.messages.len() * MESSAGE_SIZE) as u32,
+        overflow_occurred: capture.overflow_occurred,
+        log_channel: csr::CONFIG_RTIO_LOG_CHANNEL as u8,
+        dds_onehot_sel: true,
+        mode: Mode::OneShot
+    };
+    debug!("{:?}", header);
+
+    stream.write_all("e".as_bytes())?;
+    header.write_to(stream)?;
+    for message in capture.messages.iter() {
+        stream.write_all(message)?;
+    }
+
+    Ok(())
+}
+
+fn stream_records(io: &Io, stream: &mut TcpStream, config: &Config) -> Result<(), Error<SchedError>> {
+    let mut selector = if config.is_passthrough() {
+        None
+    } else {
+        match Selector::new(config) {
+            Some(selector) => Some(selector),
+            None => {
+                error!("not enough memory for {} pre-trigger messages", config.pre_trigger);
+                return Ok(())
+            }
+        }
+    };
+
+    // Streaming always starts from a freshly armed buffer, so that the
+    // position of every record in the stream is known.
+    arm();
+
+    let header = Header {
+        total_byte_count: 0,
+        sent_bytes: 0,
+        overflow_occurred: false,
+        log_channel: csr::CONFIG_RTIO_LOG_CHANNEL as u8,
+        dds_onehot_sel: true,
+        mode: Mode::Streaming
+    };
+    debug!("{:?}", header);
+
+    stream.write_all("e".as_bytes())?;
+    header.write_to(stream)?;
+
+    let mut reader = RingReader::new();
+    let mut selected = Vec::new();
+    while stream.may_recv() {
+        let encoder_overflow = take_encoder_overflow();
+        let (lost_bytes, chunk) = reader.next_chunk();
+        if lost_bytes != 0 || encoder_overflow {
+            warn!("analyzer stream overflow, {} bytes lost", lost_bytes);
+            StreamRecord::Overflow {
+                lost_bytes: lost_bytes,
+                encoder_overflow: encoder_overflow
+            }.write_to(stream)?;
+        }
+
+        if chunk.is_empty() {
+            io.sleep(POLL_MS)?;
+            continue
+        }
+
+        match selector {
+            None => StreamRecord::Data(chunk).write_to(stream)?,
+            Some(ref mut selector) => {
+                selected.clear();
+                for message in chunk.chunks(MESSAGE_SIZE) {
+                    selector.feed(message, |message| selected.extend_from_slice(message))
+                }
+                if !selected.is_empty() {
+                    StreamRecord::Data(&selected).write_to(stream)?
+                }
+                if selector.done() {
+                    // The stop trigger has fired and the post-trigger depth has been
+                    // recorded; closing the connection signals the end of the capture.
+                    break
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn worker(io: &Io, stream: &mut TcpStream, config: &mut Config,
+          capture: &mut Option<Capture>) -> Result<(), Error<SchedError>> {
+    // Configuration requests only affect this connection until the analyzer is armed.
+    let mut pending = config.clone();
+
+    let mut request = read_request(io, stream)?;
+    loop {
+        debug!("analyzer<-host {:?}", request);
+        match request {
+            None | Some(Request::Dump) => {
+                disarm();
+                return match *capture {
+                    None => dump(stream),
+                    Some(ref mut capture) => {
+                        capture.poll();
+                        dump_capture(stream, capture)
+                    }
+                }
+            }
+            Some(Request::Stream) => {
+                disarm();
+                return stream_records(io, stream, &pending)
+            }
+
+            Some(Request::SetChannelFilter { channels }) => {
+                pending.channels = channels;
+                pending.channels.sort();
+                pending.channels.dedup();
+            }
+            Some(Request::SetTrigger { start, stop }) => {
+                pending.start = start;
+                pending.stop = stop;
+            }
+            Some(Request::SetTriggerDepth { pre_trigger, post_trigger }) => {
+                // The pre-trigger messages are held on the heap, and no more
+                // than CAPTURE_DEPTH messages can be kept anyway.
+                if pre_trigger as usize > CAPTURE_DEPTH || post_trigger as usize > CAPTURE_DEPTH {
+                    warn!("trigger depth {}/{} limited to {} messages",
+                          pre_trigger, post_trigger, CAPTURE_DEPTH);
+                }
+                pending.pre_trigger = pre_trigger.min(CAPTURE_DEPTH as u32);
+                pending.post_trigger = post_trigger.min(CAPTURE_DEPTH as u32);
+            }
+            Some(Request::Arm) => {
+                // The previous capture is kept if the new one cannot be allocated.
+                if pending.is_passthrough() {
+                    *capture = None
+                } else {
+                    match Capture::new(&pending) {
+                        Some(new_capture) => *capture = Some(new_capture),
+                        None => {
+                            error!("not enough memory to arm analyzer with {:?}", pending);
+                            Reply::OutOfMemory.write_to(stream)?;
+                            return Ok(())
+                        }
+                    }
+                }
+                info!("arming analyzer with {:?}", pending);
+                *config = pending;
+                Reply::Armed.write_to(stream)?;
+                return Ok(())
+            }
+        }
+        request = Some(Request::read_from(stream)?);
+    }
+}
+
 pub fn thread(io: Io) {
     let listener = TcpListener::new(&io, 65535);
     listener.listen(1382).expect("analyzer: cannot listen");
 
+    let mut config = Config::new();
+    let mut capture = None;
+
     loop {
         arm();
 
+        if let Some(ref mut capture) = capture {
+            capture.restart();
+            while !listener.can_accept() {
+                if !capture.done() {
+                    capture.poll();
+                    if capture.done() {
+                        info!("analyzer capture complete");
+                        disarm();
+                    }
+                }
+                io.sleep(POLL_MS).unwrap();
+            }
+        }
+
         let mut stream = listener.accept().expect("analyzer: cannot accept");
         info!("connection from {}", stream.remote_endpoint());
 
-        disarm();
-
-        match worker(&mut stream) {
+        match worker(&io, &mut stream, &mut config, &mut capture) {
             Ok(())   => (),
             Err(err) => error!("analyzer aborted: {}", err)
         }
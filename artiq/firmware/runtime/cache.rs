@@ -80,4 +80,30 @@ impl Cache {
             entry.borrowed = false;
         }
     }
+
+    // Yields the key, length and borrowed status of each entry.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(&'a str, usize, bool)> + 'a {
+        self.entries.iter().map(|(key, entry)| (key.as_str(), entry.data.len(), entry.borrowed))
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), ()> {
+        match self.entries.get(key) {
+            Some(entry) if entry.borrowed => return Err(()),
+            _ => ()
+        }
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    // Returns the number of borrowed entries that were kept.
+    pub fn clear(&mut self) -> usize {
+        let unborrowed = self.entries.iter()
+            .filter(|&(_key, entry)| !entry.borrowed)
+            .map(|(key, _entry)| key.clone())
+            .collect::<Vec<_>>();
+        for key in unborrowed {
+            self.entries.remove(&key);
+        }
+        self.entries.len()
+    }
 }
@@ -0,0 +1,349 @@
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    SpaceExhausted,
+    Truncated { offset: usize },
+    InvalidSize { offset: usize, size: usize },
+    MissingSeparator { offset: usize },
+    ChecksumMismatch { offset: usize },
+    InvalidName { offset: usize },
+    EmptyTrace,
+    UnterminatedTrace,
+    NoFlash,
+    TraceNotFound
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::SpaceExhausted =>
+                write!(f, "space exhausted"),
+            &Error::Truncated { offset } =>
+                write!(f, "truncated record at offset {}", offset),
+            &Error::InvalidSize { offset, size } =>
+                write!(f, "invalid record size {} at offset {}", size, offset),
+            &Error::MissingSeparator { offset } =>
+                write!(f, "missing separator at offset {}", offset),
+            &Error::ChecksumMismatch { offset } =>
+                write!(f, "checksum mismatch in record at offset {}", offset),
+            &Error::InvalidName { offset } =>
+                write!(f, "invalid trace name in record at offset {}", offset),
+            &Error::EmptyTrace =>
+                write!(f, "empty trace"),
+            &Error::UnterminatedTrace =>
+                write!(f, "trace is not terminated"),
+            &Error::NoFlash =>
+                write!(f, "flash memory is not present"),
+            &Error::TraceNotFound =>
+                write!(f, "trace not found")
+        }
+    }
+}
+
+#[cfg(has_spiflash)]
+mod imp {
+    use core::str;
+    use alloc::vec::Vec;
+    use byteorder::{ByteOrder, BigEndian};
+    use crc::{crc32, Hasher32};
+    use board_misoc::{cache, spiflash};
+    use super::Error;
+
+    const ADDR: usize = spiflash::DMA_STORE_ADDRESS;
+    const SIZE: usize = spiflash::DMA_STORE_SIZE;
+
+    // The region is split into two banks. Compaction copies the live records
+    // into the inactive bank and programs its header last, so that the previous
+    // bank stays in use if it is interrupted. The bank header holds a magic and
+    // a generation; the valid bank with the latest generation is active.
+    const BANK_SIZE: usize = SIZE / 2;
+    const BANK_HEADER_SIZE: usize = 8;
+    const BANK_MAGIC: u32 = 0x444d4154; // "DMAT"
+
+    // Record layout: size (including header), CRC32 of the rest of the record,
+    // duration, name, zero separator, trace. Records without a trace mark removals.
+    const HEADER_SIZE: usize = 16;
+
+    fn bank(index: usize) -> &'static [u8] {
+        unsafe {
+            ::core::slice::from_raw_parts((ADDR + index * BANK_SIZE) as *const u8, BANK_SIZE)
+        }
+    }
+
+    fn generation(bank: &[u8]) -> Option<u32> {
+        if BigEndian::read_u32(&bank[0..]) == BANK_MAGIC {
+            Some(BigEndian::read_u32(&bank[4..]))
+        } else {
+            None
+        }
+    }
+
+    fn active_bank() -> Option<(usize, u32)> {
+        match (generation(bank(0)), generation(bank(1))) {
+            (Some(gen0), Some(gen1)) if (gen1.wrapping_sub(gen0) as i32) > 0 =>
+                Some((1, gen1)),
+            (Some(gen0), _) => Some((0, gen0)),
+            (None, Some(gen1)) => Some((1, gen1)),
+            (None, None) => None
+        }
+    }
+
+    // Records of the active bank; empty if the store was never written.
+    fn data() -> &'static [u8] {
+        match active_bank() {
+            Some((index, _)) => &bank(index)[BANK_HEADER_SIZE..],
+            None => &[]
+        }
+    }
+
+    struct Record<'a> {
+        name: &'a str,
+        duration: u64,
+        trace: &'a [u8]
+    }
+
+    struct Iter<'a> {
+        data:   &'a [u8],
+        offset: usize
+    }
+
+    impl<'a> Iter<'a> {
+        fn new(data: &'a [u8]) -> Iter<'a> {
+            Iter { data: data, offset: 0 }
+        }
+    }
+
+    impl<'a> Iterator for Iter<'a> {
+        type Item = Result<Record<'a>, Error>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let data = &self.data[self.offset..];
+
+            if data.is_empty() {
+                return None
+            } else if data.len() < 4 {
+                return Some(Err(Error::Truncated { offset: self.offset }))
+            }
+
+            let record_size = BigEndian::read_u32(data) as usize;
+            if record_size == !0 /* all ones; erased flash */ {
+                return None
+            } else if record_size <= HEADER_SIZE || record_size > data.len() {
+                return Some(Err(Error::InvalidSize { offset: self.offset, size: record_size }))
+            }
+
+            let checksum = BigEndian::read_u32(&data[4..]);
+            if crc32::checksum_ieee(&data[8..record_size]) != checksum {
+                return Some(Err(Error::ChecksumMismatch { offset: self.offset }))
+            }
+
+            let duration = BigEndian::read_u64(&data[8..]);
+            let record_body = &data[HEADER_SIZE..record_size];
+            let pos = match record_body.iter().position(|&x| x == 0) {
+                None => return Some(Err(Error::MissingSeparator { offset: self.offset })),
+                Some(pos) => pos
+            };
+            let name = match str::from_utf8(&record_body[..pos]) {
+                Ok(name) => name,
+                Err(_) => return Some(Err(Error::InvalidName { offset: self.offset }))
+            };
+
+            self.offset += record_size;
+            Some(Ok(Record {
+                name: name,
+                duration: duration,
+                trace: &record_body[pos + 1..]
+            }))
+        }
+    }
+
+    // Returns the records that are neither removed nor overwritten by a later record,
+    // and the offset of the free space.
+    fn live_records(data: &[u8]) -> Result<(Vec<Record>, usize), Error> {
+        let mut records: Vec<Record> = Vec::new();
+        let mut iter = Iter::new(data);
+        while let Some(result) = iter.next() {
+            let record = result?;
+            // last write wins
+            records.retain(|other| other.name != record.name);
+            if !record.trace.is_empty() {
+                records.push(record)
+            }
+        }
+        Ok((records, iter.offset))
+    }
+
+    unsafe fn append_at(data: &[u8], mut offset: usize,
+                        name: &str, duration: u64, trace: &[u8]) -> Result<usize, Error> {
+        let record_size = HEADER_SIZE + name.len() + 1 + trace.len();
+        if offset + record_size > data.len() {
+            return Err(Error::SpaceExhausted)
+        }
+
+        let mut duration_bytes = [0u8; 8];
+        BigEndian::write_u64(&mut duration_bytes[..], duration);
+
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        digest.write(&duration_bytes[..]);
+        digest.write(name.as_bytes());
+        digest.write(&[0]);
+        digest.write(trace);
+
+        let mut header = [0u8; 8];
+        BigEndian::write_u32(&mut header[0..], record_size as u32);
+        BigEndian::write_u32(&mut header[4..], digest.sum32());
+
+        {
+            let mut write = |payload: &[u8]| {
+                spiflash::write(data.as_ptr().offset(offset as isize) as usize, payload);
+                offset += payload.len();
+            };
+
+            write(&header[..]);
+            write(&duration_bytes[..]);
+            write(name.as_bytes());
+            write(&[0]);
+            write(trace);
+            cache::flush_l2_cache();
+        }
+
+        Ok(offset)
+    }
+
+    unsafe fn erase_bank(index: usize) {
+        let mut addr = ADDR + index * BANK_SIZE;
+        while addr < ADDR + (index + 1) * BANK_SIZE {
+            spiflash::erase_sector(addr);
+            addr += spiflash::SECTOR_SIZE;
+        }
+        cache::flush_l2_cache();
+    }
+
+    unsafe fn write_bank_header(index: usize, generation: u32) {
+        let mut header = [0u8; BANK_HEADER_SIZE];
+        BigEndian::write_u32(&mut header[0..], BANK_MAGIC);
+        BigEndian::write_u32(&mut header[4..], generation);
+        spiflash::write(ADDR + index * BANK_SIZE, &header[..]);
+        cache::flush_l2_cache();
+    }
+
+    fn compact() -> Result<(), Error> {
+        let (index, generation) = match active_bank() {
+            Some(active) => active,
+            None => return Ok(())
+        };
+        let (new_index, new_generation) = (1 - index, generation.wrapping_add(1));
+
+        let records = live_records(data())?.0;
+
+        unsafe {
+            erase_bank(new_index);
+            let new_data = &bank(new_index)[BANK_HEADER_SIZE..];
+            let mut offset = 0;
+            for record in records.iter() {
+                offset = append_at(new_data, offset, record.name, record.duration, record.trace)?;
+            }
+            write_bank_header(new_index, new_generation);
+        }
+
+        Ok(())
+    }
+
+    fn append(name: &str, duration: u64, trace: &[u8]) -> Result<(), Error> {
+        if active_bank().is_none() {
+            unsafe {
+                erase_bank(0);
+                write_bank_header(0, 0);
+            }
+        }
+
+        let data = data();
+        let free_offset = live_records(data)?.1;
+        unsafe { append_at(data, free_offset, name, duration, trace)? };
+        Ok(())
+    }
+
+    // The slices passed to `f` point into flash and are invalidated by any
+    // modification of the store; callers must not yield to other threads in `f`.
+    pub fn for_each<F: FnMut(&str, u64, &[u8])>(mut f: F) -> Result<(), Error> {
+        for record in live_records(data())?.0 {
+            f(record.name, record.duration, record.trace)
+        }
+        Ok(())
+    }
+
+    pub fn read<F: FnOnce(Result<(u64, &[u8]), Error>) -> R, R>(name: &str, f: F) -> R {
+        f(live_records(data()).and_then(|(records, _)| {
+            records.iter()
+                .find(|record| record.name == name)
+                .map(|record| (record.duration, record.trace))
+                .ok_or(Error::TraceNotFound)
+        }))
+    }
+
+    pub fn write(name: &str, duration: u64, trace: &[u8]) -> Result<(), Error> {
+        if trace.is_empty() {
+            // An empty trace would read back as a removal.
+            return Err(Error::EmptyTrace)
+        } else if trace[trace.len() - 1] != 0 {
+            // Traces are loaded into the DMA manager as is, and the DMA core stops
+            // at the terminating zero.
+            return Err(Error::UnterminatedTrace)
+        }
+
+        match append(name, duration, trace) {
+            Err(Error::SpaceExhausted) => {
+                compact()?;
+                append(name, duration, trace)
+            }
+            res => res
+        }
+    }
+
+    pub fn remove(name: &str) -> Result<(), Error> {
+        read(name, |result| result.map(|_| ()))?;
+        match append(name, 0, &[]) {
+            Err(Error::SpaceExhausted) => {
+                compact()?;
+                append(name, 0, &[])
+            }
+            res => res
+        }
+    }
+
+    pub fn erase() -> Result<(), Error> {
+        unsafe {
+            erase_bank(0);
+            erase_bank(1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(has_spiflash))]
+mod imp {
+    use super::Error;
+
+    pub fn for_each<F: FnMut(&str, u64, &[u8])>(_f: F) -> Result<(), Error> {
+        Err(Error::NoFlash)
+    }
+
+    pub fn read<F: FnOnce(Result<(u64, &[u8]), Error>) -> R, R>(_name: &str, f: F) -> R {
+        f(Err(Error::NoFlash))
+    }
+
+    pub fn write(_name: &str, _duration: u64, _trace: &[u8]) -> Result<(), Error> {
+        Err(Error::NoFlash)
+    }
+
+    pub fn remove(_name: &str) -> Result<(), Error> {
+        Err(Error::NoFlash)
+    }
+
+    pub fn erase() -> Result<(), Error> {
+        Err(Error::NoFlash)
+    }
+}
+
+pub use self::imp::*;
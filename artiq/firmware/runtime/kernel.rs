@@ -1,5 +1,5 @@
 use core::ptr;
-use board_misoc::csr;
+use board_misoc::{csr, clock};
 use mailbox;
 use rpc_queue;
 
@@ -44,3 +44,35 @@ pub unsafe fn stop() {
 pub fn validate(ptr: usize) -> bool {
     ptr >= KERNELCPU_EXEC_ADDRESS && ptr <= KERNELCPU_LAST_ADDRESS
 }
+
+// Expires when the kernel CPU has not sent a message for `timeout_ms`.
+#[derive(Debug)]
+pub struct Watchdog {
+    timeout_ms: u64,
+    last_fed: u64
+}
+
+impl Watchdog {
+    pub fn new(timeout_ms: u64) -> Watchdog {
+        Watchdog {
+            timeout_ms: timeout_ms,
+            last_fed: clock::get_ms()
+        }
+    }
+
+    pub fn timeout_ms(&self) -> u64 {
+        self.timeout_ms
+    }
+
+    pub fn feed(&mut self) {
+        self.last_fed = clock::get_ms()
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        clock::get_ms() - self.last_fed
+    }
+
+    pub fn expired(&self) -> bool {
+        self.elapsed_ms() > self.timeout_ms
+    }
+}
@@ -1,4 +1,4 @@
-#![feature(lang_items, panic_info_message)]
+#![feature(lang_items, panic_info_message, try_reserve)]
 #![no_std]
 
 extern crate eh;
@@ -11,6 +11,7 @@ extern crate cslice;
 #[macro_use]
 extern crate log;
 extern crate byteorder;
+extern crate crc;
 extern crate fringe;
 extern crate managed;
 extern crate smoltcp;
@@ -51,6 +52,7 @@ mod urc;
 mod sched;
 mod cache;
 mod rtio_dma;
+mod dma_store;
 
 mod mgmt;
 mod kernel;
@@ -98,6 +100,12 @@ fn startup() {
     info!("gateware ident {}", ident::read(&mut [0; 64]));
 
     setup_log_levels();
+    config::check(|err| warn!("configuration: {}; record ignored", err)).ok();
+    config::for_each(|key, value| {
+        if let Err(err) = config::validate(key, value) {
+            warn!("configuration: {}: {}; using default", key, err)
+        }
+    }).ok();
     #[cfg(has_i2c)]
     board_misoc::i2c::init().expect("I2C initialization failed");
     #[cfg(all(soc_platform = "kasli", hw_rev = "v2.0"))]
@@ -178,12 +186,20 @@ fn startup() {
 
     rtio_mgt::startup(&io, &aux_mutex, &drtio_routing_table, &up_destinations);
 
-    io.spawn(4096, mgmt::thread);
+    let congress = urc::Urc::new(session::Congress::new());
+
+    {
+        let aux_mutex = aux_mutex.clone();
+        let drtio_routing_table = drtio_routing_table.clone();
+        let congress = congress.clone();
+        io.spawn(4096, move |io| { mgmt::thread(io, &aux_mutex, &drtio_routing_table, &congress) });
+    }
     {
         let aux_mutex = aux_mutex.clone();
         let drtio_routing_table = drtio_routing_table.clone();
         let up_destinations = up_destinations.clone();
-        io.spawn(16384, move |io| { session::thread(io, &aux_mutex, &drtio_routing_table, &up_destinations) });
+        let congress = congress.clone();
+        io.spawn(16384, move |io| { session::thread(io, &aux_mutex, &drtio_routing_table, &up_destinations, &congress) });
     }
     #[cfg(any(has_rtio_moninj, has_drtio))]
     {
@@ -1,10 +1,20 @@
 use log::{self, LevelFilter};
+use core::cell::RefCell;
+use alloc::{vec::Vec, string::String};
 
 use io::{Write, ProtoWrite, Error as IoError};
 use board_misoc::{config, spiflash};
+#[cfg(has_drtio)]
+use board_misoc::csr;
+use board_artiq::drtio_routing;
 use logger_artiq::BufferLogger;
 use mgmt_proto::*;
-use sched::{Io, TcpListener, TcpStream, Error as SchedError};
+use dma_store;
+use sched::{Io, Mutex, TcpListener, TcpStream, Error as SchedError};
+use rtio_mgt::drtio;
+use proto_artiq::drtioaux_proto::{BULK_STATUS, BULK_CONFIG_READ, BULK_CONFIG_WRITE, BULK_CONFIG_REMOVE};
+use session::Congress;
+use urc::Urc;
 
 impl From<SchedError> for Error<SchedError> {
     fn from(value: SchedError) -> Error<SchedError> {
@@ -12,7 +22,33 @@ impl From<SchedError> for Error<SchedError> {
     }
 }
 
-fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
+// Checks a value against the config schema and, for the routing table, the
+// routes themselves, so that a table the firmware would reject at boot is
+// not stored.
+fn validate_config(key: &str, value: &[u8]) -> Result<(), String> {
+    config::validate(key, value).map_err(|err| format!("{}: {}", key, err))?;
+    #[cfg(has_drtio)]
+    {
+        if key == "routing_table" && !value.is_empty() {
+            parse_routing_table(value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(has_drtio)]
+fn parse_routing_table(data: &[u8]) -> Result<drtio_routing::RoutingTable, String> {
+    drtio_routing::RoutingTable::parse(data, csr::DRTIO.len())
+        .map_err(|err| format!("routing_table: {}", err))
+}
+
+#[cfg(not(has_drtio))]
+fn parse_routing_table(_data: &[u8]) -> Result<drtio_routing::RoutingTable, String> {
+    Err(String::from("routing_table: DRTIO is not supported"))
+}
+
+fn worker(io: &Io, aux_mutex: &Mutex, bulk_mutex: &Mutex, routing_table: &RefCell<drtio_routing::RoutingTable>,
+        stream: &mut TcpStream, congress: &Congress) -> Result<(), Error<SchedError>> {
     read_magic(stream)?;
     Write::write_all(stream, "e".as_bytes())?;
     info!("new connection from {}", stream.remote_endpoint());
@@ -74,7 +110,7 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                 Reply::Success.write_to(stream)?;
             }
 
-            Request::ConfigRead { ref key } => {
+            Request::ConfigRead { destination: 0, ref key } => {
                 config::read(key, |result| {
                     match result {
                         Ok(value) => Reply::ConfigData(&value).write_to(stream),
@@ -82,18 +118,56 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                     }
                 })?;
             }
-            Request::ConfigWrite { ref key, ref value } => {
-                match config::write(key, value) {
-                    Ok(_)  => Reply::Success.write_to(stream),
-                    Err(_) => Reply::Error.write_to(stream)
+            Request::ConfigRead { destination, ref key } => {
+                match drtio::bulk_transfer(io, aux_mutex, bulk_mutex, &routing_table.borrow(), destination,
+                                           BULK_CONFIG_READ, key.as_bytes()) {
+                    Ok(value) => Reply::ConfigData(&value).write_to(stream),
+                    Err(err) => {
+                        error!("cannot read config key {:?} of destination {}: {}", key, destination, err);
+                        Reply::Error.write_to(stream)
+                    }
                 }?;
             }
-            Request::ConfigRemove { ref key } => {
+            Request::ConfigWrite { destination, ref key, ref value } => {
+                if let Err(message) = validate_config(key, value) {
+                    warn!("rejected config write: {}", message);
+                    Reply::ConfigRejected(&message).write_to(stream)?;
+                    continue
+                }
+                if destination == 0 {
+                    match config::write(key, value) {
+                        Ok(_)  => Reply::Success.write_to(stream),
+                        Err(_) => Reply::Error.write_to(stream)
+                    }?;
+                } else {
+                    let mut request = Vec::from(key.as_bytes());
+                    request.push(0);
+                    request.extend_from_slice(value);
+                    match drtio::bulk_transfer(io, aux_mutex, bulk_mutex, &routing_table.borrow(), destination,
+                                               BULK_CONFIG_WRITE, &request) {
+                        Ok(_) => Reply::Success.write_to(stream),
+                        Err(err) => {
+                            error!("cannot write config key {:?} of destination {}: {}", key, destination, err);
+                            Reply::Error.write_to(stream)
+                        }
+                    }?;
+                }
+            }
+            Request::ConfigRemove { destination: 0, ref key } => {
                 match config::remove(key) {
                     Ok(()) => Reply::Success.write_to(stream),
                     Err(_) => Reply::Error.write_to(stream)
                 }?;
-
+            }
+            Request::ConfigRemove { destination, ref key } => {
+                match drtio::bulk_transfer(io, aux_mutex, bulk_mutex, &routing_table.borrow(), destination,
+                                           BULK_CONFIG_REMOVE, key.as_bytes()) {
+                    Ok(_) => Reply::Success.write_to(stream),
+                    Err(err) => {
+                        error!("cannot remove config key {:?} of destination {}: {}", key, destination, err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
             }
             Request::ConfigErase => {
                 match config::erase() {
@@ -101,6 +175,232 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                     Err(_) => Reply::Error.write_to(stream)
                 }?;
             }
+            // Writing to the stream may wait, so the records are copied out of flash first.
+            Request::ConfigList => {
+                let mut keys = Vec::new();
+                match config::for_each(|key, _| keys.push(String::from(key))) {
+                    Ok(()) => Reply::ConfigKeys(&keys).write_to(stream),
+                    Err(_) => Reply::Error.write_to(stream)
+                }?;
+            }
+            Request::ConfigDump => {
+                let mut entries = Vec::new();
+                match config::for_each(|key, value| {
+                    entries.push((String::from(key), Vec::from(value)))
+                }) {
+                    Ok(()) => Reply::ConfigDump(&entries).write_to(stream),
+                    Err(_) => Reply::Error.write_to(stream)
+                }?;
+            }
+            Request::ConfigRestore { ref entries } => {
+                let invalid = entries.iter().filter_map(|&(ref key, ref value)| {
+                    validate_config(key, value).err()
+                }).next();
+                if let Some(message) = invalid {
+                    warn!("rejected config restore: {}", message);
+                    Reply::ConfigRejected(&message).write_to(stream)?;
+                    continue
+                }
+                let entries = entries.iter().map(|&(ref key, ref value)| (key.as_str(), &value[..]));
+                match config::replace_all(entries) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(err) => {
+                        error!("cannot restore config: {}", err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::ConfigStats => {
+                match config::stats() {
+                    Ok(stats) => Reply::ConfigStats {
+                        capacity:     stats.capacity,
+                        used:         stats.used,
+                        live_bytes:   stats.live_bytes,
+                        live_records: stats.live_records,
+                        dead_records: stats.dead_records,
+                        corruption:   stats.corruption
+                    }.write_to(stream),
+                    Err(_) => Reply::Error.write_to(stream)
+                }?;
+            }
+
+            // The stored traces are copied out of flash before being sent, since another
+            // thread may modify the store while this one waits on the network.
+            Request::DmaTraceList => {
+                let mut traces = Vec::new();
+                match dma_store::for_each(|name, duration, trace| {
+                    traces.push((String::from(name), duration, trace.len()))
+                }) {
+                    Ok(()) => Reply::DmaTraceList(&traces).write_to(stream),
+                    Err(err) => {
+                        error!("cannot list stored DMA traces: {}", err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::DmaTraceExport { ref name } => {
+                match dma_store::read(name, |result| result.map(|(duration, trace)| {
+                    (duration, Vec::from(trace))
+                })) {
+                    Ok((duration, trace)) =>
+                        Reply::DmaTrace { duration: duration, trace: &trace }.write_to(stream),
+                    Err(err) => {
+                        error!("cannot export DMA trace {:?}: {}", name, err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::DmaTraceImport { ref name, duration, ref trace } => {
+                match dma_store::write(name, duration, trace) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(err) => {
+                        error!("cannot import DMA trace {:?}: {}", name, err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::DmaTraceDelete { ref name } => {
+                match dma_store::remove(name) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(err) => {
+                        error!("cannot delete DMA trace {:?}: {}", name, err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+
+            // The session thread may hold these borrows while waiting for the kernel CPU,
+            // and the replies are built before writing, which may also wait.
+            Request::DmaList => {
+                let traces = io.until_ok(|| congress.dma_manager.try_borrow())?
+                    .iter()
+                    .map(|(name, size, duration, borrowed)|
+                        (String::from(name), size, duration, borrowed))
+                    .collect::<Vec<_>>();
+                Reply::DmaList(&traces).write_to(stream)?;
+            }
+            Request::DmaErase { ref name } => {
+                let result = io.until_ok(|| congress.dma_manager.try_borrow_mut())?
+                    .remove(name);
+                match result {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(()) => {
+                        error!("cannot erase DMA trace {:?} in use by the kernel", name);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::DmaClear => {
+                let kept = io.until_ok(|| congress.dma_manager.try_borrow_mut())?.clear();
+                if kept == 0 {
+                    Reply::Success.write_to(stream)?;
+                } else {
+                    error!("kept {} DMA trace(s) in use by the kernel", kept);
+                    Reply::Error.write_to(stream)?;
+                }
+            }
+            Request::CacheList => {
+                let entries = io.until_ok(|| congress.cache.try_borrow())?
+                    .iter()
+                    .map(|(key, length, borrowed)| (String::from(key), length, borrowed))
+                    .collect::<Vec<_>>();
+                Reply::CacheList(&entries).write_to(stream)?;
+            }
+            Request::CacheErase { ref key } => {
+                let result = io.until_ok(|| congress.cache.try_borrow_mut())?.remove(key);
+                match result {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(()) => {
+                        error!("cannot erase cache entry {:?} in use by the kernel", key);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::CacheClear => {
+                let kept = io.until_ok(|| congress.cache.try_borrow_mut())?.clear();
+                if kept == 0 {
+                    Reply::Success.write_to(stream)?;
+                } else {
+                    error!("kept {} cache entries in use by the kernel", kept);
+                    Reply::Error.write_to(stream)?;
+                }
+            }
+
+            Request::DrtioLinkStats { destination } => {
+                if destination == 0 {
+                    Reply::DrtioLinkStats(&drtio::link_stats()).write_to(stream)?;
+                } else {
+                    match drtio::remote_link_stats(io, aux_mutex, &routing_table.borrow(), destination) {
+                        Ok(stats) => Reply::DrtioLinkStats(&stats).write_to(stream),
+                        Err(err) => {
+                            error!("cannot read link statistics of destination {}: {}", destination, err);
+                            Reply::Error.write_to(stream)
+                        }
+                    }?;
+                }
+            }
+            Request::DrtioStatus { destination } => {
+                match drtio::bulk_transfer(io, aux_mutex, bulk_mutex, &routing_table.borrow(), destination, BULK_STATUS, &[]) {
+                    Ok(status) => Reply::DrtioStatus(&String::from_utf8_lossy(&status)).write_to(stream),
+                    Err(err) => {
+                        error!("cannot read status of destination {}: {}", destination, err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::DrtioTopology => {
+                match drtio::discover_topology(io, aux_mutex) {
+                    Ok(discovered) => {
+                        let paths = (0..drtio_routing::DEST_COUNT)
+                            .filter_map(|destination| {
+                                discovered.path(destination as u8)
+                                    .map(|hops| (destination as u8, Vec::from(hops)))
+                            })
+                            .collect::<Vec<_>>();
+                        Reply::DrtioTopology(&paths).write_to(stream)
+                    },
+                    Err(err) => {
+                        error!("cannot discover DRTIO topology: {}", err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::DrtioSetRoutingTable { ref table } => {
+                match parse_routing_table(table) {
+                    Ok(new_table) => {
+                        match drtio::set_routing_table(io, routing_table, new_table) {
+                            Ok(()) => Reply::Success.write_to(stream),
+                            Err(err) => {
+                                error!("cannot set routing table: {}", err);
+                                Reply::Error.write_to(stream)
+                            }
+                        }
+                    },
+                    Err(message) => {
+                        warn!("rejected routing table: {}", message);
+                        Reply::ConfigRejected(&message).write_to(stream)
+                    }
+                }?;
+            }
+            Request::DrtioFirmwareUpdate { destination, ref image } => {
+                info!("writing firmware of destination {}", destination);
+                match drtio::firmware_update(io, aux_mutex, bulk_mutex, &routing_table.borrow(), destination, image) {
+                    Ok(()) => Reply::Success.write_to(stream),
+                    Err(err) => {
+                        error!("cannot write firmware of destination {}: {}", destination, err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
+            Request::DrtioReboot { destination } => {
+                match drtio::reboot(io, aux_mutex, &routing_table.borrow(), destination) {
+                    Ok(()) => Reply::RebootImminent.write_to(stream),
+                    Err(err) => {
+                        error!("cannot reboot destination {}: {}", destination, err);
+                        Reply::Error.write_to(stream)
+                    }
+                }?;
+            }
 
             Request::Reboot => {
                 Reply::RebootImminent.write_to(stream)?;
@@ -117,16 +417,25 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
     }
 }
 
-pub fn thread(io: Io) {
+pub fn thread(io: Io, aux_mutex: &Mutex,
+        routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>, congress: &Urc<Congress>) {
     let listener = TcpListener::new(&io, 8192);
     listener.listen(1380).expect("mgmt: cannot listen");
     info!("management interface active");
 
+    // Satellites hold a single bulk transfer at a time.
+    let bulk_mutex = Mutex::new();
+
     loop {
         let stream = listener.accept().expect("mgmt: cannot accept").into_handle();
-        io.spawn(4096, move |io| {
+        let aux_mutex = aux_mutex.clone();
+        let bulk_mutex = bulk_mutex.clone();
+        let routing_table = routing_table.clone();
+        let congress = congress.clone();
+        // The stack holds up to two routing tables (8 KiB each).
+        io.spawn(32768, move |io| {
             let mut stream = TcpStream::from_handle(&io, stream);
-            match worker(&io, &mut stream) {
+            match worker(&io, &aux_mutex, &bulk_mutex, &routing_table, &mut stream, &congress) {
                 Ok(()) => (),
                 Err(Error::Io(IoError::UnexpectedEnd)) => (),
                 Err(err) => error!("aborted: {}", err)
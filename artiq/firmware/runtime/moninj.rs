@@ -1,12 +1,167 @@
 use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+use alloc::string::String;
 use core::cell::RefCell;
+use core::mem;
 
 use io::Error as IoError;
 use moninj_proto::*;
 use sched::{Io, Mutex, TcpListener, TcpStream, Error as SchedError};
 use urc::Urc;
-use board_misoc::clock;
+use board_misoc::{clock, csr};
 use board_artiq::drtio_routing;
+#[cfg(has_drtio)]
+use drtioaux;
+
+const DEFAULT_POLL_INTERVAL_MS: u64 = 200;
+const MIN_POLL_INTERVAL_MS: u64 = 10;
+
+// Number of samples kept for each watched probe when history is enabled.
+const HISTORY_DEPTH: usize = 64;
+
+// Override enable, the first override of TTL PHYs.
+const OVERRIDE_EN: u8 = 0;
+
+// Overrides enabled by a connection, released when the connection closes
+// or when the host has not sent anything for `timeout_ms` (if non-zero).
+struct Lease {
+    timeout_ms: u64,
+    channels: BTreeSet<u32>
+}
+
+// A probe or injection status watched by at least one connection, with its
+// value and the RTIO counter at the last poll.
+struct Watched<T> {
+    watchers:  usize,
+    value:     Option<T>,
+    timestamp: u64
+}
+
+fn watch<T>(map: &mut BTreeMap<(u32, u8), Watched<T>>, key: (u32, u8)) {
+    map.entry(key)
+        .or_insert(Watched { watchers: 0, value: None, timestamp: 0 })
+        .watchers += 1
+}
+
+fn unwatch<T>(map: &mut BTreeMap<(u32, u8), Watched<T>>, key: (u32, u8)) {
+    let unwatched = match map.get_mut(&key) {
+        Some(watched) => {
+            watched.watchers -= 1;
+            watched.watchers == 0
+        }
+        None => false
+    };
+    if unwatched {
+        map.remove(&key);
+    }
+}
+
+#[derive(Clone)]
+struct Notification {
+    channel: u32,
+    overrd:  u8,
+    value:   u8,
+    origin:  String
+}
+
+// State shared by all connections and the poll thread, which reads every
+// watched probe and injection status once per poll for all connections.
+struct Shared {
+    probes:        BTreeMap<(u32, u8), Watched<u64>>,
+    injections:    BTreeMap<(u32, u8), Watched<u8>>,
+    // Incremented after each poll.
+    generation:    u64,
+    // Shortest interval at which each connection needs new values.
+    intervals:     BTreeMap<usize, u64>,
+    // Injections made by other connections, not yet sent to each connection.
+    notifications: BTreeMap<usize, Vec<Notification>>,
+    // Overrides enabled through moninj, on all connections.
+    overrides:     BTreeSet<u32>,
+    next_id:       usize
+}
+
+impl Shared {
+    fn new() -> Shared {
+        Shared {
+            probes:        BTreeMap::new(),
+            injections:    BTreeMap::new(),
+            generation:    0,
+            intervals:     BTreeMap::new(),
+            notifications: BTreeMap::new(),
+            overrides:     BTreeSet::new(),
+            next_id:       0
+        }
+    }
+
+    fn register(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.intervals.insert(id, DEFAULT_POLL_INTERVAL_MS);
+        self.notifications.insert(id, Vec::new());
+        id
+    }
+
+    fn unregister(&mut self, client: &Client) {
+        for &key in client.probes.keys() {
+            unwatch(&mut self.probes, key);
+        }
+        for &key in client.injections.keys() {
+            unwatch(&mut self.injections, key);
+        }
+        self.intervals.remove(&client.id);
+        self.notifications.remove(&client.id);
+    }
+
+    fn poll_interval(&self) -> u64 {
+        self.intervals.values().cloned().min().unwrap_or(DEFAULT_POLL_INTERVAL_MS)
+    }
+
+    // Records an injection made by connection `from`.
+    fn injected(&mut self, from: usize, origin: &str, channel: u32, overrd: u8, value: u8) {
+        if overrd == OVERRIDE_EN {
+            if value != 0 {
+                self.overrides.insert(channel);
+            } else {
+                self.overrides.remove(&channel);
+            }
+        }
+        if let Some(watched) = self.injections.get_mut(&(channel, overrd)) {
+            watched.value = Some(value);
+        }
+        for (&id, queue) in self.notifications.iter_mut() {
+            if id != from {
+                queue.push(Notification {
+                    channel: channel,
+                    overrd:  overrd,
+                    value:   value,
+                    origin:  String::from(origin)
+                })
+            }
+        }
+    }
+}
+
+// Per-connection state, kept outside of `connection_worker` so that it is
+// cleaned up however the connection ends.
+struct Client {
+    id:         usize,
+    origin:     String,
+    // Last values sent to the host.
+    probes:     BTreeMap<(u32, u8), Option<u64>>,
+    injections: BTreeMap<(u32, u8), Option<u8>>,
+    lease:      Option<Lease>
+}
+
+// The kernel CPU may latch the counter concurrently, which only affects
+// the timestamp by the time between the two latches.
+fn rtio_counter() -> u64 {
+    unsafe {
+        csr::rtio::counter_update_write(1);
+        csr::rtio::counter_read()
+    }
+}
 
 #[cfg(has_rtio_moninj)]
 mod local_moninj {
@@ -36,6 +191,15 @@ mod local_moninj {
             csr::rtio_moninj::inj_value_read()
         }
     }
+
+    // Number of channels, and largest number of probes and overrides of a channel.
+    pub fn read_info() -> (u16, u8, u8) {
+        unsafe {
+            (csr::rtio_moninj::mon_n_channels_read(),
+             csr::rtio_moninj::mon_n_probes_read(),
+             csr::rtio_moninj::inj_n_overrides_read())
+        }
+    }
 }
 
 #[cfg(not(has_rtio_moninj))]
@@ -45,14 +209,31 @@ mod local_moninj {
     pub fn inject(_channel: u16, _overrd: u8, _value: u8) { }
 
     pub fn read_injection_status(_channel: u16, _overrd: u8) -> u8 { 0 }
+
+    pub fn read_info() -> (u16, u8, u8) { (0, 0, 0) }
 }
 
 #[cfg(has_drtio)]
 mod remote_moninj {
+    use alloc::vec::Vec;
+    use board_artiq::drtio_routing;
     use drtioaux;
     use rtio_mgt::drtio;
     use sched::{Io, Mutex};
 
+    // Destinations that did not answer a batch request, e.g. satellites running
+    // older firmware; they are read one item at a time from then on.
+    static mut NO_BATCH: [bool; drtio_routing::DEST_COUNT] = [false; drtio_routing::DEST_COUNT];
+
+    fn batch_supported(destination: u8) -> bool {
+        unsafe { !NO_BATCH[destination as usize] }
+    }
+
+    fn disable_batch(destination: u8) {
+        warn!("[DEST#{}] batch requests failed, reading moninj values one at a time", destination);
+        unsafe { NO_BATCH[destination as usize] = true }
+    }
+
     pub fn read_probe(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, channel: u16, probe: u8) -> u64 {
         let reply = drtio::aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::MonitorRequest { 
             destination: destination,
@@ -67,14 +248,47 @@ mod remote_moninj {
         0
     }
 
+    pub fn read_probes(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8,
+                       probes: &[(u32, u8)], values: &mut Vec<u64>) {
+        if batch_supported(destination) {
+            let mut channels = [0; drtioaux::MONITOR_BATCH_MAX];
+            let mut probe_sels = [0; drtioaux::MONITOR_BATCH_MAX];
+            for (i, &(channel, probe)) in probes.iter().enumerate() {
+                channels[i] = channel as u16;
+                probe_sels[i] = probe;
+            }
+            let reply = drtio::aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::MonitorBatchRequest {
+                destination: destination,
+                count: probes.len() as u8,
+                channels: channels,
+                probes: probe_sels
+            });
+            match reply {
+                Ok(drtioaux::Packet::MonitorBatchReply { count, values: ref batch })
+                        if count as usize == probes.len() => {
+                    values.extend_from_slice(&batch[..probes.len()]);
+                    return
+                }
+                Ok(packet) => error!("received unexpected aux packet: {:?}", packet),
+                Err(e) => error!("aux packet error ({})", e)
+            }
+            disable_batch(destination);
+        }
+        values.extend(probes.iter().map(|&(channel, probe)|
+            read_probe(io, aux_mutex, linkno, destination, channel as u16, probe)))
+    }
+
     pub fn inject(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, channel: u16, overrd: u8, value: u8) {
         let _lock = aux_mutex.lock(io).unwrap();
-        drtioaux::send(linkno, &drtioaux::Packet::InjectionRequest {
+        let result = drtioaux::send_waiting(linkno, &drtioaux::Packet::InjectionRequest {
             destination: destination,
             channel: channel,
             overrd: overrd,
             value: value
-        }).unwrap();
+        }, || io.relinquish().unwrap());
+        if let Err(e) = result {
+            error!("aux packet error ({})", e)
+        }
     }
 
     pub fn read_injection_status(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8, channel: u16, overrd: u8) -> u8 {
@@ -90,6 +304,49 @@ mod remote_moninj {
         }
         0
     }
+
+    pub fn read_injection_statuses(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8,
+                                   overrides: &[(u32, u8)], values: &mut Vec<u8>) {
+        if batch_supported(destination) {
+            let mut channels = [0; drtioaux::MONITOR_BATCH_MAX];
+            let mut overrds = [0; drtioaux::MONITOR_BATCH_MAX];
+            for (i, &(channel, overrd)) in overrides.iter().enumerate() {
+                channels[i] = channel as u16;
+                overrds[i] = overrd;
+            }
+            let reply = drtio::aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::InjectionStatusBatchRequest {
+                destination: destination,
+                count: overrides.len() as u8,
+                channels: channels,
+                overrds: overrds
+            });
+            match reply {
+                Ok(drtioaux::Packet::InjectionStatusBatchReply { count, values: ref batch })
+                        if count as usize == overrides.len() => {
+                    values.extend_from_slice(&batch[..overrides.len()]);
+                    return
+                }
+                Ok(packet) => error!("received unexpected aux packet: {:?}", packet),
+                Err(e) => error!("aux packet error ({})", e)
+            }
+            disable_batch(destination);
+        }
+        values.extend(overrides.iter().map(|&(channel, overrd)|
+            read_injection_status(io, aux_mutex, linkno, destination, channel as u16, overrd)))
+    }
+
+    pub fn read_info(io: &Io, aux_mutex: &Mutex, linkno: u8, destination: u8) -> (u16, u8, u8) {
+        let reply = drtio::aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::MoninjInfoRequest {
+            destination: destination
+        });
+        match reply {
+            Ok(drtioaux::Packet::MoninjInfoReply { channels, probes, overrides }) =>
+                return (channels, probes, overrides),
+            Ok(packet) => error!("received unexpected aux packet: {:?}", packet),
+            Err(e) => error!("aux packet error ({})", e)
+        }
+        (0, 0, 0)
+    }
 }
 
 #[cfg(has_drtio)]
@@ -97,7 +354,7 @@ macro_rules! dispatch {
     ($io:ident, $aux_mutex:ident, $routing_table:ident, $channel:expr, $func:ident $(, $param:expr)*) => {{
         let destination = ($channel >> 16) as u8;
         let channel = $channel as u16;
-        let hop = $routing_table.0[destination as usize][0];
+        let hop = $routing_table.borrow().0[destination as usize][0];
         if hop == 0 {
             local_moninj::$func(channel, $($param, )*)
         } else {
@@ -115,36 +372,171 @@ macro_rules! dispatch {
     }}
 }
 
-fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+// Reads the (channel, probe or override) pairs in the order given, batching
+// those of each remote destination.
+#[cfg(has_drtio)]
+macro_rules! dispatch_batch {
+    ($io:ident, $aux_mutex:ident, $routing_table:ident, $items:expr, $func:ident, $batch_func:ident) => {{
+        let items: &[(u32, u8)] = $items;
+        let mut values = Vec::with_capacity(items.len());
+        let mut rest = items;
+        while !rest.is_empty() {
+            let destination = (rest[0].0 >> 16) as u8;
+            let count = rest.iter()
+                .take(drtioaux::MONITOR_BATCH_MAX)
+                .take_while(|&&(channel, _)| (channel >> 16) as u8 == destination)
+                .count();
+            let (batch, next) = rest.split_at(count);
+            let hop = $routing_table.borrow().0[destination as usize][0];
+            if hop == 0 {
+                values.extend(batch.iter().map(|&(channel, sel)|
+                    local_moninj::$func(channel as u16, sel)))
+            } else {
+                let linkno = hop - 1;
+                remote_moninj::$batch_func($io, $aux_mutex, linkno, destination, batch, &mut values)
+            }
+            rest = next;
+        }
+        values
+    }}
+}
+
+#[cfg(not(has_drtio))]
+macro_rules! dispatch_batch {
+    ($io:ident, $aux_mutex:ident, $routing_table:ident, $items:expr, $func:ident, $batch_func:ident) => {{
+        let items: &[(u32, u8)] = $items;
+        items.iter()
+            .map(|&(channel, sel)| local_moninj::$func(channel as u16, sel))
+            .collect::<Vec<_>>()
+    }}
+}
+
+#[cfg(has_drtio)]
+fn read_info(io: &Io, aux_mutex: &Mutex, routing_table: &RefCell<drtio_routing::RoutingTable>,
+             destination: u8) -> (u16, u8, u8) {
+    let hop = routing_table.borrow().0[destination as usize][0];
+    if hop == 0 {
+        local_moninj::read_info()
+    } else if hop == drtio_routing::INVALID_HOP || hop as usize > csr::DRTIO.len() {
+        (0, 0, 0)
+    } else {
+        remote_moninj::read_info(io, aux_mutex, hop - 1, destination)
+    }
+}
+
+#[cfg(not(has_drtio))]
+fn read_info(_io: &Io, _aux_mutex: &Mutex, _routing_table: &RefCell<drtio_routing::RoutingTable>,
+             destination: u8) -> (u16, u8, u8) {
+    if destination == 0 {
+        local_moninj::read_info()
+    } else {
+        (0, 0, 0)
+    }
+}
+
+fn release_overrides(io: &Io, _aux_mutex: &Mutex, _routing_table: &RefCell<drtio_routing::RoutingTable>,
+        shared: &RefCell<Shared>, client: &Client, channels: &BTreeSet<u32>) {
+    for &channel in channels.iter() {
+        dispatch!(io, _aux_mutex, _routing_table, channel, inject, OVERRIDE_EN, 0);
+        shared.borrow_mut().injected(client.id, &client.origin, channel, OVERRIDE_EN, 0);
+    }
+}
+
+fn poll_thread(io: &Io, _aux_mutex: &Mutex, _routing_table: &RefCell<drtio_routing::RoutingTable>,
+        shared: &RefCell<Shared>) {
+    loop {
+        // Values are read without borrowing the shared state, since remote reads yield.
+        let (probes, injections) = {
+            let shared = shared.borrow();
+            (shared.probes.keys().cloned().collect::<Vec<_>>(),
+             shared.injections.keys().cloned().collect::<Vec<_>>())
+        };
+        let probe_values = dispatch_batch!(io, _aux_mutex, _routing_table,
+            &probes, read_probe, read_probes);
+        let injection_values = dispatch_batch!(io, _aux_mutex, _routing_table,
+            &injections, read_injection_status, read_injection_statuses);
+        let timestamp = rtio_counter();
+
+        let interval = {
+            let mut shared = shared.borrow_mut();
+            for (key, value) in probes.iter().zip(probe_values) {
+                if let Some(watched) = shared.probes.get_mut(key) {
+                    watched.value = Some(value);
+                    watched.timestamp = timestamp;
+                }
+            }
+            for (key, value) in injections.iter().zip(injection_values) {
+                if let Some(watched) = shared.injections.get_mut(key) {
+                    watched.value = Some(value);
+                    watched.timestamp = timestamp;
+                }
+            }
+            shared.generation += 1;
+            shared.poll_interval()
+        };
+        io.sleep(interval).unwrap();
+    }
+}
+
+fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &RefCell<drtio_routing::RoutingTable>,
+        shared: &RefCell<Shared>, client: &mut Client,
         mut stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
-    let mut probe_watch_list = BTreeMap::new();
-    let mut inject_watch_list = BTreeMap::new();
+    let mut poll_interval = DEFAULT_POLL_INTERVAL_MS;
+    let mut batch_updates = false;
     let mut next_check = 0;
+    let mut checked_generation = 0;
+    let mut last_request = clock::get_ms();
+    let mut history = BTreeMap::new();
+    let mut history_interval = 0;
+    let mut next_sample = 0;
+    let mut sampled_generation = 0;
 
     read_magic(&mut stream)?;
-    info!("new connection from {}", stream.remote_endpoint());
+    info!("new connection from {}", client.origin);
 
     loop {
         if stream.can_recv() {
             let request = HostMessage::read_from(stream)?;
             trace!("moninj<-host {:?}", request);
+            last_request = clock::get_ms();
 
             match request {
                 HostMessage::MonitorProbe { enable, channel, probe } => {
+                    let key = (channel, probe);
                     if enable {
-                        let _ = probe_watch_list.entry((channel, probe)).or_insert(None);
-                    } else {
-                        let _ = probe_watch_list.remove(&(channel, probe));
+                        if !client.probes.contains_key(&key) {
+                            client.probes.insert(key, None);
+                            watch(&mut shared.borrow_mut().probes, key);
+                        }
+                    } else if client.probes.remove(&key).is_some() {
+                        unwatch(&mut shared.borrow_mut().probes, key);
+                        let _ = history.remove(&key);
                     }
                 },
                 HostMessage::MonitorInjection { enable, channel, overrd } => {
+                    let key = (channel, overrd);
                     if enable {
-                        let _ = inject_watch_list.entry((channel, overrd)).or_insert(None);
-                    } else {
-                        let _ = inject_watch_list.remove(&(channel, overrd));
+                        if !client.injections.contains_key(&key) {
+                            client.injections.insert(key, None);
+                            watch(&mut shared.borrow_mut().injections, key);
+                        }
+                    } else if client.injections.remove(&key).is_some() {
+                        unwatch(&mut shared.borrow_mut().injections, key);
+                    }
+                },
+                HostMessage::Inject { channel, overrd, value } => {
+                    dispatch!(io, _aux_mutex, _routing_table, channel, inject, overrd, value);
+                    shared.borrow_mut().injected(client.id, &client.origin, channel, overrd, value);
+                    if overrd == OVERRIDE_EN {
+                        if let Some(ref mut lease) = client.lease {
+                            if value != 0 {
+                                lease.channels.insert(channel);
+                            } else {
+                                lease.channels.remove(&channel);
+                            }
+                        }
                     }
                 },
-                HostMessage::Inject { channel, overrd, value } => dispatch!(io, _aux_mutex, _routing_table, channel, inject, overrd, value),
                 HostMessage::GetInjectionStatus { channel, overrd } => {
                     let value = dispatch!(io, _aux_mutex, _routing_table, channel, read_injection_status, overrd);
                     let reply = DeviceMessage::InjectionStatus {
@@ -155,44 +547,194 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
 
                     trace!("moninj->host {:?}", reply);
                     reply.write_to(stream)?;
+                },
+                HostMessage::SetPollInterval { interval_ms } => {
+                    poll_interval = (interval_ms as u64).max(MIN_POLL_INTERVAL_MS);
+                    next_check = clock::get_ms() + poll_interval;
+                },
+                HostMessage::BatchUpdates { enable } =>
+                    batch_updates = enable,
+                HostMessage::SetLease { enable, timeout_ms } => {
+                    if enable {
+                        let channels = client.lease.take().map(|lease| lease.channels)
+                                                          .unwrap_or_else(BTreeSet::new);
+                        client.lease = Some(Lease { timeout_ms: timeout_ms as u64, channels: channels });
+                    } else {
+                        // Overrides already leased stay in effect.
+                        client.lease = None;
+                    }
+                },
+                HostMessage::Snapshot { destination } => {
+                    let (channels, probes, overrides) =
+                        read_info(io, _aux_mutex, _routing_table, destination);
+                    let base = (destination as u32) << 16;
+                    let probe_list = (0..channels as u32)
+                        .flat_map(|channel| (0..probes).map(move |probe| (base | channel, probe)))
+                        .collect::<Vec<_>>();
+                    let override_list = (0..channels as u32)
+                        .flat_map(|channel| (0..overrides).map(move |overrd| (base | channel, overrd)))
+                        .collect::<Vec<_>>();
+                    let probe_values = dispatch_batch!(io, _aux_mutex, _routing_table,
+                        &probe_list, read_probe, read_probes);
+                    let injection_values = dispatch_batch!(io, _aux_mutex, _routing_table,
+                        &override_list, read_injection_status, read_injection_statuses);
+                    let reply = DeviceMessage::Snapshot {
+                        destination: destination,
+                        channels: channels,
+                        probes: probes,
+                        overrides: overrides,
+                        probe_values: &probe_values,
+                        injection_values: &injection_values
+                    };
+
+                    trace!("moninj->host {:?}", reply);
+                    reply.write_to(stream)?;
+                },
+                HostMessage::SetHistoryInterval { interval_ms } => {
+                    if interval_ms == 0 {
+                        history_interval = 0;
+                        history.clear();
+                    } else {
+                        history_interval = (interval_ms as u64).max(MIN_POLL_INTERVAL_MS);
+                        next_sample = clock::get_ms();
+                    }
+                },
+                HostMessage::GetHistory { channel, probe } => {
+                    let samples = match history.get(&(channel, probe)) {
+                        Some(ring) => ring.iter().cloned().collect::<Vec<_>>(),
+                        None => Vec::new()
+                    };
+                    let reply = DeviceMessage::History {
+                        channel: channel,
+                        probe: probe,
+                        samples: &samples
+                    };
+
+                    trace!("moninj->host {:?}", reply);
+                    reply.write_to(stream)?;
+                },
+                HostMessage::ReleaseAllOverrides => {
+                    let channels = mem::replace(&mut shared.borrow_mut().overrides, BTreeSet::new());
+                    info!("releasing {} overrides", channels.len());
+                    release_overrides(io, _aux_mutex, _routing_table, shared, client, &channels);
+                    if let Some(ref mut lease) = client.lease {
+                        lease.channels.clear();
+                    }
                 }
             }
+
+            let interval = if history_interval != 0 { poll_interval.min(history_interval) }
+                           else { poll_interval };
+            shared.borrow_mut().intervals.insert(client.id, interval);
         } else if !stream.may_recv() {
             return Ok(())
         }
 
-        if clock::get_ms() > next_check {
-            for (&(channel, probe), previous) in probe_watch_list.iter_mut() {
-                let current = dispatch!(io, _aux_mutex, _routing_table, channel, read_probe, probe);
-                if previous.is_none() || previous.unwrap() != current {
+        let expired = match client.lease {
+            Some(ref lease) => lease.timeout_ms != 0 && !lease.channels.is_empty() &&
+                               clock::get_ms() > last_request + lease.timeout_ms,
+            None => false
+        };
+        if expired {
+            let channels = client.lease.as_mut().map(|lease| mem::replace(&mut lease.channels, BTreeSet::new()))
+                                                .unwrap_or_else(BTreeSet::new);
+            warn!("moninj lease expired, releasing {} overrides", channels.len());
+            release_overrides(io, _aux_mutex, _routing_table, shared, client, &channels);
+        }
+
+        let notifications = shared.borrow_mut().notifications.get_mut(&client.id)
+            .map(|queue| mem::replace(queue, Vec::new()))
+            .unwrap_or_else(Vec::new);
+        for notification in notifications.iter() {
+            if !client.injections.contains_key(&(notification.channel, notification.overrd)) {
+                continue
+            }
+            let message = DeviceMessage::InjectionChanged {
+                channel: notification.channel,
+                overrd: notification.overrd,
+                value: notification.value,
+                origin: &notification.origin
+            };
+
+            trace!("moninj->host {:?}", message);
+            message.write_to(stream)?;
+        }
+
+        let generation = shared.borrow().generation;
+
+        if history_interval != 0 && clock::get_ms() >= next_sample && generation != sampled_generation {
+            for (&key, watched) in shared.borrow().probes.iter() {
+                if !client.probes.contains_key(&key) {
+                    continue
+                }
+                if let Some(value) = watched.value {
+                    let ring = history.entry(key).or_insert_with(|| VecDeque::with_capacity(HISTORY_DEPTH));
+                    if ring.len() == HISTORY_DEPTH {
+                        ring.pop_front();
+                    }
+                    ring.push_back((watched.timestamp, value));
+                }
+            }
+            sampled_generation = generation;
+            next_sample = clock::get_ms() + history_interval;
+        }
+
+        if clock::get_ms() > next_check && generation != checked_generation {
+            let mut monitor_updates = Vec::new();
+            let mut injection_updates = Vec::new();
+
+            {
+                let shared = shared.borrow();
+                for (&(channel, probe), previous) in client.probes.iter_mut() {
+                    let current = shared.probes.get(&(channel, probe)).and_then(|watched| watched.value);
+                    if current.is_some() && *previous != current {
+                        monitor_updates.push((channel, probe, current.unwrap()));
+                        *previous = current;
+                    }
+                }
+                for (&(channel, overrd), previous) in client.injections.iter_mut() {
+                    let current = shared.injections.get(&(channel, overrd)).and_then(|watched| watched.value);
+                    if current.is_some() && *previous != current {
+                        injection_updates.push((channel, overrd, current.unwrap()));
+                        *previous = current;
+                    }
+                }
+            }
+
+            if batch_updates {
+                if !monitor_updates.is_empty() || !injection_updates.is_empty() {
+                    let message = DeviceMessage::Updates {
+                        monitor: &monitor_updates,
+                        injection: &injection_updates
+                    };
+
+                    trace!("moninj->host {:?}", message);
+                    message.write_to(stream)?;
+                }
+            } else {
+                for &(channel, probe, value) in monitor_updates.iter() {
                     let message = DeviceMessage::MonitorStatus {
                         channel: channel,
                         probe: probe,
-                        value: current
+                        value: value
                     };
 
                     trace!("moninj->host {:?}", message);
                     message.write_to(stream)?;
-
-                    *previous = Some(current);
                 }
-            }
-            for (&(channel, overrd), previous) in inject_watch_list.iter_mut() {
-                let current = dispatch!(io, _aux_mutex, _routing_table, channel, read_injection_status, overrd);
-                if previous.is_none() || previous.unwrap() != current {
+                for &(channel, overrd, value) in injection_updates.iter() {
                     let message = DeviceMessage::InjectionStatus {
                         channel: channel,
                         overrd: overrd,
-                        value: current
+                        value: value
                     };
 
                     trace!("moninj->host {:?}", message);
                     message.write_to(stream)?;
-
-                    *previous = Some(current);
                 }
             }
-            next_check = clock::get_ms() + 200;
+            checked_generation = generation;
+            next_check = clock::get_ms() + poll_interval;
         }
 
         io.relinquish().map_err(|err| Error::Io(IoError::Other(err)))?;
@@ -203,17 +745,43 @@ pub fn thread(io: Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routi
     let listener = TcpListener::new(&io, 2047);
     listener.listen(1383).expect("moninj: cannot listen");
 
+    let shared = Urc::new(RefCell::new(Shared::new()));
+
+    {
+        let aux_mutex = aux_mutex.clone();
+        let routing_table = routing_table.clone();
+        let shared = shared.clone();
+        io.spawn(16384, move |io| {
+            poll_thread(&io, &aux_mutex, &routing_table, &shared)
+        });
+    }
+
     loop {
         let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
+        let shared = shared.clone();
         let stream = listener.accept().expect("moninj: cannot accept").into_handle();
         io.spawn(16384, move |io| {
-            let routing_table = routing_table.borrow();
             let mut stream = TcpStream::from_handle(&io, stream);
-            match connection_worker(&io, &aux_mutex, &routing_table, &mut stream) {
+            let mut client = Client {
+                id:         shared.borrow_mut().register(),
+                origin:     format!("{}", stream.remote_endpoint()),
+                probes:     BTreeMap::new(),
+                injections: BTreeMap::new(),
+                lease:      None
+            };
+            match connection_worker(&io, &aux_mutex, &routing_table, &shared, &mut client,
+                                    &mut stream) {
                 Ok(()) => {},
                 Err(err) => error!("moninj aborted: {}", err)
             }
+            if let Some(lease) = client.lease.take() {
+                if !lease.channels.is_empty() {
+                    info!("releasing {} leased overrides", lease.channels.len());
+                    release_overrides(&io, &aux_mutex, &routing_table, &shared, &client, &lease.channels);
+                }
+            }
+            shared.borrow_mut().unregister(&client);
             stream.close().expect("moninj: close socket");
         });
     }
@@ -1,5 +1,7 @@
 use core::mem;
 use alloc::{vec::Vec, string::String, collections::btree_map::BTreeMap};
+use board_misoc::config;
+use dma_store;
 
 const ALIGNMENT: usize = 64;
 
@@ -7,22 +9,44 @@ const ALIGNMENT: usize = 64;
 struct Entry {
     trace: Vec<u8>,
     padding_len: usize,
-    duration: u64
+    duration: u64,
+    borrowed: bool
 }
 
 #[derive(Debug)]
 pub struct Manager {
     entries: BTreeMap<String, Entry>,
     recording_name: String,
-    recording_trace: Vec<u8>
+    recording_trace: Vec<u8>,
+    persist: bool
 }
 
 impl Manager {
     pub fn new() -> Manager {
-        Manager {
+        let mut manager = Manager {
             entries: BTreeMap::new(),
             recording_name: String::new(),
             recording_trace: Vec::new(),
+            persist: config::read_str("dma_persist", |r| r == Ok("1"))
+        };
+        manager.load_persistent();
+        manager
+    }
+
+    fn load_persistent(&mut self) {
+        let mut traces = Vec::new();
+        let result = dma_store::for_each(|name, duration, trace| {
+            traces.push((String::from(name), duration, Vec::from(trace)))
+        });
+        match result {
+            Ok(()) => (),
+            Err(dma_store::Error::NoFlash) => (),
+            Err(err) => error!("cannot load stored DMA traces: {}", err)
+        }
+
+        for (name, duration, trace) in traces {
+            info!("loaded DMA trace {:?} from flash", name);
+            self.insert(name, trace, duration);
         }
     }
 
@@ -42,6 +66,21 @@ impl Manager {
         let mut trace = Vec::new();
         mem::swap(&mut self.recording_trace, &mut trace);
         trace.push(0);
+
+        let mut name = String::new();
+        mem::swap(&mut self.recording_name, &mut name);
+
+        if self.persist {
+            if let Err(err) = dma_store::write(&name, duration, &trace) {
+                error!("cannot store DMA trace {:?} in flash: {}", name, err)
+            }
+        }
+
+        self.insert(name, trace, duration);
+    }
+
+    // `trace` must include the terminating zero.
+    fn insert(&mut self, name: String, mut trace: Vec<u8>, duration: u64) {
         let data_len = trace.len();
 
         // Realign.
@@ -56,24 +95,71 @@ impl Manager {
             trace[data_len + padding - i] = trace[data_len - i]
         }
 
-        let mut name = String::new();
-        mem::swap(&mut self.recording_name, &mut name);
         self.entries.insert(name, Entry {
             trace: trace,
             padding_len: padding,
-            duration: duration
+            duration: duration,
+            borrowed: false
         });
     }
 
     pub fn erase(&mut self, name: &str) {
         self.entries.remove(name);
+
+        if self.persist {
+            match dma_store::remove(name) {
+                Ok(()) | Err(dma_store::Error::TraceNotFound) => (),
+                Err(err) => error!("cannot remove DMA trace {:?} from flash: {}", name, err)
+            }
+        }
     }
 
-    pub fn with_trace<F, R>(&self, name: &str, f: F) -> R
+    // The kernel keeps the trace after retrieving it, so it is marked as borrowed
+    // until the kernel CPU is stopped.
+    pub fn with_trace<F, R>(&mut self, name: &str, f: F) -> R
             where F: FnOnce(Option<&[u8]>, u64) -> R {
-        match self.entries.get(name) {
-            Some(entry) => f(Some(&entry.trace[entry.padding_len..]), entry.duration),
+        match self.entries.get_mut(name) {
+            Some(entry) => {
+                entry.borrowed = true;
+                f(Some(&entry.trace[entry.padding_len..]), entry.duration)
+            }
             None => f(None, 0)
         }
     }
+
+    pub unsafe fn unborrow(&mut self) {
+        for (_name, entry) in self.entries.iter_mut() {
+            entry.borrowed = false;
+        }
+    }
+
+    // Yields the name, length, duration and borrowed status of each trace.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(&'a str, usize, u64, bool)> + 'a {
+        self.entries.iter().map(|(name, entry)| {
+            (name.as_str(), entry.trace.len() - entry.padding_len, entry.duration, entry.borrowed)
+        })
+    }
+
+    // Unlike `erase`, leaves traces the kernel may still play back, and the copies
+    // stored in flash, untouched.
+    pub fn remove(&mut self, name: &str) -> Result<(), ()> {
+        match self.entries.get(name) {
+            Some(entry) if entry.borrowed => return Err(()),
+            _ => ()
+        }
+        self.entries.remove(name);
+        Ok(())
+    }
+
+    // Returns the number of borrowed traces that were kept.
+    pub fn clear(&mut self) -> usize {
+        let unborrowed = self.entries.iter()
+            .filter(|&(_name, entry)| !entry.borrowed)
+            .map(|(name, _entry)| name.clone())
+            .collect::<Vec<_>>();
+        for name in unborrowed {
+            self.entries.remove(&name);
+        }
+        self.entries.len()
+    }
 }
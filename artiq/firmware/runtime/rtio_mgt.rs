@@ -1,9 +1,10 @@
 use core::cell::RefCell;
+use alloc::vec::Vec;
 use urc::Urc;
-use board_misoc::csr;
-#[cfg(has_drtio)]
-use board_misoc::clock;
+use board_misoc::{csr, clock};
 use board_artiq::drtio_routing;
+use proto_artiq::drtioaux_proto::LinkStats;
+use session_proto::{RtioEvent, RtioEventKind};
 use sched::Io;
 use sched::Mutex;
 const ASYNC_ERROR_COLLISION: u8 = 1 << 0;
@@ -13,6 +14,7 @@ const ASYNC_ERROR_SEQUENCE_ERROR: u8 = 1 << 2;
 #[cfg(has_drtio)]
 pub mod drtio {
     use super::*;
+    use crc;
     use drtioaux;
 
     pub fn startup(io: &Io, aux_mutex: &Mutex,
@@ -22,11 +24,32 @@ pub mod drtio {
         let routing_table = routing_table.clone();
         let up_destinations = up_destinations.clone();
         io.spawn(4096, move |io| {
-            let routing_table = routing_table.borrow();
             link_thread(io, &aux_mutex, &routing_table, &up_destinations);
         });
     }
 
+    // First hops of the routing table still applied to the interconnect, set
+    // when a new table is installed and taken by the link thread when it
+    // pushes the new table to the satellites.
+    static mut PREVIOUS_FIRST_HOPS: Option<[u8; drtio_routing::DEST_COUNT]> = None;
+
+    pub fn set_routing_table(io: &Io, routing_table: &RefCell<drtio_routing::RoutingTable>,
+            new_table: drtio_routing::RoutingTable) -> Result<(), &'static str> {
+        let mut routing_table = io.until_ok(|| routing_table.try_borrow_mut()).map_err(|_| "interrupted")?;
+        unsafe {
+            if PREVIOUS_FIRST_HOPS.is_none() {
+                let mut first_hops = [drtio_routing::INVALID_HOP; drtio_routing::DEST_COUNT];
+                for (first_hop, hops) in first_hops.iter_mut().zip(routing_table.0.iter()) {
+                    *first_hop = hops[0];
+                }
+                PREVIOUS_FIRST_HOPS = Some(first_hops);
+            }
+        }
+        *routing_table = new_table;
+        info!("routing table changed");
+        Ok(())
+    }
+
     fn link_rx_up(linkno: u8) -> bool {
         let linkno = linkno as usize;
         unsafe {
@@ -52,11 +75,29 @@ pub mod drtio {
         }
     }
 
+    // The caller must hold the aux mutex.
+    fn aux_transact_locked(io: &Io, linkno: u8, request: &drtioaux::Packet)
+            -> Result<drtioaux::Packet, &'static str> {
+        aux_transact_locked_timeout(io, linkno, request, 200)
+    }
+
+    fn aux_transact_locked_timeout(io: &Io, linkno: u8, request: &drtioaux::Packet, timeout: u32)
+            -> Result<drtioaux::Packet, &'static str> {
+        drtioaux::send_waiting(linkno, request, || io.relinquish().unwrap())
+            .map_err(|_| "aux packet not acknowledged")?;
+        recv_aux_timeout(io, linkno, timeout)
+    }
+
     pub fn aux_transact(io: &Io, aux_mutex: &Mutex,
             linkno: u8, request: &drtioaux::Packet) -> Result<drtioaux::Packet, &'static str> {
         let _lock = aux_mutex.lock(io).unwrap();
-        drtioaux::send(linkno, request).unwrap();
-        recv_aux_timeout(io, linkno, 200)
+        aux_transact_locked(io, linkno, request)
+    }
+
+    fn aux_transact_timeout(io: &Io, aux_mutex: &Mutex,
+            linkno: u8, request: &drtioaux::Packet, timeout: u32) -> Result<drtioaux::Packet, &'static str> {
+        let _lock = aux_mutex.lock(io).unwrap();
+        aux_transact_locked_timeout(io, linkno, request, timeout)
     }
 
     fn ping_remote(io: &Io, aux_mutex: &Mutex, linkno: u8) -> u32 {
@@ -69,9 +110,12 @@ pub mod drtio {
             if count > 100 {
                 return 0;
             }
+            let sent_at = clock::get_us();
             let reply = aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::EchoRequest);
             match reply {
                 Ok(drtioaux::Packet::EchoReply) => {
+                    let latency = (clock::get_us() - sent_at) as u32;
+                    drtioaux::update_stats(linkno, |stats| stats.ping_latency_us = latency);
                     // make sure receive buffer is drained
                     let max_time = clock::get_ms() + 200;
                     loop {
@@ -88,6 +132,18 @@ pub mod drtio {
         }
     }
 
+    fn enable_sequencing(io: &Io, aux_mutex: &Mutex, linkno: u8) {
+        // Satellites without support for sequencing do not reply.
+        let _lock = aux_mutex.lock(io).unwrap();
+        match aux_transact_locked(io, linkno, &drtioaux::Packet::AuxSequencingRequest) {
+            Ok(drtioaux::Packet::AuxSequencingAck) => {
+                drtioaux::set_sequencing(linkno, true);
+                info!("[LINK#{}] aux packet retransmission enabled", linkno);
+            },
+            _ => info!("[LINK#{}] remote does not support aux packet retransmission", linkno)
+        }
+    }
+
     fn sync_tsc(io: &Io, aux_mutex: &Mutex, linkno: u8) -> Result<(), &'static str> {
         let _lock = aux_mutex.lock(io).unwrap();
 
@@ -105,15 +161,46 @@ pub mod drtio {
         }
     }
 
+    fn routing_transact(io: &Io, aux_mutex: &Mutex, linkno: u8, request: &drtioaux::Packet)
+            -> Result<(), &'static str> {
+        match aux_transact(io, aux_mutex, linkno, request)? {
+            drtioaux::Packet::RoutingAck => Ok(()),
+            drtioaux::Packet::RoutingNack => Err("routing table rejected"),
+            _ => Err("unexpected reply")
+        }
+    }
+
+    // Only the routed destinations are sent, as many per packet as fit; the
+    // satellite switches to the new table once it has received all of them.
+    // Satellites that do not acknowledge RoutingTableStart get one path per packet.
     fn load_routing_table(io: &Io, aux_mutex: &Mutex, linkno: u8, routing_table: &drtio_routing::RoutingTable)
             -> Result<(), &'static str> {
-        for i in 0..drtio_routing::DEST_COUNT {
-            let reply = aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::RoutingSetPath {
-                destination: i as u8,
-                hops: routing_table.0[i]
-            })?;
-            if reply != drtioaux::Packet::RoutingAck {
-                return Err("unexpected reply");
+        if routing_transact(io, aux_mutex, linkno, &drtioaux::Packet::RoutingTableStart).is_err() {
+            return load_routing_table_paths(io, aux_mutex, linkno, routing_table)
+        }
+        let mut destination = 0;
+        while destination < drtio_routing::DEST_COUNT {
+            let mut data = [0; drtioaux::ROUTING_CHUNK_MAX];
+            let (length, next) = routing_table.write_entries(destination, &mut data);
+            if length > 0 {
+                routing_transact(io, aux_mutex, linkno, &drtioaux::Packet::RoutingTableChunk {
+                    length: length as u16,
+                    data: data
+                })?;
+            }
+            destination = next;
+        }
+        routing_transact(io, aux_mutex, linkno, &drtioaux::Packet::RoutingTableCommit)
+    }
+
+    fn load_routing_table_paths(io: &Io, aux_mutex: &Mutex, linkno: u8, routing_table: &drtio_routing::RoutingTable)
+            -> Result<(), &'static str> {
+        for destination in 0..drtio_routing::DEST_COUNT {
+            if routing_table.path(destination as u8).is_some() {
+                routing_transact(io, aux_mutex, linkno, &drtioaux::Packet::RoutingSetPath {
+                    destination: destination as u8,
+                    hops: routing_table.0[destination]
+                })?;
             }
         }
         Ok(())
@@ -162,12 +249,15 @@ pub mod drtio {
             error!("[LINK#{}] error(s) found (0x{:02x}):", linkno, errors);
             if errors & 1 != 0 {
                 error!("[LINK#{}] received packet of an unknown type", linkno);
+                drtioaux::update_stats(linkno, |stats| stats.unknown_packets += 1);
             }
             if errors & 2 != 0 {
                 error!("[LINK#{}] received truncated packet", linkno);
+                drtioaux::update_stats(linkno, |stats| stats.truncated_packets += 1);
             }
             if errors & 4 != 0 {
                 error!("[LINK#{}] timeout attempting to get remote buffer space", linkno);
+                drtioaux::update_stats(linkno, |stats| stats.buffer_space_timeouts += 1);
             }
         }
     }
@@ -180,9 +270,11 @@ pub mod drtio {
         if up {
             drtio_routing::interconnect_enable(routing_table, 0, destination);
             info!("[DEST#{}] destination is up", destination);
+            push_event(destination, RtioEventKind::DestinationUp);
         } else {
             drtio_routing::interconnect_disable(destination);
             info!("[DEST#{}] destination is down", destination);
+            push_event(destination, RtioEventKind::DestinationDown);
         }
     }
 
@@ -217,14 +309,26 @@ pub mod drtio {
                             Ok(drtioaux::Packet::DestinationSequenceErrorReply { channel }) => {
                                 error!("[DEST#{}] RTIO sequence error involving channel 0x{:04x}", destination, channel);
                                 unsafe { SEEN_ASYNC_ERRORS |= ASYNC_ERROR_SEQUENCE_ERROR };
+                                push_event(destination, RtioEventKind::AsyncError {
+                                    errors: ASYNC_ERROR_SEQUENCE_ERROR,
+                                    channel: channel
+                                });
                             }
                             Ok(drtioaux::Packet::DestinationCollisionReply { channel }) => {
                                 error!("[DEST#{}] RTIO collision involving channel 0x{:04x}", destination, channel);
                                 unsafe { SEEN_ASYNC_ERRORS |= ASYNC_ERROR_COLLISION };
+                                push_event(destination, RtioEventKind::AsyncError {
+                                    errors: ASYNC_ERROR_COLLISION,
+                                    channel: channel
+                                });
                             }
                             Ok(drtioaux::Packet::DestinationBusyReply { channel }) => {
                                 error!("[DEST#{}] RTIO busy error involving channel 0x{:04x}", destination, channel);
                                 unsafe { SEEN_ASYNC_ERRORS |= ASYNC_ERROR_BUSY };
+                                push_event(destination, RtioEventKind::AsyncError {
+                                    errors: ASYNC_ERROR_BUSY,
+                                    channel: channel
+                                });
                             }
                             Ok(packet) => error!("[DEST#{}] received unexpected aux packet: {:?}", destination, packet),
                             Err(e) => error!("[DEST#{}] communication failed ({})", destination, e)
@@ -252,11 +356,53 @@ pub mod drtio {
         }
     }
 
+    // Pushes a new routing table to the satellites of the up links and to the
+    // local interconnect. Destinations whose first hop changed, or that are no
+    // longer routed through an up link, go down; the survey then brings up
+    // those that are reachable, initializing their buffer space again.
+    fn reload_routing_table(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+            previous_first_hops: &[u8; drtio_routing::DEST_COUNT], up_links: &[bool],
+            up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
+        for linkno in 0..csr::DRTIO.len() {
+            if !up_links[linkno] {
+                continue
+            }
+            let linkno = linkno as u8;
+            if let Err(e) = load_routing_table(io, aux_mutex, linkno, routing_table) {
+                error!("[LINK#{}] failed to load routing table ({})", linkno, e);
+            }
+            // Satellites apply their routing table to the interconnect on rank changes.
+            if let Err(e) = set_rank(io, aux_mutex, linkno, 1) {
+                error!("[LINK#{}] failed to set rank ({})", linkno, e);
+            }
+        }
+        drtio_routing::interconnect_enable_all(routing_table, 0);
+        for destination in 0..drtio_routing::DEST_COUNT {
+            let hop = routing_table.0[destination][0];
+            let destination = destination as u8;
+            if !destination_up(up_destinations, destination) {
+                continue
+            }
+            let reachable = hop == 0 ||
+                (hop != drtio_routing::INVALID_HOP && hop as usize <= csr::DRTIO.len() &&
+                 up_links[hop as usize - 1]);
+            if !reachable || hop != previous_first_hops[destination as usize] {
+                destination_set_up(routing_table, up_destinations, destination, false);
+            }
+        }
+    }
+
     pub fn link_thread(io: Io, aux_mutex: &Mutex,
-            routing_table: &drtio_routing::RoutingTable,
+            routing_table: &RefCell<drtio_routing::RoutingTable>,
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
         let mut up_links = [false; csr::DRTIO.len()];
         loop {
+            // Not held while sleeping, so that the table can be replaced.
+            let routing_table = routing_table.borrow();
+            if let Some(previous_first_hops) = unsafe { PREVIOUS_FIRST_HOPS.take() } {
+                reload_routing_table(&io, aux_mutex, &routing_table, &previous_first_hops,
+                                     &up_links, up_destinations);
+            }
             for linkno in 0..csr::DRTIO.len() {
                 let linkno = linkno as u8;
                 if up_links[linkno as usize] {
@@ -267,6 +413,8 @@ pub mod drtio {
                     } else {
                         info!("[LINK#{}] link is down", linkno);
                         up_links[linkno as usize] = false;
+                        let _lock = aux_mutex.lock(&io).unwrap();
+                        drtioaux::set_link_up(linkno, false);
                     }
                 } else {
                     /* link was previously down */
@@ -276,10 +424,15 @@ pub mod drtio {
                         if ping_count > 0 {
                             info!("[LINK#{}] remote replied after {} packets", linkno, ping_count);
                             up_links[linkno as usize] = true;
+                            {
+                                let _lock = aux_mutex.lock(&io).unwrap();
+                                drtioaux::set_link_up(linkno, true);
+                            }
+                            enable_sequencing(&io, aux_mutex, linkno);
                             if let Err(e) = sync_tsc(&io, aux_mutex, linkno) {
                                 error!("[LINK#{}] failed to sync TSC ({})", linkno, e);
                             }
-                            if let Err(e) = load_routing_table(&io, aux_mutex, linkno, routing_table) {
+                            if let Err(e) = load_routing_table(&io, aux_mutex, linkno, &routing_table) {
                                 error!("[LINK#{}] failed to load routing table ({})", linkno, e);
                             }
                             if let Err(e) = set_rank(&io, aux_mutex, linkno, 1) {
@@ -292,11 +445,212 @@ pub mod drtio {
                     }
                 }
             }
-            destination_survey(&io, aux_mutex, routing_table, &up_links, up_destinations);
+            destination_survey(&io, aux_mutex, &routing_table, &up_links, up_destinations);
+            drop(routing_table);
             io.sleep(200).unwrap();
         }
     }
 
+    pub fn link_stats() -> Vec<LinkStats> {
+        (0..csr::DRTIO.len()).map(|linkno| drtioaux::stats(linkno as u8)).collect()
+    }
+
+    fn destination_link(routing_table: &drtio_routing::RoutingTable, destination: u8)
+            -> Result<u8, &'static str> {
+        let hop = routing_table.0[destination as usize][0];
+        if hop == 0 || hop as usize > csr::DRTIO.len() {
+            return Err("destination is not a satellite")
+        }
+        Ok(hop - 1)
+    }
+
+    // Returns the statistics of all aux links of a satellite, starting with its uplink.
+    pub fn remote_link_stats(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+            destination: u8) -> Result<Vec<LinkStats>, &'static str> {
+        let linkno = destination_link(routing_table, destination)?;
+        let mut stats = Vec::new();
+        loop {
+            let reply = aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::LinkStatsRequest {
+                destination: destination,
+                linkno: stats.len() as u8
+            })?;
+            match reply {
+                drtioaux::Packet::LinkStatsReply { links, stats: link_stats } => {
+                    if stats.len() >= links as usize {
+                        return Ok(stats)
+                    }
+                    stats.push(link_stats);
+                },
+                _ => return Err("unexpected reply")
+            }
+        }
+    }
+
+    fn bulk_reply(reply: drtioaux::Packet) -> Result<(u32, u32), &'static str> {
+        match reply {
+            drtioaux::Packet::BulkReply { status: drtioaux::BulkStatus::Ok, length, crc } =>
+                Ok((length, crc)),
+            drtioaux::Packet::BulkReply { status, .. } => Err(status.as_str()),
+            _ => Err("unexpected reply")
+        }
+    }
+
+    // Uploads a request of the given kind to a satellite, and returns its response.
+    pub fn bulk_transfer(io: &Io, aux_mutex: &Mutex, bulk_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+            destination: u8, kind: u8, request: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let linkno = destination_link(routing_table, destination)?;
+        // The satellite holds a single transfer; the aux link stays available
+        // to other threads between packets.
+        let _lock = bulk_mutex.lock(io).unwrap();
+
+        bulk_reply(aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::BulkStart {
+            destination: destination,
+            kind: kind,
+            length: request.len() as u32
+        })?)?;
+        for (i, chunk) in request.chunks(drtioaux::BULK_CHUNK_MAX).enumerate() {
+            let mut data = [0; drtioaux::BULK_CHUNK_MAX];
+            data[..chunk.len()].copy_from_slice(chunk);
+            bulk_reply(aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::BulkChunk {
+                destination: destination,
+                offset: (i * drtioaux::BULK_CHUNK_MAX) as u32,
+                length: chunk.len() as u16,
+                data: data
+            })?)?;
+        }
+        let (length, crc) = bulk_reply(aux_transact_timeout(io, aux_mutex, linkno, &drtioaux::Packet::BulkEnd {
+            destination: destination,
+            crc: crc::crc32::checksum_ieee(request)
+        }, drtioaux::BULK_END_TIMEOUT_MS)?)?;
+
+        let mut response = Vec::with_capacity(length as usize);
+        while response.len() < length as usize {
+            let reply = aux_transact(io, aux_mutex, linkno, &drtioaux::Packet::BulkReadRequest {
+                destination: destination,
+                offset: response.len() as u32
+            })?;
+            match reply {
+                drtioaux::Packet::BulkReadReply { length: 0, .. } =>
+                    return Err("response truncated"),
+                drtioaux::Packet::BulkReadReply { length, data } =>
+                    response.extend_from_slice(&data[..length as usize]),
+                _ => return Err("unexpected reply")
+            }
+        }
+        if crc::crc32::checksum_ieee(&response) != crc {
+            return Err("response checksum mismatch")
+        }
+        Ok(response)
+    }
+
+    const FIRMWARE_WRITE_TIMEOUT_MS: u64 = 120_000;
+
+    // Uploads a firmware image, with the header checked by the bootloader, to a
+    // satellite and waits until the satellite has written it to its boot flash.
+    pub fn firmware_update(io: &Io, aux_mutex: &Mutex, bulk_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+            destination: u8, image: &[u8]) -> Result<(), &'static str> {
+        let linkno = destination_link(routing_table, destination)?;
+        bulk_transfer(io, aux_mutex, bulk_mutex, routing_table, destination, drtioaux::BULK_FIRMWARE, image)?;
+
+        let deadline = clock::get_ms() + FIRMWARE_WRITE_TIMEOUT_MS;
+        loop {
+            io.sleep(100).unwrap();
+            let reply = aux_transact(io, aux_mutex, linkno,
+                &drtioaux::Packet::FirmwareStatusRequest { destination: destination });
+            match reply {
+                Ok(drtioaux::Packet::FirmwareStatusReply { status: drtioaux::BulkStatus::Busy, written }) =>
+                    debug!("[DEST#{}] {} bytes of firmware written", destination, written),
+                Ok(drtioaux::Packet::FirmwareStatusReply { status: drtioaux::BulkStatus::Ok, .. }) =>
+                    return Ok(()),
+                Ok(drtioaux::Packet::FirmwareStatusReply { status, .. }) =>
+                    return Err(status.as_str()),
+                Ok(_) => return Err("unexpected reply"),
+                Err(e) => {
+                    if clock::get_ms() > deadline {
+                        return Err(e)
+                    }
+                    // The satellite does not answer while it erases a sector;
+                    // drop its late reply so that it is not taken for the next one.
+                    io.sleep(1000).unwrap();
+                    let _lock = aux_mutex.lock(io).unwrap();
+                    while let Ok(Some(_)) = drtioaux::recv(linkno) {}
+                }
+            }
+        }
+    }
+
+    pub fn reboot(io: &Io, aux_mutex: &Mutex, routing_table: &drtio_routing::RoutingTable,
+            destination: u8) -> Result<(), &'static str> {
+        let linkno = destination_link(routing_table, destination)?;
+        match aux_transact(io, aux_mutex, linkno,
+                &drtioaux::Packet::RebootRequest { destination: destination })? {
+            drtioaux::Packet::RebootReply { succeeded: true } => Ok(()),
+            drtioaux::Packet::RebootReply { succeeded: false } => Err("firmware is being written"),
+            _ => Err("unexpected reply")
+        }
+    }
+
+    // Gives the next destination number to the satellite reached through `hops`,
+    // then to the satellites behind each of its repeaters in turn.
+    fn discover_subtree(io: &Io, aux_mutex: &Mutex, routing_table: &mut drtio_routing::RoutingTable,
+            next_destination: &mut usize, hops: [u8; drtio_routing::MAX_HOPS], rank: usize)
+            -> Result<(), &'static str> {
+        if *next_destination >= drtio_routing::DEST_COUNT {
+            return Err("too many destinations")
+        }
+        let destination = *next_destination;
+        *next_destination += 1;
+        routing_table.0[destination] = hops;
+
+        let reply = aux_transact(io, aux_mutex, hops[0] - 1,
+            &drtioaux::Packet::TopologyRequest { hops: hops });
+        let (repeaters, up) = match reply {
+            Ok(drtioaux::Packet::TopologyReply { repeaters, up }) => (repeaters, up),
+            Ok(_) => return Err("unexpected reply"),
+            Err(e) => {
+                // e.g. a satellite with older firmware, whose repeaters are then not discovered
+                warn!("[DEST#{}] topology request failed ({})", destination, e);
+                return Ok(())
+            }
+        };
+        for repno in 0..repeaters.min(32) {
+            if up & (1 << repno) == 0 {
+                continue
+            }
+            if rank + 1 >= drtio_routing::MAX_HOPS {
+                return Err("too many hops")
+            }
+            let mut child_hops = hops;
+            child_hops[rank] = repno + 1;
+            child_hops[rank + 1] = 0;
+            discover_subtree(io, aux_mutex, routing_table, next_destination, child_hops, rank + 1)?;
+        }
+        Ok(())
+    }
+
+    // Builds a routing table for the satellites that are up. Destinations are
+    // numbered depth-first: the local RTIO core is 0, followed by the satellite
+    // on link 0 and the satellites behind its repeaters, then link 1, etc.
+    pub fn discover_topology(io: &Io, aux_mutex: &Mutex) -> Result<drtio_routing::RoutingTable, &'static str> {
+        if drtio_routing::DEST_COUNT == 0 {
+            return Err("DRTIO routing is not supported")
+        }
+        let mut routing_table = drtio_routing::RoutingTable::default_empty();
+        routing_table.0[0][0] = 0;
+        let mut next_destination = 1;
+        for linkno in 0..csr::DRTIO.len() {
+            if !link_rx_up(linkno as u8) {
+                continue
+            }
+            let mut hops = [drtio_routing::INVALID_HOP; drtio_routing::MAX_HOPS];
+            hops[0] = linkno as u8 + 1;
+            hops[1] = 0;
+            discover_subtree(io, aux_mutex, &mut routing_table, &mut next_destination, hops, 1)?;
+        }
+        info!("discovered routing table: {}", routing_table);
+        Ok(routing_table)
+    }
+
     pub fn reset(io: &Io, aux_mutex: &Mutex) {
         for linkno in 0..csr::DRTIO.len() {
             unsafe {
@@ -333,10 +687,94 @@ pub mod drtio {
         _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         _up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {}
     pub fn reset(_io: &Io, _aux_mutex: &Mutex) {}
+
+    pub fn link_stats() -> Vec<LinkStats> {
+        Vec::new()
+    }
+
+    pub fn remote_link_stats(_io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+            _destination: u8) -> Result<Vec<LinkStats>, &'static str> {
+        Err("DRTIO is not supported")
+    }
+
+    pub fn bulk_transfer(_io: &Io, _aux_mutex: &Mutex, _bulk_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+            _destination: u8, _kind: u8, _request: &[u8]) -> Result<Vec<u8>, &'static str> {
+        Err("DRTIO is not supported")
+    }
+
+    pub fn firmware_update(_io: &Io, _aux_mutex: &Mutex, _bulk_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+            _destination: u8, _image: &[u8]) -> Result<(), &'static str> {
+        Err("DRTIO is not supported")
+    }
+
+    pub fn reboot(_io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
+            _destination: u8) -> Result<(), &'static str> {
+        Err("DRTIO is not supported")
+    }
+
+    pub fn discover_topology(_io: &Io, _aux_mutex: &Mutex) -> Result<drtio_routing::RoutingTable, &'static str> {
+        Err("DRTIO is not supported")
+    }
+
+    pub fn set_routing_table(_io: &Io, _routing_table: &RefCell<drtio_routing::RoutingTable>,
+            _new_table: drtio_routing::RoutingTable) -> Result<(), &'static str> {
+        Err("DRTIO is not supported")
+    }
 }
 
 static mut SEEN_ASYNC_ERRORS: u8 = 0;
 
+const EVENT_QUEUE_LEN: usize = 64;
+
+// Destination and async error events, kept until a host session takes them.
+// The oldest events are dropped when the queue is full.
+struct EventQueue {
+    events:  [RtioEvent; EVENT_QUEUE_LEN],
+    first:   usize,
+    len:     usize,
+    dropped: u32
+}
+
+static mut EVENTS: EventQueue = EventQueue {
+    events:  [RtioEvent { timestamp_ms: 0, destination: 0, kind: RtioEventKind::DestinationUp };
+              EVENT_QUEUE_LEN],
+    first:   0,
+    len:     0,
+    dropped: 0
+};
+
+fn push_event(destination: u8, kind: RtioEventKind) {
+    let event = RtioEvent {
+        timestamp_ms: clock::get_ms(),
+        destination: destination,
+        kind: kind
+    };
+    unsafe {
+        if EVENTS.len == EVENT_QUEUE_LEN {
+            EVENTS.first = (EVENTS.first + 1) % EVENT_QUEUE_LEN;
+            EVENTS.len -= 1;
+            EVENTS.dropped += 1;
+        }
+        EVENTS.events[(EVENTS.first + EVENTS.len) % EVENT_QUEUE_LEN] = event;
+        EVENTS.len += 1;
+    }
+}
+
+// Returns the queued events, oldest first, and the number of events dropped
+// since the last call, and empties the queue.
+pub fn take_events() -> (Vec<RtioEvent>, u32) {
+    unsafe {
+        let events = (0..EVENTS.len)
+            .map(|i| EVENTS.events[(EVENTS.first + i) % EVENT_QUEUE_LEN])
+            .collect();
+        let dropped = EVENTS.dropped;
+        EVENTS.first = 0;
+        EVENTS.len = 0;
+        EVENTS.dropped = 0;
+        (events, dropped)
+    }
+}
+
 pub unsafe fn get_async_errors() -> u8 {
     let errors = SEEN_ASYNC_ERRORS;
     SEEN_ASYNC_ERRORS = 0;
@@ -349,16 +787,28 @@ fn async_error_thread(io: Io) {
             io.until(|| csr::rtio_core::async_error_read() != 0).unwrap();
             let errors = csr::rtio_core::async_error_read();
             if errors & ASYNC_ERROR_COLLISION != 0 {
-                error!("RTIO collision involving channel {}",
-                       csr::rtio_core::collision_channel_read());
+                let channel = csr::rtio_core::collision_channel_read();
+                error!("RTIO collision involving channel {}", channel);
+                push_event(0, RtioEventKind::AsyncError {
+                    errors: ASYNC_ERROR_COLLISION,
+                    channel: channel as u16
+                });
             }
             if errors & ASYNC_ERROR_BUSY != 0 {
-                error!("RTIO busy error involving channel {}",
-                       csr::rtio_core::busy_channel_read());
+                let channel = csr::rtio_core::busy_channel_read();
+                error!("RTIO busy error involving channel {}", channel);
+                push_event(0, RtioEventKind::AsyncError {
+                    errors: ASYNC_ERROR_BUSY,
+                    channel: channel as u16
+                });
             }
             if errors & ASYNC_ERROR_SEQUENCE_ERROR != 0 {
-                error!("RTIO sequence error involving channel {}",
-                       csr::rtio_core::sequence_error_channel_read());
+                let channel = csr::rtio_core::sequence_error_channel_read();
+                error!("RTIO sequence error involving channel {}", channel);
+                push_event(0, RtioEventKind::AsyncError {
+                    errors: ASYNC_ERROR_SEQUENCE_ERROR,
+                    channel: channel as u16
+                });
             }
             SEEN_ASYNC_ERRORS = errors;
             csr::rtio_core::async_error_write(errors);
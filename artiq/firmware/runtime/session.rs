@@ -1,4 +1,4 @@
-use core::{mem, str, cell::{Cell, RefCell}, fmt::Write as FmtWrite};
+use core::{mem, str, fmt, cell::{Cell, RefCell}, fmt::Write as FmtWrite};
 use alloc::{vec::Vec, string::String};
 use byteorder::{ByteOrder, NativeEndian};
 use cslice::CSlice;
@@ -10,13 +10,14 @@ use urc::Urc;
 use sched::{ThreadHandle, Io, Mutex, TcpListener, TcpStream, Error as SchedError};
 use rtio_clocking;
 use rtio_dma::Manager as DmaManager;
-use rtio_mgt::get_async_errors;
+use rtio_mgt::{self, get_async_errors};
 use cache::Cache;
 use kern_hwreq;
 use board_artiq::drtio_routing;
 
 use rpc_proto as rpc;
 use session_proto as host;
+use session_proto::KernelState;
 use kernel_proto as kern;
 
 #[derive(Fail, Debug)]
@@ -53,50 +54,55 @@ impl From<IoError<SchedError>> for Error<SchedError> {
     }
 }
 
+// Longest description of a kernel CPU message kept for watchdog reports.
+const WATCHDOG_MESSAGE_LIMIT: usize = 256;
+
 macro_rules! unexpected {
      ($($arg:tt)*) => (return Err(Error::Unexpected(format!($($arg)*))));
 }
 
-// Persistent state
+// Persistent state, shared with the management interface
 #[derive(Debug)]
-struct Congress {
-    cache: Cache,
-    dma_manager: DmaManager,
+pub struct Congress {
+    pub cache: RefCell<Cache>,
+    pub dma_manager: RefCell<DmaManager>,
     finished_cleanly: Cell<bool>
 }
 
 impl Congress {
-    fn new() -> Congress {
+    pub fn new() -> Congress {
         Congress {
-            cache: Cache::new(),
-            dma_manager: DmaManager::new(),
+            cache: RefCell::new(Cache::new()),
+            dma_manager: RefCell::new(DmaManager::new()),
             finished_cleanly: Cell::new(true)
         }
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum KernelState {
-    Absent,
-    Loaded,
-    Running,
-    RpcWait
+    // Must only be called once the kernel CPU is stopped.
+    unsafe fn unborrow(&self) {
+        self.cache.borrow_mut().unborrow();
+        self.dma_manager.borrow_mut().unborrow();
+    }
 }
 
 // Per-connection state
 #[derive(Debug)]
 struct Session<'a> {
-    congress: &'a mut Congress,
+    congress: &'a Congress,
     kernel_state: KernelState,
-    log_buffer: String
+    log_buffer: String,
+    watchdog: Option<kernel::Watchdog>,
+    last_message: String
 }
 
 impl<'a> Session<'a> {
-    fn new(congress: &mut Congress) -> Session {
+    fn new(congress: &Congress) -> Session {
         Session {
             congress: congress,
             kernel_state: KernelState::Absent,
-            log_buffer: String::new()
+            log_buffer: String::new(),
+            watchdog: None,
+            last_message: String::new()
         }
     }
 
@@ -107,6 +113,27 @@ impl<'a> Session<'a> {
         }
     }
 
+    fn feed_watchdog(&mut self) {
+        if let Some(ref mut watchdog) = self.watchdog {
+            watchdog.feed()
+        }
+    }
+
+    fn watchdog_expired(&self) -> bool {
+        self.watchdog.as_ref().map(|watchdog| watchdog.expired()).unwrap_or(false)
+    }
+
+    fn record_message(&mut self, message: &kern::Message) {
+        if self.watchdog.is_some() {
+            self.feed_watchdog();
+            self.last_message.clear();
+            // Formatting stops once the limit is reached, so that large messages
+            // (e.g. DMA traces) cost no more than small ones.
+            let _ = write!(TruncatingWriter::new(&mut self.last_message, WATCHDOG_MESSAGE_LIMIT),
+                           "{:?}", message);
+        }
+    }
+
     fn flush_log_buffer(&mut self) {
         if &self.log_buffer[self.log_buffer.len() - 1..] == "\n" {
             for line in self.log_buffer.lines() {
@@ -117,9 +144,34 @@ impl<'a> Session<'a> {
     }
 }
 
+struct TruncatingWriter<'a> {
+    buffer: &'a mut String,
+    limit: usize
+}
+
+impl<'a> TruncatingWriter<'a> {
+    fn new(buffer: &'a mut String, limit: usize) -> TruncatingWriter<'a> {
+        TruncatingWriter { buffer: buffer, limit: limit }
+    }
+}
+
+impl<'a> FmtWrite for TruncatingWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = s.len().min(self.limit.saturating_sub(self.buffer.len()));
+        while !s.is_char_boundary(end) {
+            end -= 1
+        }
+        self.buffer.push_str(&s[..end]);
+        if end < s.len() { Err(fmt::Error) } else { Ok(()) }
+    }
+}
+
 impl<'a> Drop for Session<'a> {
     fn drop(&mut self) {
-        unsafe { kernel::stop() }
+        unsafe {
+            kernel::stop();
+            self.congress.unborrow()
+        }
     }
 }
 
@@ -221,16 +273,24 @@ unsafe fn kern_load(io: &Io, session: &mut Session, library: &[u8])
     })
 }
 
-fn kern_run(session: &mut Session) -> Result<(), Error<SchedError>> {
+fn kern_run(session: &mut Session, watchdog_ms: Option<u64>) -> Result<(), Error<SchedError>> {
     if session.kernel_state != KernelState::Loaded {
         unexpected!("attempted to run a kernel while not in Loaded state")
     }
 
     session.kernel_state = KernelState::Running;
+    session.watchdog = watchdog_ms.map(kernel::Watchdog::new);
+    session.last_message.clear();
     // TODO: make this a separate request
     kern_acknowledge()
 }
 
+fn watchdog_config() -> Option<u64> {
+    config::read_str("kernel_watchdog_ms", |r| r.ok().and_then(|s| s.parse::<u32>().ok()))
+        .filter(|&timeout_ms| timeout_ms > 0)
+        .map(|timeout_ms| timeout_ms as u64)
+}
+
 fn process_host_message(io: &Io,
                         stream: &mut TcpStream,
                         session: &mut Session) -> Result<(), Error<SchedError>> {
@@ -254,10 +314,28 @@ fn process_host_message(io: &Io,
                 }
             },
         host::Request::RunKernel =>
-            match kern_run(session) {
+            match kern_run(session, watchdog_config()) {
                 Ok(()) => (),
                 Err(_) => host_write(stream, host::Reply::KernelStartupFailed)?
             },
+        host::Request::RunKernelWatchdog { timeout_ms } => {
+            let watchdog_ms = Some(timeout_ms as u64).filter(|&timeout_ms| timeout_ms > 0);
+            match kern_run(session, watchdog_ms) {
+                Ok(()) => (),
+                Err(_) => host_write(stream, host::Reply::KernelStartupFailed)?
+            }
+        }
+        host::Request::AbortKernel => {
+            let state = session.kernel_state;
+            unsafe { kernel::stop() }
+            session.kernel_state = KernelState::Absent;
+            unsafe { session.congress.unborrow() }
+
+            if state != KernelState::Absent {
+                warn!("kernel aborted by host in {:?} state", state);
+            }
+            host_write(stream, host::Reply::KernelAborted { state: state })?
+        }
 
         host::Request::RpcReply { tag } => {
             if session.kernel_state != KernelState::RpcWait {
@@ -288,7 +366,8 @@ fn process_host_message(io: &Io,
             })?;
             kern_send(io, &kern::RpcRecvReply(Ok(0)))?;
 
-            session.kernel_state = KernelState::Running
+            session.kernel_state = KernelState::Running;
+            session.feed_watchdog()
         }
 
         host::Request::RpcException {
@@ -319,7 +398,8 @@ fn process_host_message(io: &Io,
                 kern_send(io, &kern::RpcRecvReply(Err(exn)))?;
             }
 
-            session.kernel_state = KernelState::Running
+            session.kernel_state = KernelState::Running;
+            session.feed_watchdog()
         }
     }
 
@@ -327,11 +407,13 @@ fn process_host_message(io: &Io,
 }
 
 fn process_kern_message(io: &Io, aux_mutex: &Mutex,
-                        routing_table: &drtio_routing::RoutingTable,
+                        routing_table: &RefCell<drtio_routing::RoutingTable>,
                         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                         mut stream: Option<&mut TcpStream>,
                         session: &mut Session) -> Result<bool, Error<SchedError>> {
     kern_recv_notrace(io, |request| {
+        session.record_message(request);
+
         match (request, session.kernel_state) {
             (&kern::LoadReply(_), KernelState::Loaded) |
             (&kern::RpcRecvRequest(_), KernelState::RpcWait) => {
@@ -347,7 +429,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
 
         kern_recv_dotrace(request);
 
-        if kern_hwreq::process_kern_hwreq(io, aux_mutex, routing_table, up_destinations, request)? {
+        if kern_hwreq::process_kern_hwreq(io, aux_mutex, &routing_table.borrow(), up_destinations, request)? {
             return Ok(false)
         }
 
@@ -368,24 +450,24 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             }
 
             &kern::DmaRecordStart(name) => {
-                session.congress.dma_manager.record_start(name);
+                session.congress.dma_manager.borrow_mut().record_start(name);
                 kern_acknowledge()
             }
             &kern::DmaRecordAppend(data) => {
-                session.congress.dma_manager.record_append(data);
+                session.congress.dma_manager.borrow_mut().record_append(data);
                 kern_acknowledge()
             }
             &kern::DmaRecordStop { duration } => {
-                session.congress.dma_manager.record_stop(duration);
+                session.congress.dma_manager.borrow_mut().record_stop(duration);
                 cache::flush_l2_cache();
                 kern_acknowledge()
             }
             &kern::DmaEraseRequest { name } => {
-                session.congress.dma_manager.erase(name);
+                session.congress.dma_manager.borrow_mut().erase(name);
                 kern_acknowledge()
             }
             &kern::DmaRetrieveRequest { name } => {
-                session.congress.dma_manager.with_trace(name, |trace, duration| {
+                session.congress.dma_manager.borrow_mut().with_trace(name, |trace, duration| {
                     kern_send(io, &kern::DmaRetrieveReply {
                         trace:    trace,
                         duration: duration
@@ -414,7 +496,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             },
 
             &kern::CacheGetRequest { key } => {
-                let value = session.congress.cache.get(key);
+                let value = session.congress.cache.borrow_mut().get(key);
                 kern_send(io, &kern::CacheGetReply {
                     // Zing! This transmute is only safe because we dynamically track
                     // whether the kernel has borrowed any values from the cache.
@@ -423,14 +505,14 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             }
 
             &kern::CachePutRequest { key, value } => {
-                let succeeded = session.congress.cache.put(key, value).is_ok();
+                let succeeded = session.congress.cache.borrow_mut().put(key, value).is_ok();
                 kern_send(io, &kern::CachePutReply { succeeded: succeeded })
             }
 
             &kern::RunFinished => {
                 unsafe { kernel::stop() }
                 session.kernel_state = KernelState::Absent;
-                unsafe { session.congress.cache.unborrow() }
+                unsafe { session.congress.unborrow() }
 
                 match stream {
                     None => return Ok(true),
@@ -447,7 +529,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             } => {
                 unsafe { kernel::stop() }
                 session.kernel_state = KernelState::Absent;
-                unsafe { session.congress.cache.unborrow() }
+                unsafe { session.congress.unborrow() }
 
                 match stream {
                     None => {
@@ -486,10 +568,10 @@ fn process_kern_queued_rpc(stream: &mut TcpStream,
 }
 
 fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
-                      routing_table: &drtio_routing::RoutingTable,
+                      routing_table: &RefCell<drtio_routing::RoutingTable>,
                       up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                       stream: &mut TcpStream,
-                      congress: &mut Congress) -> Result<(), Error<SchedError>> {
+                      congress: &Congress) -> Result<(), Error<SchedError>> {
     let mut session = Session::new(congress);
 
     loop {
@@ -510,7 +592,30 @@ fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
         }
 
         if session.kernel_state == KernelState::Running {
-            if !rtio_clocking::crg::check() {
+            // The host reads these between RPCs, while it serves the kernel.
+            let (events, dropped) = rtio_mgt::take_events();
+            if !events.is_empty() || dropped != 0 {
+                host_write(stream, host::Reply::RtioEvents {
+                    events: &events,
+                    dropped: dropped
+                })?;
+            }
+
+            if session.watchdog_expired() {
+                let timeout_ms = session.watchdog.as_ref().unwrap().timeout_ms();
+                error!("kernel watchdog expired after {} ms, last message from kernel CPU: {}",
+                       timeout_ms, session.last_message);
+
+                unsafe { kernel::stop() }
+                session.kernel_state = KernelState::Absent;
+                unsafe { session.congress.unborrow() }
+                session.congress.finished_cleanly.set(false);
+
+                host_write(stream, host::Reply::WatchdogExpired {
+                    timeout_ms: timeout_ms as u32,
+                    last_message: &session.last_message
+                })?;
+            } else if !rtio_clocking::crg::check() {
                 host_write(stream, host::Reply::ClockFailure)?;
                 return Err(Error::ClockFailure)
             }
@@ -521,9 +626,9 @@ fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
 }
 
 fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
-                       routing_table: &drtio_routing::RoutingTable,
+                       routing_table: &RefCell<drtio_routing::RoutingTable>,
                        up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
-                       congress: &mut Congress,
+                       congress: &Congress,
                        config_key: &str) -> Result<(), Error<SchedError>> {
     let mut session = Session::new(congress);
 
@@ -537,7 +642,7 @@ fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
             _ => Err(Error::KernelNotFound)
         }
     })?;
-    kern_run(&mut session)?;
+    kern_run(&mut session, None)?;
 
     loop {
         if !rpc_queue::empty() {
@@ -575,13 +680,12 @@ fn respawn<F>(io: &Io, handle: &mut Option<ThreadHandle>, f: F)
 
 pub fn thread(io: Io, aux_mutex: &Mutex,
         routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
-        up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
+        up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+        congress: &Urc<Congress>) {
     let listener = TcpListener::new(&io, 65535);
     listener.listen(1381).expect("session: cannot listen");
     info!("accepting network sessions");
 
-    let congress = Urc::new(RefCell::new(Congress::new()));
-
     let mut kernel_thread = None;
     {
         let aux_mutex = aux_mutex.clone();
@@ -589,10 +693,8 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
         let up_destinations = up_destinations.clone();
         let congress = congress.clone();
         respawn(&io, &mut kernel_thread, move |io| {
-            let routing_table = routing_table.borrow();
-            let mut congress = congress.borrow_mut();
             info!("running startup kernel");
-            match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &mut congress, "startup_kernel") {
+            match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &congress, "startup_kernel") {
                 Ok(()) =>
                     info!("startup kernel finished"),
                 Err(Error::KernelNotFound) =>
@@ -635,10 +737,8 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
             let congress = congress.clone();
             let stream = stream.into_handle();
             respawn(&io, &mut kernel_thread, move |io| {
-                let routing_table = routing_table.borrow();
-                let mut congress = congress.borrow_mut();
                 let mut stream = TcpStream::from_handle(&io, stream);
-                match host_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &mut stream, &mut *congress) {
+                match host_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &mut stream, &congress) {
                     Ok(()) => (),
                     Err(Error::Protocol(host::Error::Io(IoError::UnexpectedEnd))) =>
                         info!("connection closed"),
@@ -662,9 +762,7 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
             let up_destinations = up_destinations.clone();
             let congress = congress.clone();
             respawn(&io, &mut kernel_thread, move |io| {
-                let routing_table = routing_table.borrow();
-                let mut congress = congress.borrow_mut();
-                match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &mut *congress, "idle_kernel") {
+                match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &congress, "idle_kernel") {
                     Ok(()) =>
                         info!("idle kernel finished, standing by"),
                     Err(Error::Protocol(host::Error::Io(
@@ -0,0 +1,131 @@
+use core::fmt;
+use crc;
+
+use board_misoc::spiflash;
+use board_artiq::drtioaux::{BulkStatus, BULK_CHUNK_MAX};
+
+// Largest request or response of a bulk transfer: a firmware image, with the
+// header checked by the bootloader.
+pub const BUFFER_SIZE: usize = spiflash::FIRMWARE_HEADER_SIZE + spiflash::FIRMWARE_MAX;
+
+static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+// A bulk transfer to this satellite. The request and then the response are
+// held in the same buffer, so a new request discards the previous response.
+pub struct Transfer {
+    buffer:   &'static mut [u8],
+    kind:     Option<u8>,
+    length:   usize,
+    received: usize,
+    response: usize
+}
+
+impl Transfer {
+    // There must be only one Transfer, as it owns the static buffer.
+    pub unsafe fn new() -> Transfer {
+        Transfer {
+            buffer:   &mut BUFFER[..],
+            kind:     None,
+            length:   0,
+            received: 0,
+            response: 0
+        }
+    }
+
+    pub fn start(&mut self, kind: u8, length: u32) -> BulkStatus {
+        self.kind = None;
+        self.response = 0;
+        if length as usize > self.buffer.len() {
+            return BulkStatus::TooLarge
+        }
+        self.kind = Some(kind);
+        self.length = length as usize;
+        self.received = 0;
+        BulkStatus::Ok
+    }
+
+    // Chunks must arrive in order; a retransmitted chunk is accepted again.
+    pub fn chunk(&mut self, offset: u32, data: &[u8]) -> BulkStatus {
+        let offset = offset as usize;
+        if self.kind.is_none() {
+            return BulkStatus::NoTransfer
+        }
+        if offset > self.received {
+            return BulkStatus::OutOfOrder
+        }
+        if offset + data.len() > self.length {
+            return BulkStatus::TooLarge
+        }
+        self.buffer[offset..offset + data.len()].copy_from_slice(data);
+        self.received = offset + data.len();
+        BulkStatus::Ok
+    }
+
+    // Checks the request and passes it to `handler`, which writes the
+    // response in place and returns its length. Returns the status, and the
+    // length and CRC of the response.
+    pub fn end<F>(&mut self, crc: u32, handler: F) -> (BulkStatus, u32, u32)
+        where F: FnOnce(u8, &mut [u8], usize) -> Result<usize, BulkStatus>
+    {
+        let kind = match self.kind.take() {
+            Some(kind) => kind,
+            None => return (BulkStatus::NoTransfer, 0, 0)
+        };
+        if self.received != self.length {
+            return (BulkStatus::OutOfOrder, 0, 0)
+        }
+        if crc::crc32::checksum_ieee(&self.buffer[..self.length]) != crc {
+            return (BulkStatus::ChecksumMismatch, 0, 0)
+        }
+        match handler(kind, self.buffer, self.length) {
+            Ok(length) => {
+                self.response = length;
+                let crc = crc::crc32::checksum_ieee(&self.buffer[..length]);
+                (BulkStatus::Ok, length as u32, crc)
+            },
+            Err(status) => (status, 0, 0)
+        }
+    }
+
+    // The request of the last transfer, valid until the next one starts if the
+    // handler did not write a response.
+    pub fn request(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+
+    // Copies the part of the response at `offset` into `data`, and returns its length.
+    pub fn read(&self, offset: u32, data: &mut [u8; BULK_CHUNK_MAX]) -> usize {
+        let offset = (offset as usize).min(self.response);
+        let length = (self.response - offset).min(BULK_CHUNK_MAX);
+        data[..length].copy_from_slice(&self.buffer[offset..offset + length]);
+        length
+    }
+}
+
+// Formats text responses into the transfer buffer.
+pub struct TextWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize
+}
+
+impl<'a> TextWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> TextWriter<'a> {
+        TextWriter { buffer: buffer, length: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+}
+
+impl<'a> fmt::Write for TextWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let bytes = s.as_bytes();
+        if self.length + bytes.len() > self.buffer.len() {
+            return Err(fmt::Error)
+        }
+        self.buffer[self.length..self.length + bytes.len()].copy_from_slice(bytes);
+        self.length += bytes.len();
+        Ok(())
+    }
+}
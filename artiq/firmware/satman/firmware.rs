@@ -0,0 +1,115 @@
+use crc;
+
+use board_misoc::{mem, cache, spiflash};
+use board_misoc::spiflash::{FIRMWARE_MAX, FIRMWARE_HEADER_SIZE};
+use board_artiq::drtioaux::BulkStatus;
+
+// Flash programmed at each step; aux packets are processed between steps.
+const PROGRAM_STEP: usize = 4096;
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+// Checks an image, header included, before anything is erased.
+pub fn check(image: &[u8]) -> Result<(), BulkStatus> {
+    if image.len() <= FIRMWARE_HEADER_SIZE {
+        return Err(BulkStatus::Rejected)
+    }
+    let length = read_u32(&image[0..]) as usize;
+    let expected_crc = read_u32(&image[4..]);
+    if length != image.len() - FIRMWARE_HEADER_SIZE || length > FIRMWARE_MAX {
+        error!("firmware length {} does not match image of {} bytes", length, image.len());
+        return Err(BulkStatus::Rejected)
+    }
+    let actual_crc = crc::crc32::checksum_ieee(&image[FIRMWARE_HEADER_SIZE..]);
+    if actual_crc != expected_crc {
+        error!("firmware CRC failed (actual {:08x}, expected {:08x})", actual_crc, expected_crc);
+        return Err(BulkStatus::ChecksumMismatch)
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Idle,
+    Erasing { offset: usize, length: usize },
+    Programming { offset: usize, length: usize },
+    Done(BulkStatus)
+}
+
+// Writes a checked image to the boot flash, one sector erase or program
+// step at a time, so that the satellite keeps answering aux packets.
+pub struct Update {
+    state: State
+}
+
+impl Update {
+    pub fn new() -> Update {
+        Update { state: State::Idle }
+    }
+
+    pub fn start(&mut self, length: usize) {
+        info!("writing firmware of {} bytes", length);
+        self.state = State::Erasing { offset: 0, length: length }
+    }
+
+    pub fn is_running(&self) -> bool {
+        match self.state {
+            State::Erasing { .. } | State::Programming { .. } => true,
+            _ => false
+        }
+    }
+
+    // Returns the status of the last update, and the number of bytes programmed.
+    pub fn status(&self) -> (BulkStatus, u32) {
+        match self.state {
+            State::Idle => (BulkStatus::NoTransfer, 0),
+            State::Erasing { .. } => (BulkStatus::Busy, 0),
+            State::Programming { offset, .. } => (BulkStatus::Busy, offset as u32),
+            State::Done(status) => (status, 0)
+        }
+    }
+
+    // `image` must be the one passed to `check`, and left untouched since.
+    pub fn step(&mut self, image: &[u8]) {
+        self.state = match self.state {
+            State::Erasing { offset, length } => {
+                unsafe { spiflash::erase_sector(mem::FLASH_BOOT_ADDRESS + offset) };
+                let offset = offset + spiflash::SECTOR_SIZE;
+                if offset < length {
+                    State::Erasing { offset: offset, length: length }
+                } else {
+                    State::Programming { offset: 0, length: length }
+                }
+            },
+            State::Programming { offset, length } => {
+                let end = (offset + PROGRAM_STEP).min(length);
+                unsafe { spiflash::write(mem::FLASH_BOOT_ADDRESS + offset, &image[offset..end]) };
+                if end < length {
+                    State::Programming { offset: end, length: length }
+                } else {
+                    cache::flush_l2_cache();
+                    State::Done(verify(&image[..length]))
+                }
+            },
+            state => state
+        }
+    }
+}
+
+fn verify(image: &[u8]) -> BulkStatus {
+    let flash = unsafe {
+        ::core::slice::from_raw_parts(mem::FLASH_BOOT_ADDRESS as *const u8, image.len())
+    };
+    match check(flash) {
+        Ok(()) if flash == image => {
+            info!("firmware written, reboot to start it");
+            BulkStatus::Ok
+        },
+        _ => {
+            error!("firmware readback failed");
+            BulkStatus::Failed
+        }
+    }
+}
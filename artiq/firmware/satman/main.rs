@@ -3,13 +3,16 @@
 
 #[macro_use]
 extern crate log;
+extern crate crc;
 #[macro_use]
 extern crate board_misoc;
 extern crate board_artiq;
 extern crate riscv;
 
 use core::convert::TryFrom;
-use board_misoc::{csr, ident, clock, uart_logger, i2c, pmp};
+use core::fmt::Write;
+use core::str;
+use board_misoc::{csr, ident, clock, uart_logger, i2c, pmp, config, spiflash};
 #[cfg(has_si5324)]
 use board_artiq::si5324;
 #[cfg(has_wrpll)]
@@ -21,6 +24,8 @@ use board_artiq::hmc830_7043;
 use riscv::register::{mcause, mepc, mtval};
 
 mod repeater;
+mod bulk;
+mod firmware;
 #[cfg(has_jdcg)]
 mod jdcg;
 #[cfg(any(has_ad9154, has_jdcg))]
@@ -75,14 +80,110 @@ macro_rules! forward {
     ($routing_table:expr, $destination:expr, $rank:expr, $repeaters:expr, $packet:expr) => {}
 }
 
+fn write_status<W: Write>(writer: &mut W, repeaters: &[repeater::Repeater],
+        routing_table: &drtio_routing::RoutingTable, rank: u8) -> core::fmt::Result {
+    writeln!(writer, "software ident {}", csr::CONFIG_IDENTIFIER_STR)?;
+    writeln!(writer, "gateware ident {}", ident::read(&mut [0; 64]))?;
+    writeln!(writer, "rank {}", rank)?;
+    for linkno in 0..csr::DRTIOAUX.len() {
+        let linkno = linkno as u8;
+        writeln!(writer, "link {}: {:?}, retransmission {}", linkno, drtioaux::stats(linkno),
+                 if drtioaux::sequencing_enabled(linkno) { "enabled" } else { "disabled" })?;
+    }
+    for (repno, rep) in repeaters.iter().enumerate() {
+        writeln!(writer, "repeater {}: {}", repno, if rep.is_up() { "up" } else { "down" })?;
+    }
+    writeln!(writer, "{}", routing_table)
+}
+
+// Splits a config request into the key and the value.
+fn config_request(request: &[u8]) -> Result<(&str, &[u8]), drtioaux::BulkStatus> {
+    let (key, value) = match request.iter().position(|&x| x == 0) {
+        Some(pos) => (&request[..pos], &request[pos + 1..]),
+        None => (request, &[][..])
+    };
+    let key = str::from_utf8(key).map_err(|_| drtioaux::BulkStatus::Rejected)?;
+    Ok((key, value))
+}
+
+fn config_status(err: config::Error) -> drtioaux::BulkStatus {
+    match err {
+        config::Error::KeyNotFound => drtioaux::BulkStatus::NotFound,
+        config::Error::InvalidValue(_) => drtioaux::BulkStatus::Rejected,
+        err => {
+            error!("config request failed: {}", err);
+            drtioaux::BulkStatus::Failed
+        }
+    }
+}
+
+fn process_bulk_request(kind: u8, buffer: &mut [u8], length: usize,
+        repeaters: &[repeater::Repeater], routing_table: &drtio_routing::RoutingTable,
+        rank: u8, update: &mut firmware::Update) -> Result<usize, drtioaux::BulkStatus> {
+    match kind {
+        drtioaux::BULK_ECHO => Ok(length),
+        drtioaux::BULK_STATUS => {
+            let mut writer = bulk::TextWriter::new(buffer);
+            write_status(&mut writer, repeaters, routing_table, rank)
+                .map_err(|_| drtioaux::BulkStatus::TooLarge)?;
+            Ok(writer.len())
+        },
+        drtioaux::BULK_CONFIG_READ => {
+            // The value is read after the key, then moved to the start of the buffer.
+            let value_length = {
+                let (request, response) = buffer.split_at_mut(length);
+                let (key, _) = config_request(request)?;
+                config::read(key, |result| {
+                    let value = result.map_err(config_status)?;
+                    if value.len() > response.len() {
+                        return Err(drtioaux::BulkStatus::TooLarge)
+                    }
+                    response[..value.len()].copy_from_slice(value);
+                    Ok(value.len())
+                })?
+            };
+            buffer.copy_within(length..length + value_length, 0);
+            Ok(value_length)
+        },
+        drtioaux::BULK_CONFIG_WRITE => {
+            let (key, value) = config_request(&buffer[..length])?;
+            config::validate(key, value).map_err(config_status)?;
+            config::write(key, value).map_err(config_status)?;
+            info!("config key {:?} written", key);
+            Ok(0)
+        },
+        drtioaux::BULK_CONFIG_REMOVE => {
+            let (key, _) = config_request(&buffer[..length])?;
+            config::remove(key).map_err(config_status)?;
+            info!("config key {:?} removed", key);
+            Ok(0)
+        },
+        drtioaux::BULK_FIRMWARE => {
+            // The image is left in the buffer and written from there.
+            firmware::check(&buffer[..length])?;
+            update.start(length);
+            Ok(0)
+        },
+        _ => Err(drtioaux::BulkStatus::UnknownKind)
+    }
+}
+
 fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
-        _routing_table: &mut drtio_routing::RoutingTable, _rank: &mut u8,
+        _routing_table: &mut drtio_routing::RoutingTable,
+        _new_routing_table: &mut Option<drtio_routing::RoutingTable>, _rank: &mut u8,
+        _bulk: &mut bulk::Transfer, _update: &mut firmware::Update,
         packet: drtioaux::Packet) -> Result<(), drtioaux::Error<!>> {
     // In the code below, *_chan_sel_write takes an u8 if there are fewer than 256 channels,
     // and u16 otherwise; hence the `as _` conversion.
     match packet {
         drtioaux::Packet::EchoRequest =>
             drtioaux::send(0, &drtioaux::Packet::EchoReply),
+        drtioaux::Packet::AuxSequencingRequest => {
+            drtioaux::send(0, &drtioaux::Packet::AuxSequencingAck)?;
+            drtioaux::set_sequencing(0, true);
+            info!("aux packet retransmission enabled on uplink");
+            Ok(())
+        },
         drtioaux::Packet::ResetRequest => {
             info!("resetting RTIO");
             drtiosat_reset(true);
@@ -173,6 +274,51 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
             drtioaux::send(0, &drtioaux::Packet::RoutingAck)
         }
         #[cfg(has_drtio_routing)]
+        drtioaux::Packet::RoutingTableStart => {
+            *_new_routing_table = Some(drtio_routing::RoutingTable::default_empty());
+            drtioaux::send(0, &drtioaux::Packet::RoutingAck)
+        }
+        #[cfg(has_drtio_routing)]
+        drtioaux::Packet::RoutingTableChunk { length, data } => {
+            let accepted = match *_new_routing_table {
+                Some(ref mut table) => match table.read_entries(&data[..length as usize]) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        error!("invalid routing table entries ({})", e);
+                        false
+                    }
+                },
+                None => {
+                    error!("routing table entries received without a start");
+                    false
+                }
+            };
+            if accepted {
+                drtioaux::send(0, &drtioaux::Packet::RoutingAck)
+            } else {
+                *_new_routing_table = None;
+                drtioaux::send(0, &drtioaux::Packet::RoutingNack)
+            }
+        }
+        #[cfg(has_drtio_routing)]
+        drtioaux::Packet::RoutingTableCommit => {
+            match _new_routing_table.take() {
+                Some(table) => {
+                    *_routing_table = table;
+                    for rep in _repeaters.iter() {
+                        if let Err(e) = rep.load_routing_table(_routing_table) {
+                            error!("failed to load routing table ({})", e);
+                        }
+                    }
+                    drtioaux::send(0, &drtioaux::Packet::RoutingAck)
+                },
+                None => {
+                    error!("routing table committed without a start");
+                    drtioaux::send(0, &drtioaux::Packet::RoutingNack)
+                }
+            }
+        }
+        #[cfg(has_drtio_routing)]
         drtioaux::Packet::RoutingSetRank { rank } => {
             *_rank = rank;
             drtio_routing::interconnect_enable_all(_routing_table, rank);
@@ -190,6 +336,27 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
             drtioaux::send(0, &drtioaux::Packet::RoutingAck)
         }
 
+        drtioaux::Packet::TopologyRequest { hops } => {
+            let hop = hops[*_rank as usize];
+            if hop != 0 {
+                #[cfg(has_drtio_routing)]
+                {
+                    let repno = (hop - 1) as usize;
+                    if repno < _repeaters.len() {
+                        return _repeaters[repno].aux_forward(&packet)
+                    }
+                }
+                return Err(drtioaux::Error::RoutingError)
+            }
+            let mut up = 0;
+            for (repno, rep) in _repeaters.iter().enumerate() {
+                if rep.is_up() {
+                    up |= 1 << repno;
+                }
+            }
+            drtioaux::send(0, &drtioaux::Packet::TopologyReply { repeaters: _repeaters.len() as u8, up: up })
+        }
+
         #[cfg(not(has_drtio_routing))]
         drtioaux::Packet::RoutingSetPath { destination: _, hops: _ } => {
             drtioaux::send(0, &drtioaux::Packet::RoutingAck)
@@ -198,6 +365,12 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
         drtioaux::Packet::RoutingSetRank { rank: _ } => {
             drtioaux::send(0, &drtioaux::Packet::RoutingAck)
         }
+        #[cfg(not(has_drtio_routing))]
+        drtioaux::Packet::RoutingTableStart |
+        drtioaux::Packet::RoutingTableChunk { .. } |
+        drtioaux::Packet::RoutingTableCommit => {
+            drtioaux::send(0, &drtioaux::Packet::RoutingAck)
+        }
 
         drtioaux::Packet::MonitorRequest { destination: _destination, channel, probe } => {
             forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
@@ -216,6 +389,47 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
             let reply = drtioaux::Packet::MonitorReply { value: value };
             drtioaux::send(0, &reply)
         },
+        drtioaux::Packet::MonitorBatchRequest { destination: _destination, count, channels, probes } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut values = [0; drtioaux::MONITOR_BATCH_MAX];
+            #[cfg(has_rtio_moninj)]
+            for i in 0..count as usize {
+                unsafe {
+                    csr::rtio_moninj::mon_chan_sel_write(channels[i] as _);
+                    csr::rtio_moninj::mon_probe_sel_write(probes[i]);
+                    csr::rtio_moninj::mon_value_update_write(1);
+                    values[i] = csr::rtio_moninj::mon_value_read() as u64;
+                }
+            }
+            #[cfg(not(has_rtio_moninj))]
+            let _ = (channels, probes);
+            let reply = drtioaux::Packet::MonitorBatchReply { count: count, values: values };
+            drtioaux::send(0, &reply)
+        },
+        drtioaux::Packet::LinkStatsRequest { destination: _destination, linkno } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let links = csr::DRTIOAUX.len() as u8;
+            let stats = if linkno < links { drtioaux::stats(linkno) } else { drtioaux::LinkStats::new() };
+            drtioaux::send(0, &drtioaux::Packet::LinkStatsReply { links: links, stats: stats })
+        },
+
+        drtioaux::Packet::MoninjInfoRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let reply;
+            #[cfg(has_rtio_moninj)]
+            unsafe {
+                reply = drtioaux::Packet::MoninjInfoReply {
+                    channels: csr::rtio_moninj::mon_n_channels_read(),
+                    probes: csr::rtio_moninj::mon_n_probes_read(),
+                    overrides: csr::rtio_moninj::inj_n_overrides_read()
+                };
+            }
+            #[cfg(not(has_rtio_moninj))]
+            {
+                reply = drtioaux::Packet::MoninjInfoReply { channels: 0, probes: 0, overrides: 0 };
+            }
+            drtioaux::send(0, &reply)
+        },
         drtioaux::Packet::InjectionRequest { destination: _destination, channel, overrd, value } => {
             forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
             #[cfg(has_rtio_moninj)]
@@ -241,6 +455,22 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
             }
             drtioaux::send(0, &drtioaux::Packet::InjectionStatusReply { value: value })
         },
+        drtioaux::Packet::InjectionStatusBatchRequest { destination: _destination, count, channels, overrds } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut values = [0; drtioaux::MONITOR_BATCH_MAX];
+            #[cfg(has_rtio_moninj)]
+            for i in 0..count as usize {
+                unsafe {
+                    csr::rtio_moninj::inj_chan_sel_write(channels[i] as _);
+                    csr::rtio_moninj::inj_override_sel_write(overrds[i]);
+                    values[i] = csr::rtio_moninj::inj_value_read();
+                }
+            }
+            #[cfg(not(has_rtio_moninj))]
+            let _ = (channels, overrds);
+            let reply = drtioaux::Packet::InjectionStatusBatchReply { count: count, values: values };
+            drtioaux::send(0, &reply)
+        },
 
         drtioaux::Packet::I2cStartRequest { destination: _destination, busno } => {
             forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
@@ -340,6 +570,54 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
                 &drtioaux::Packet::JdacBasicReply { succeeded: succeeded, retval: retval })
         }
 
+        drtioaux::Packet::BulkStart { destination: _destination, kind, length } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let status = if _update.is_running() {
+                drtioaux::BulkStatus::Busy
+            } else {
+                _bulk.start(kind, length)
+            };
+            drtioaux::send(0, &drtioaux::Packet::BulkReply { status: status, length: 0, crc: 0 })
+        },
+        drtioaux::Packet::BulkChunk { destination: _destination, offset, length, data } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let status = _bulk.chunk(offset, &data[..length as usize]);
+            drtioaux::send(0, &drtioaux::Packet::BulkReply { status: status, length: 0, crc: 0 })
+        },
+        drtioaux::Packet::BulkEnd { destination: _destination, crc } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let (status, length, crc) = _bulk.end(crc, |kind, buffer, length|
+                process_bulk_request(kind, buffer, length, _repeaters, _routing_table, *_rank, _update));
+            drtioaux::send(0, &drtioaux::Packet::BulkReply { status: status, length: length, crc: crc })
+        },
+        drtioaux::Packet::BulkReadRequest { destination: _destination, offset } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let mut data = [0; drtioaux::BULK_CHUNK_MAX];
+            let length = _bulk.read(offset, &mut data);
+            drtioaux::send(0, &drtioaux::Packet::BulkReadReply { length: length as u16, data: data })
+        },
+        drtioaux::Packet::FirmwareStatusRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            let (status, written) = _update.status();
+            drtioaux::send(0, &drtioaux::Packet::FirmwareStatusReply { status: status, written: written })
+        },
+        drtioaux::Packet::RebootRequest { destination: _destination } => {
+            forward!(_routing_table, _destination, *_rank, _repeaters, &packet);
+            if _update.is_running() {
+                warn!("not restarting while the firmware is being written");
+                return drtioaux::send(0, &drtioaux::Packet::RebootReply { succeeded: false })
+            }
+            if _update.status().0 == drtioaux::BulkStatus::Failed {
+                warn!("not restarting after a failed firmware update, the boot flash may be corrupted");
+                return drtioaux::send(0, &drtioaux::Packet::RebootReply { succeeded: false })
+            }
+            drtioaux::send(0, &drtioaux::Packet::RebootReply { succeeded: true })?;
+            warn!("restarting");
+            // Let the reply go out before the gateware is reloaded.
+            clock::spin_us(10_000);
+            unsafe { spiflash::reload() }
+        },
+
         _ => {
             warn!("received unexpected aux packet");
             Ok(())
@@ -348,11 +626,13 @@ fn process_aux_packet(_repeaters: &mut [repeater::Repeater],
 }
 
 fn process_aux_packets(repeaters: &mut [repeater::Repeater],
-        routing_table: &mut drtio_routing::RoutingTable, rank: &mut u8) {
+        routing_table: &mut drtio_routing::RoutingTable,
+        new_routing_table: &mut Option<drtio_routing::RoutingTable>, rank: &mut u8,
+        bulk: &mut bulk::Transfer, update: &mut firmware::Update) {
     let result =
         drtioaux::recv(0).and_then(|packet| {
             if let Some(packet) = packet {
-                process_aux_packet(repeaters, routing_table, rank, packet)
+                process_aux_packet(repeaters, routing_table, new_routing_table, rank, bulk, update, packet)
             } else {
                 Ok(())
             }
@@ -370,16 +650,19 @@ fn drtiosat_process_errors() {
     }
     if errors & 1 != 0 {
         error!("received packet of an unknown type");
+        drtioaux::update_stats(0, |stats| stats.unknown_packets += 1);
     }
     if errors & 2 != 0 {
         error!("received truncated packet");
+        drtioaux::update_stats(0, |stats| stats.truncated_packets += 1);
     }
     if errors & 4 != 0 {
         let destination;
         unsafe {
             destination = csr::drtiosat::buffer_space_timeout_dest_read();
         }
-        error!("timeout attempting to get buffer space from CRI, destination=0x{:02x}", destination)
+        error!("timeout attempting to get buffer space from CRI, destination=0x{:02x}", destination);
+        drtioaux::update_stats(0, |stats| stats.buffer_space_timeouts += 1);
     }
     if errors & 8 != 0 {
         let channel;
@@ -553,7 +836,11 @@ pub extern fn main() -> i32 {
         repeaters[i] = repeater::Repeater::new(i as u8);
     } 
     let mut routing_table = drtio_routing::RoutingTable::default_empty();
+    // Routing table being received from the uplink.
+    let mut new_routing_table = None;
     let mut rank = 1;
+    let mut bulk = unsafe { bulk::Transfer::new() };
+    let mut update = firmware::Update::new();
 
     let mut hardware_tick_ts = 0;
 
@@ -565,6 +852,9 @@ pub extern fn main() -> i32 {
         }
         while !drtiosat_link_rx_up() {
             drtiosat_process_errors();
+            if update.is_running() {
+                update.step(bulk.request());
+            }
             for rep in repeaters.iter_mut() {
                 rep.service(&routing_table, rank);
             }
@@ -586,6 +876,7 @@ pub extern fn main() -> i32 {
         wrpll::select_recovered_clock(true);
 
         drtioaux::reset(0);
+        drtioaux::set_link_up(0, true);
         drtiosat_reset(false);
         drtiosat_reset_phy(false);
 
@@ -593,7 +884,11 @@ pub extern fn main() -> i32 {
         let mut was_up = false;
         while drtiosat_link_rx_up() {
             drtiosat_process_errors();
-            process_aux_packets(&mut repeaters, &mut routing_table, &mut rank);
+            process_aux_packets(&mut repeaters, &mut routing_table, &mut new_routing_table, &mut rank,
+                &mut bulk, &mut update);
+            if update.is_running() {
+                update.step(bulk.request());
+            }
             for rep in repeaters.iter_mut() {
                 rep.service(&routing_table, rank);
             }
@@ -661,6 +956,7 @@ pub extern fn main() -> i32 {
         drtiosat_reset_phy(true);
         drtiosat_reset(true);
         drtiosat_tsc_loaded();
+        drtioaux::set_link_up(0, false);
         info!("uplink is down, switching to local oscillator clock");
         #[cfg(has_si5324)]
         si5324::siphaser::select_recovered_clock(false).expect("failed to switch clocks");
@@ -15,7 +15,7 @@ fn rep_link_rx_up(repno: u8) -> bool {
 enum RepeaterState {
     Down,
     SendPing { ping_count: u16 },
-    WaitPingReply { ping_count: u16, timeout: u64 },
+    WaitPingReply { ping_count: u16, sent_at: u64, timeout: u64 },
     Up,
     Failed
 }
@@ -43,7 +43,6 @@ impl Repeater {
         }
     }
 
-    #[allow(dead_code)]
     pub fn is_up(&self) -> bool {
         self.state == RepeaterState::Up
     }
@@ -63,6 +62,7 @@ impl Repeater {
                     drtioaux::send(self.auxno, &drtioaux::Packet::EchoRequest).unwrap();
                     self.state = RepeaterState::WaitPingReply {
                         ping_count: ping_count + 1,
+                        sent_at: clock::get_us(),
                         timeout: clock::get_ms() + 100
                     }
                 } else {
@@ -70,11 +70,15 @@ impl Repeater {
                     self.state = RepeaterState::Down;
                 }
             }
-            RepeaterState::WaitPingReply { ping_count, timeout } => {
+            RepeaterState::WaitPingReply { ping_count, sent_at, timeout } => {
                 if rep_link_rx_up(self.repno) {
                     if let Ok(Some(drtioaux::Packet::EchoReply)) = drtioaux::recv(self.auxno) {
                         info!("[REP#{}] remote replied after {} packets", self.repno, ping_count);
+                        let latency = (clock::get_us() - sent_at) as u32;
+                        drtioaux::update_stats(self.auxno, |stats| stats.ping_latency_us = latency);
+                        drtioaux::set_link_up(self.auxno, true);
                         self.state = RepeaterState::Up;
+                        self.enable_sequencing();
                         if let Err(e) = self.sync_tsc() {
                             error!("[REP#{}] failed to sync TSC ({})", self.repno, e);
                             self.state = RepeaterState::Failed;
@@ -109,12 +113,14 @@ impl Repeater {
                 self.process_unsolicited_aux();
                 if !rep_link_rx_up(self.repno) {
                     info!("[REP#{}] link is down", self.repno);
+                    drtioaux::set_link_up(self.auxno, false);
                     self.state = RepeaterState::Down;
                 }
             }
             RepeaterState::Failed => {
                 if !rep_link_rx_up(self.repno) {
                     info!("[REP#{}] link is down", self.repno);
+                    drtioaux::set_link_up(self.auxno, false);
                     self.state = RepeaterState::Down;
                 }
             }
@@ -137,9 +143,11 @@ impl Repeater {
         }
         if errors & 1 != 0 {
             error!("[REP#{}] received packet of an unknown type", repno);
+            drtioaux::update_stats(self.auxno, |stats| stats.unknown_packets += 1);
         }
         if errors & 2 != 0 {
             error!("[REP#{}] received truncated packet", repno);
+            drtioaux::update_stats(self.auxno, |stats| stats.truncated_packets += 1);
         }
         if errors & 4 != 0 {
             let cmd;
@@ -156,6 +164,7 @@ impl Repeater {
                 destination = (csr::DRTIOREP[repno].buffer_space_timeout_dest_read)();
             }
             error!("[REP#{}] timeout attempting to get remote buffer space, destination=0x{:02x}", repno, destination);
+            drtioaux::update_stats(self.auxno, |stats| stats.buffer_space_timeouts += 1);
         }
         unsafe {
             (csr::DRTIOREP[repno].protocol_error_write)(errors);
@@ -179,13 +188,30 @@ impl Repeater {
         }
     }
 
+    fn enable_sequencing(&self) {
+        // Satellites without support for sequencing do not reply.
+        let reply = drtioaux::send(self.auxno, &drtioaux::Packet::AuxSequencingRequest)
+            .and_then(|()| self.recv_aux_timeout(200));
+        match reply {
+            Ok(drtioaux::Packet::AuxSequencingAck) => {
+                drtioaux::set_sequencing(self.auxno, true);
+                info!("[REP#{}] aux packet retransmission enabled", self.repno);
+            },
+            _ => info!("[REP#{}] remote does not support aux packet retransmission", self.repno)
+        }
+    }
+
     pub fn aux_forward(&self, request: &drtioaux::Packet) -> Result<(), drtioaux::Error<!>> {
         if self.state != RepeaterState::Up {
             return Err(drtioaux::Error::LinkDown);
         }
-        drtioaux::send(self.auxno, request).unwrap();
-        let reply = self.recv_aux_timeout(200)?;
-        drtioaux::send(0, &reply).unwrap();
+        drtioaux::send(self.auxno, request)?;
+        let timeout = match *request {
+            drtioaux::Packet::BulkEnd { .. } => drtioaux::BULK_END_TIMEOUT_MS,
+            _ => 200
+        };
+        let reply = self.recv_aux_timeout(timeout)?;
+        drtioaux::send(0, &reply)?;
         Ok(())
     }
 
@@ -218,7 +244,7 @@ impl Repeater {
         drtioaux::send(self.auxno, &drtioaux::Packet::RoutingSetPath {
             destination: destination,
             hops: *hops
-        }).unwrap();
+        })?;
         let reply = self.recv_aux_timeout(200)?;
         if reply != drtioaux::Packet::RoutingAck {
             return Err(drtioaux::Error::UnexpectedReply);
@@ -226,20 +252,51 @@ impl Repeater {
         Ok(())
     }
 
-    pub fn load_routing_table(&self, routing_table: &drtio_routing::RoutingTable) -> Result<(), drtioaux::Error<!>> {
-        for i in 0..drtio_routing::DEST_COUNT {
-            self.set_path(i as u8, &routing_table.0[i])?;
+    fn routing_transact(&self, request: &drtioaux::Packet) -> Result<(), drtioaux::Error<!>> {
+        drtioaux::send(self.auxno, request)?;
+        let reply = self.recv_aux_timeout(200)?;
+        if reply != drtioaux::Packet::RoutingAck {
+            return Err(drtioaux::Error::UnexpectedReply);
         }
         Ok(())
     }
 
+    pub fn load_routing_table(&self, routing_table: &drtio_routing::RoutingTable) -> Result<(), drtioaux::Error<!>> {
+        if self.state != RepeaterState::Up {
+            return Ok(());
+        }
+
+        // Satellites that do not acknowledge RoutingTableStart get one path per packet.
+        if self.routing_transact(&drtioaux::Packet::RoutingTableStart).is_err() {
+            for destination in 0..drtio_routing::DEST_COUNT {
+                if routing_table.path(destination as u8).is_some() {
+                    self.set_path(destination as u8, &routing_table.0[destination])?;
+                }
+            }
+            return Ok(())
+        }
+        let mut destination = 0;
+        while destination < drtio_routing::DEST_COUNT {
+            let mut data = [0; drtioaux::ROUTING_CHUNK_MAX];
+            let (length, next) = routing_table.write_entries(destination, &mut data);
+            if length > 0 {
+                self.routing_transact(&drtioaux::Packet::RoutingTableChunk {
+                    length: length as u16,
+                    data: data
+                })?;
+            }
+            destination = next;
+        }
+        self.routing_transact(&drtioaux::Packet::RoutingTableCommit)
+    }
+
     pub fn set_rank(&self, rank: u8) -> Result<(), drtioaux::Error<!>> {
         if self.state != RepeaterState::Up {
             return Ok(());
         }
         drtioaux::send(self.auxno, &drtioaux::Packet::RoutingSetRank {
             rank: rank
-        }).unwrap();
+        })?;
         let reply = self.recv_aux_timeout(200)?;
         if reply != drtioaux::Packet::RoutingAck {
             return Err(drtioaux::Error::UnexpectedReply);
@@ -257,7 +314,7 @@ impl Repeater {
             return Ok(());
         }
 
-        drtioaux::send(self.auxno, &drtioaux::Packet::ResetRequest).unwrap();
+        drtioaux::send(self.auxno, &drtioaux::Packet::ResetRequest)?;
         let reply = self.recv_aux_timeout(200)?;
         if reply != drtioaux::Packet::ResetAck {
             return Err(drtioaux::Error::UnexpectedReply);
@@ -275,6 +332,8 @@ pub struct Repeater {
 impl Repeater {
     pub fn new(_repno: u8) -> Repeater { Repeater::default() }
 
+    pub fn is_up(&self) -> bool { false }
+
     pub fn service(&self, _routing_table: &drtio_routing::RoutingTable, _rank: u8) { }
 
     pub fn sync_tsc(&self) -> Result<(), drtioaux::Error<!>> { Ok(()) }
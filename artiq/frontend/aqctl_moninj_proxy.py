@@ -10,7 +10,7 @@ from sipyco.asyncio_tools import AsyncioServer, SignalHandler
 from sipyco.pc_rpc import Server
 from sipyco import common_args
 
-from artiq.coredevice.comm_moninj import CommMonInj
+from artiq.coredevice.comm_moninj import CommMonInj, TTLOverride
 
 
 logger = logging.getLogger(__name__)
@@ -125,11 +125,31 @@ class ProxyConnection:
         self.monitor_mux = monitor_mux
         self.reader = reader
         self.writer = writer
+        # Overrides are leased by the proxy on behalf of its clients,
+        # as the proxy holds the only connection to the core device.
+        self.lease_timeout = None
+        self.leased = set()
+
+    def _release_leased(self):
+        for channel in self.leased:
+            self.monitor_mux.comm_moninj.inject(channel, TTLOverride.en.value, 0)
+        self.leased.clear()
 
     async def handle(self):
         try:
             while True:
-                ty = await self.reader.read(1)
+                if self.lease_timeout:
+                    try:
+                        ty = await asyncio.wait_for(self.reader.read(1),
+                                                    self.lease_timeout)
+                    except asyncio.TimeoutError:
+                        if self.leased:
+                            logger.warning("lease expired, releasing %d overrides",
+                                           len(self.leased))
+                            self._release_leased()
+                        continue
+                else:
+                    ty = await self.reader.read(1)
                 if not ty:
                     return
                 if ty == b"\x00":     # MonitorProbe
@@ -140,6 +160,12 @@ class ProxyConnection:
                     packet = await self.reader.readexactly(6)
                     channel, overrd, value = struct.unpack("<lbb", packet)
                     self.monitor_mux.comm_moninj.inject(channel, overrd, value)
+                    if (self.lease_timeout is not None
+                            and overrd == TTLOverride.en.value):
+                        if value:
+                            self.leased.add(channel)
+                        else:
+                            self.leased.discard(channel)
                 elif ty == b"\x02":   # GetInjectionStatus
                     packet = await self.reader.readexactly(5)
                     channel, overrd = struct.unpack("<lb", packet)
@@ -148,9 +174,18 @@ class ProxyConnection:
                     packet = await self.reader.readexactly(6)
                     enable, channel, overrd = struct.unpack("<blb", packet)
                     self.monitor_mux.monitor_injection(self, enable, channel, overrd)
+                elif ty == b"\x06":   # SetLease
+                    packet = await self.reader.readexactly(5)
+                    enable, timeout_ms = struct.unpack("<bl", packet)
+                    self.lease_timeout = timeout_ms/1000 if enable else None
+                elif ty == b"\x07":   # ReleaseAllOverrides
+                    self.monitor_mux.comm_moninj.release_all_overrides()
+                    self.leased.clear()
                 else:
                     raise ValueError
         finally:
+            if self.lease_timeout is not None:
+                self._release_leased()
             self.monitor_mux.remove_listener(self)
 
     def monitor_cb(self, channel, probe, value):
@@ -185,6 +220,10 @@ def get_argparser():
     ])
     parser.add_argument("core_addr", metavar="CORE_ADDR",
                         help="hostname or IP address of the core device")
+    parser.add_argument("--poll-interval", default=None, type=int,
+                        help="interval in milliseconds at which the core "
+                             "device checks monitored channels for changes "
+                             "(default: 200)")
     return parser
 
 
@@ -211,6 +250,9 @@ def main():
                                      monitor_mux.disconnect_cb)
             monitor_mux.comm_moninj = comm_moninj
             loop.run_until_complete(comm_moninj.connect(args.core_addr))
+            comm_moninj.batch_updates()
+            if args.poll_interval is not None:
+                comm_moninj.set_poll_interval(args.poll_interval)
             try:
                 proxy_server = ProxyServer(monitor_mux)
                 loop.run_until_complete(proxy_server.start(bind_address, args.port_proxy))
@@ -1,6 +1,7 @@
 #!/usr/bin/env python3
 
 import argparse
+import logging
 import sys
 
 from sipyco import common_args
@@ -8,7 +9,13 @@ from sipyco import common_args
 from artiq.master.databases import DeviceDB
 from artiq.master.worker_db import DeviceManager
 from artiq.coredevice.comm_analyzer import (get_analyzer_dump,
-                                            decode_dump, decoded_dump_to_vcd)
+                                            decode_dump, decoded_dump_to_vcd,
+                                            AnalyzerStream, StreamOverflow,
+                                            DecodedDump, AnalyzerConfig,
+                                            arm_analyzer)
+
+
+logger = logging.getLogger(__name__)
 
 
 def get_argparser():
@@ -27,37 +34,115 @@ def get_argparser():
                         help="format and write contents to VCD file")
     parser.add_argument("-d", "--write-dump", type=str, default=None,
                         help="write raw dump file")
+    parser.add_argument("-s", "--stream", default=False, action="store_true",
+                        help="stream analyzer records continuously until "
+                             "interrupted with Ctrl-C, instead of fetching "
+                             "the contents of the ring buffer once")
 
     parser.add_argument("-u", "--vcd-uniform-interval", action="store_true",
                         help="emit uniform time intervals between timed VCD "
                              "events and show RTIO event interval (in SI "
                              "seconds) and timestamp (in machine units) as "
                              "separate VCD channels")
+
+    capture = parser.add_argument_group("capture configuration")
+    capture.add_argument("-a", "--arm", default=False, action="store_true",
+                         help="re-arm the analyzer with the capture "
+                              "configuration below and exit; the "
+                              "configuration applies to subsequent dumps")
+    capture.add_argument("--channel", type=int, action="append",
+                         dest="channels", default=None,
+                         help="capture only events on this channel "
+                              "(may be given several times)")
+    start = capture.add_mutually_exclusive_group()
+    start.add_argument("--start-channel", type=int, default=None,
+                       help="start capturing at the first event on "
+                            "this channel")
+    start.add_argument("--start-timestamp", type=int, default=None,
+                       help="start capturing at the first event with a "
+                            "timestamp at or after this value (in machine "
+                            "units)")
+    stop = capture.add_mutually_exclusive_group()
+    stop.add_argument("--stop-channel", type=int, default=None,
+                      help="stop capturing at the first event on "
+                           "this channel")
+    stop.add_argument("--stop-timestamp", type=int, default=None,
+                      help="stop capturing at the first event with a "
+                           "timestamp at or after this value (in machine "
+                           "units)")
+    capture.add_argument("--pre-trigger", type=int, default=0,
+                         help="number of events to keep from before the "
+                              "start trigger (default: %(default)s)")
+    capture.add_argument("--post-trigger", type=int, default=0,
+                         help="number of events to keep after the stop "
+                              "trigger (default: %(default)s)")
     return parser
 
 
+def get_capture_config(args):
+    return AnalyzerConfig(channels=args.channels,
+                          start_channel=args.start_channel,
+                          start_timestamp=args.start_timestamp,
+                          stop_channel=args.stop_channel,
+                          stop_timestamp=args.stop_timestamp,
+                          pre_trigger=args.pre_trigger,
+                          post_trigger=args.post_trigger)
+
+
 def main():
     args = get_argparser().parse_args()
     common_args.init_logger_from_args(args)
 
+    if args.arm:
+        device_mgr = DeviceManager(DeviceDB(args.device_db))
+        core_addr = device_mgr.get_desc("core")["arguments"]["host"]
+        arm_analyzer(core_addr, get_capture_config(args))
+        return
+
     if (not args.print_decoded
             and args.write_vcd is None and args.write_dump is None):
         print("No action selected, use -p, -w and/or -d. See -h for help.")
         sys.exit(1)
 
+    if args.stream and (args.read_dump or args.write_dump):
+        print("Raw dump files cannot be used in streaming mode.")
+        sys.exit(1)
+
     device_mgr = DeviceManager(DeviceDB(args.device_db))
-    if args.read_dump:
-        with open(args.read_dump, "rb") as f:
-            dump = f.read()
-    else:
+    if args.stream:
         core_addr = device_mgr.get_desc("core")["arguments"]["host"]
-        dump = get_analyzer_dump(core_addr)
-    decoded_dump = decode_dump(dump)
-    if args.print_decoded:
-        print("Log channel:", decoded_dump.log_channel)
-        print("DDS one-hot:", decoded_dump.dds_onehot_sel)
-        for message in decoded_dump.messages:
-            print(message)
+        messages = []
+        with AnalyzerStream(core_addr,
+                            config=get_capture_config(args)) as stream:
+            if args.print_decoded:
+                print("Log channel:", stream.log_channel)
+                print("DDS one-hot:", stream.dds_onehot_sel)
+            try:
+                for message in stream:
+                    if isinstance(message, StreamOverflow):
+                        logger.warning("analyzer stream overflow, "
+                                       "%d bytes lost", message.lost_bytes)
+                        continue
+                    if args.print_decoded:
+                        print(message)
+                    messages.append(message)
+            except KeyboardInterrupt:
+                pass
+        decoded_dump = DecodedDump(stream.log_channel, stream.dds_onehot_sel,
+                                   messages)
+    else:
+        if args.read_dump:
+            with open(args.read_dump, "rb") as f:
+                dump = f.read()
+        else:
+            core_addr = device_mgr.get_desc("core")["arguments"]["host"]
+            dump = get_analyzer_dump(core_addr)
+        decoded_dump = decode_dump(dump)
+        if args.print_decoded:
+            print("Log channel:", decoded_dump.log_channel)
+            print("DDS one-hot:", decoded_dump.dds_onehot_sel)
+            for message in decoded_dump.messages:
+                print(message)
     if args.write_vcd:
         with open(args.write_vcd, "w") as f:
             decoded_dump_to_vcd(f, device_mgr.get_device_db(),
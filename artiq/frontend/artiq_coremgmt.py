@@ -9,6 +9,11 @@ from artiq import __version__ as artiq_version
 from artiq.master.databases import DeviceDB
 from artiq.coredevice.comm_kernel import CommKernel
 from artiq.coredevice.comm_mgmt import CommMgmt
+from artiq.frontend.artiq_mkfs import write_record, write_end_marker
+from artiq.frontend.artiq_route import routing_table_bytes
+
+
+DMA_TRACE_MAGIC = b"ARTIQDMA"
 
 
 def get_argparser():
@@ -59,6 +64,9 @@ def get_argparser():
                                    help="read key from core device config")
     p_read.add_argument("key", metavar="KEY", type=str,
                         help="key to be read from core device config")
+    p_read.add_argument("-d", "--destination", default=0, type=int,
+                        help="DRTIO destination whose config is read; "
+                             "0 (default) is the master")
 
     p_write = subparsers.add_parser("write",
                                     help="write key-value records to core "
@@ -72,15 +80,155 @@ def get_argparser():
                          metavar=("KEY", "FILENAME"),
                          help="key and file whose content to be written to "
                               "core device config")
+    p_write.add_argument("-d", "--destination", default=0, type=int,
+                         help="DRTIO destination whose config is written; "
+                              "0 (default) is the master")
 
     p_remove = subparsers.add_parser("remove",
                                      help="remove key from core device config")
     p_remove.add_argument("key", metavar="KEY", nargs=argparse.REMAINDER,
                           default=[], type=str,
                           help="key to be removed from core device config")
+    p_remove.add_argument("-d", "--destination", default=0, type=int,
+                          help="DRTIO destination whose config is changed; "
+                               "0 (default) is the master")
 
     subparsers.add_parser("erase", help="fully erase core device config")
 
+    subparsers.add_parser("list", help="list keys in core device config")
+
+    subparsers.add_parser("stats",
+                          help="show space usage and health of core device "
+                               "config")
+
+    p_dump = subparsers.add_parser("dump",
+                                   help="save core device config to a flash "
+                                        "storage image")
+    p_dump.add_argument("filename", metavar="FILENAME", type=str,
+                        help="image file to write")
+
+    p_restore = subparsers.add_parser("restore",
+                                      help="replace core device config with "
+                                           "the contents of a flash storage "
+                                           "image")
+    p_restore.add_argument("filename", metavar="FILENAME", type=str,
+                           help="image file to read, as created by the dump "
+                                "command or artiq_mkfs")
+
+    # DMA trace storage
+    t_dma = tools.add_parser("dma",
+                             help="manage DMA traces stored in core device flash")
+
+    subparsers = t_dma.add_subparsers(dest="action")
+    subparsers.required = True
+
+    subparsers.add_parser("list", help="list stored DMA traces")
+
+    p_export = subparsers.add_parser("export",
+                                     help="save a stored DMA trace to a file")
+    p_export.add_argument("name", metavar="NAME", type=str,
+                          help="name of the trace")
+    p_export.add_argument("filename", metavar="FILENAME", type=str,
+                          help="file to write the trace to")
+
+    p_import = subparsers.add_parser("import",
+                                     help="store a DMA trace from a file "
+                                          "created by the export command")
+    p_import.add_argument("name", metavar="NAME", type=str,
+                          help="name of the trace")
+    p_import.add_argument("filename", metavar="FILENAME", type=str,
+                          help="file to read the trace from")
+
+    p_delete = subparsers.add_parser("delete",
+                                     help="delete stored DMA traces")
+    p_delete.add_argument("name", metavar="NAME", nargs="+", type=str,
+                          help="names of the traces to delete")
+
+    subparsers.add_parser("loaded",
+                          help="list DMA traces held in core device memory")
+
+    p_erase = subparsers.add_parser("erase",
+                                    help="erase DMA traces from core device "
+                                         "memory (stored copies are kept)")
+    p_erase.add_argument("name", metavar="NAME", nargs="+", type=str,
+                         help="names of the traces to erase")
+
+    subparsers.add_parser("clear",
+                          help="erase all DMA traces from core device memory "
+                               "(stored copies are kept)")
+
+    # core device cache
+    t_cache = tools.add_parser("cache",
+                               help="inspect and erase core device cache entries")
+
+    subparsers = t_cache.add_subparsers(dest="action")
+    subparsers.required = True
+
+    subparsers.add_parser("list", help="list cache entries")
+
+    p_erase = subparsers.add_parser("erase", help="erase cache entries")
+    p_erase.add_argument("key", metavar="KEY", nargs="+", type=str,
+                         help="keys of the entries to erase")
+
+    subparsers.add_parser("clear", help="erase all cache entries")
+
+    # DRTIO
+    t_drtio = tools.add_parser("drtio",
+                               help="inspect DRTIO links and satellites")
+
+    subparsers = t_drtio.add_subparsers(dest="action")
+    subparsers.required = True
+
+    p_stats = subparsers.add_parser("stats",
+                                    help="show error counters and uptime of "
+                                         "the DRTIO links of a destination")
+    p_stats.add_argument("-d", "--destination", default=0, type=int,
+                         help="destination whose links are shown; "
+                              "0 (default) is the master")
+
+    p_status = subparsers.add_parser("status",
+                                     help="show the state of a satellite")
+    p_status.add_argument("destination", metavar="DESTINATION", type=int,
+                          help="destination number of the satellite")
+
+    p_topology = subparsers.add_parser("topology",
+                                       help="discover the satellites and "
+                                            "show the routing table that "
+                                            "reaches them")
+    p_topology.add_argument("-w", "--write", default=False,
+                            action="store_true",
+                            help="store the discovered routing table in the "
+                                 "core device config (takes effect after a "
+                                 "reboot)")
+    p_topology.add_argument("-a", "--apply", default=False,
+                            action="store_true",
+                            help="install the discovered routing table on "
+                                 "the running master")
+
+    p_route = subparsers.add_parser("route",
+                                    help="install a routing table on the "
+                                         "running master")
+    p_route.add_argument("file", metavar="FILE", type=str,
+                         help="routing table, as written by artiq_route")
+    p_route.add_argument("-w", "--write", default=False, action="store_true",
+                         help="also store the routing table in the core "
+                              "device config")
+
+    p_flash = subparsers.add_parser("flash",
+                                    help="write the firmware of a satellite")
+    p_flash.add_argument("destination", metavar="DESTINATION", type=int,
+                         help="destination number of the satellite")
+    p_flash.add_argument("image", metavar="IMAGE", type=str,
+                         help="firmware image with header (satman.fbi)")
+    p_flash.add_argument("--reboot", default=False, action="store_true",
+                         help="reboot the satellite once the firmware is "
+                              "written")
+
+    p_reboot = subparsers.add_parser("reboot",
+                                     help="reboot a satellite")
+    p_reboot.add_argument("destination", metavar="DESTINATION", type=int,
+                          help="destination number of the satellite")
+
     # booting
     t_boot = tools.add_parser("reboot",
                               help="reboot the running system")
@@ -98,6 +246,24 @@ def get_argparser():
     return parser
 
 
+def read_storage_image(f):
+    entries = dict()
+    while True:
+        header = f.read(4)
+        if len(header) < 4:
+            break
+        record_size, = struct.unpack(">l", header)
+        if record_size == -1:
+            break
+        key, value = f.read(record_size - 4).split(b"\x00", 1)
+        key = key.decode()
+        if value:
+            entries[key] = value
+        else:
+            entries.pop(key, None)
+    return entries
+
+
 def main():
     args = get_argparser().parse_args()
     common_args.init_logger_from_args(args)
@@ -121,22 +287,136 @@ def main():
 
     if args.tool == "config":
         if args.action == "read":
-            value = mgmt.config_read(args.key)
+            value = mgmt.config_read(args.key, args.destination)
             if not value:
                 print("Key {} does not exist".format(args.key))
             else:
                 print(value)
         if args.action == "write":
             for key, value in args.string:
-                mgmt.config_write(key, value.encode("utf-8"))
+                mgmt.config_write(key, value.encode("utf-8"),
+                                  args.destination)
             for key, filename in args.file:
                 with open(filename, "rb") as fi:
-                    mgmt.config_write(key, fi.read())
+                    mgmt.config_write(key, fi.read(), args.destination)
         if args.action == "remove":
             for key in args.key:
-                mgmt.config_remove(key)
+                mgmt.config_remove(key, args.destination)
         if args.action == "erase":
             mgmt.config_erase()
+        if args.action == "list":
+            for key in mgmt.config_list():
+                print(key)
+        if args.action == "stats":
+            stats = mgmt.config_stats()
+            print("Used: {} of {} bytes".format(stats["used"], stats["capacity"]))
+            print("Live: {} records, {} bytes".format(stats["live_records"],
+                                                      stats["live_bytes"]))
+            print("Dead: {} records (reclaimed by compaction)".format(
+                stats["dead_records"]))
+            if stats["corruption"] is not None:
+                print("Corrupted record at offset {}".format(stats["corruption"]))
+        if args.action == "dump":
+            entries = mgmt.config_dump()
+            with open(args.filename, "wb") as fo:
+                for key, value in entries.items():
+                    write_record(fo, key, value)
+                write_end_marker(fo)
+        if args.action == "restore":
+            with open(args.filename, "rb") as fi:
+                entries = read_storage_image(fi)
+            mgmt.config_restore(entries)
+
+    if args.tool == "dma":
+        if args.action == "list":
+            for name, duration, size in mgmt.dma_list():
+                print("{}: {} bytes, {} mu".format(name, size, duration))
+        if args.action == "export":
+            duration, trace = mgmt.dma_export(args.name)
+            with open(args.filename, "wb") as fo:
+                fo.write(DMA_TRACE_MAGIC)
+                fo.write(struct.pack("<Q", duration))
+                fo.write(trace)
+        if args.action == "import":
+            with open(args.filename, "rb") as fi:
+                if fi.read(len(DMA_TRACE_MAGIC)) != DMA_TRACE_MAGIC:
+                    raise ValueError("{} is not a DMA trace file"
+                                     .format(args.filename))
+                duration, = struct.unpack("<Q", fi.read(8))
+                trace = fi.read()
+            mgmt.dma_import(args.name, duration, trace)
+        if args.action == "delete":
+            for name in args.name:
+                mgmt.dma_delete(name)
+        if args.action == "loaded":
+            for name, size, duration, borrowed in mgmt.dma_loaded():
+                print("{}: {} bytes, {} mu{}".format(
+                    name, size, duration, " (in use)" if borrowed else ""))
+        if args.action == "erase":
+            for name in args.name:
+                mgmt.dma_erase(name)
+        if args.action == "clear":
+            mgmt.dma_clear()
+
+    if args.tool == "cache":
+        if args.action == "list":
+            for key, length, borrowed in mgmt.cache_list():
+                print("{}: {} elements{}".format(
+                    key, length, " (in use)" if borrowed else ""))
+        if args.action == "erase":
+            for key in args.key:
+                mgmt.cache_erase(key)
+        if args.action == "clear":
+            mgmt.cache_clear()
+
+    if args.tool == "drtio":
+        if args.action == "stats":
+            links = mgmt.drtio_link_stats(args.destination)
+            for linkno, stats in enumerate(links):
+                if args.destination == 0:
+                    name = "Link {}".format(linkno)
+                elif linkno == 0:
+                    name = "Uplink"
+                else:
+                    name = "Repeater {}".format(linkno - 1)
+                if stats["uptime_ms"]:
+                    print("{}: up for {:.1f} s, ping latency {} us".format(
+                        name, stats["uptime_ms"]/1000, stats["ping_latency_us"]))
+                else:
+                    print("{}: down".format(name))
+                print("  aux CRC errors: {}".format(stats["crc_errors"]))
+                print("  unknown packets: {}".format(stats["unknown_packets"]))
+                print("  truncated packets: {}".format(stats["truncated_packets"]))
+                print("  buffer space timeouts: {}".format(
+                    stats["buffer_space_timeouts"]))
+                print("  link down events: {}".format(stats["link_down_events"]))
+                print("  aux retransmissions: {}".format(stats["retransmissions"]))
+        if args.action == "status":
+            print(mgmt.drtio_status(args.destination), end="")
+        if args.action == "topology":
+            topology = mgmt.drtio_topology()
+            for destination, hops in sorted(topology.items()):
+                print("Destination {}: rank {}, hops {}".format(
+                    destination, len(hops),
+                    " ".join(str(hop) for hop in hops) or "(local)"))
+            table = routing_table_bytes(topology)
+            if args.write:
+                mgmt.config_write("routing_table", table)
+            if args.apply:
+                mgmt.drtio_set_routing_table(table)
+        if args.action == "route":
+            with open(args.file, "rb") as f:
+                table = f.read()
+            mgmt.drtio_set_routing_table(table)
+            if args.write:
+                mgmt.config_write("routing_table", table)
+        if args.action == "flash":
+            with open(args.image, "rb") as f:
+                mgmt.drtio_firmware_update(args.destination, f.read())
+            if args.reboot:
+                mgmt.drtio_reboot(args.destination)
+        if args.action == "reboot":
+            mgmt.drtio_reboot(args.destination)
 
     if args.tool == "reboot":
         mgmt.reboot()
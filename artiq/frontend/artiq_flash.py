@@ -308,6 +308,13 @@ def main():
     args = get_argparser().parse_args()
     common_args.init_logger_from_args(args)
 
+    # The runtime stores DMA traces in the 512 KiB starting at the first sector
+    # following the largest firmware image (4 MiB plus an 8-byte header; see
+    # DMA_STORE_ADDRESS in firmware/libboard_misoc/spiflash.rs), e.g.
+    # 0x860000 on Kasli, 0x460000 on Sayma and Metlino, and 0xf50000 on KC705,
+    # and journals the configuration into the sector following them (0x8e0000,
+    # 0x4e0000 and 0xfd0000) in addition to "storage".
+    # Images written there with this tool would be overwritten.
     config = {
         "kasli": {
             "programmer":   partial(ProgrammerXC7, board="kasli", proxy="bscan_spi_xc7a100t.bit"),
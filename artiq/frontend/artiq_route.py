@@ -28,44 +28,123 @@ def get_argparser():
 
 DEST_COUNT = 256
 MAX_HOPS = 32
+INVALID_HOP = 0xff
+FORMAT_VERSION = 1
+
+
+def validate(routes):
+    """Checks a dictionary mapping destinations to their routes, which must
+    end with the local RTIO core (0) of the destination. The firmware
+    additionally checks that the first hop is one of its links."""
+    for destination, route in routes.items():
+        if not 0 <= destination < DEST_COUNT:
+            raise ValueError("destination {} is out of range"
+                             .format(destination))
+        if not route or route[-1] != 0 or len(route) > MAX_HOPS:
+            raise ValueError("route to destination {} does not end with a "
+                             "local RTIO core within {} hops"
+                             .format(destination, MAX_HOPS))
+        if any(not 0 < hop < INVALID_HOP for hop in route[:-1]):
+            raise ValueError("route to destination {} goes through an "
+                             "invalid hop".format(destination))
+    destinations = dict()
+    for destination, route in sorted(routes.items()):
+        other = destinations.setdefault(tuple(route), destination)
+        if other != destination:
+            raise ValueError("destinations {} and {} have the same route"
+                             .format(other, destination))
+
+
+def encode(routes):
+    """Returns a routing table in the sparse format: the format version,
+    then for each destination, the destination, the number of hops, and
+    the hops."""
+    validate(routes)
+    data = bytearray([FORMAT_VERSION])
+    for destination, route in sorted(routes.items()):
+        data += bytes([destination, len(route)] + route)
+    return bytes(data)
+
+
+def _decode_legacy(data):
+    routes = dict()
+    for destination in range(DEST_COUNT):
+        entry = data[destination*MAX_HOPS:(destination+1)*MAX_HOPS]
+        if entry[0] != INVALID_HOP:
+            end = entry.find(0)
+            routes[destination] = list(entry[:end+1] if end >= 0
+                                       else entry)
+    return routes
+
+
+def _decode_sparse(data):
+    routes = dict()
+    offset = 1
+    while offset < len(data):
+        if offset + 2 > len(data):
+            raise ValueError("truncated routing table")
+        destination, count = data[offset:offset+2]
+        if offset + 2 + count > len(data):
+            raise ValueError("truncated routing table")
+        if destination in routes:
+            raise ValueError("destination {} has several routes"
+                             .format(destination))
+        routes[destination] = list(data[offset+2:offset+2+count])
+        offset += 2 + count
+    validate(routes)
+    return routes
+
+
+def decode(data):
+    """Parses a routing table in the sparse format or in the legacy format
+    of DEST_COUNT*MAX_HOPS bytes, and returns a dictionary mapping
+    destinations to their routes. Data of the legacy length that starts
+    with the format version is read in the sparse format if valid."""
+    legacy = len(data) == DEST_COUNT*MAX_HOPS
+    if data and data[0] == FORMAT_VERSION:
+        try:
+            return _decode_sparse(data)
+        except ValueError:
+            if not legacy:
+                raise
+    elif not legacy:
+        raise ValueError("unsupported routing table format")
+    routes = _decode_legacy(data)
+    validate(routes)
+    return routes
+
+
+def routing_table_bytes(routes):
+    """Returns the contents of a routing table file for a dictionary
+    mapping destinations to their hops, as returned by
+    ``CommMgmt.drtio_topology``."""
+    return encode({destination: hops + [0]
+                   for destination, hops in routes.items()})
 
 
 def init(filename):
     with open(filename, "wb") as f:
-        f.write(b"\xff"*(DEST_COUNT*MAX_HOPS))
+        f.write(encode(dict()))
 
 
 def show_routes(filename):
-    routes = []
     with open(filename, "rb") as f:
-        for i in range(DEST_COUNT):
-            hops = [int.from_bytes(f.read(1), "big") for j in range(MAX_HOPS)]
-            routes.append(hops)
+        routes = decode(f.read())
 
-    for destination, route in enumerate(routes):
-        if route[0] != 0xff:
-            fmt = "{:3d}:".format(destination)
-            for hop in route:
-                if hop == 0xff:
-                    break
-                fmt += " {:3d}".format(hop)
-            print(fmt)
+    for destination, route in sorted(routes.items()):
+        fmt = "{:3d}:".format(destination)
+        for hop in route:
+            fmt += " {:3d}".format(hop)
+        print(fmt)
 
 
 def set_route(filename, destination, hops):
-    with open(filename, "r+b") as f:
-        if destination >= DEST_COUNT:
-            raise ValueError("destination must be less than {}".format(DEST_COUNT))
-        f.seek(destination*MAX_HOPS)
-
-        if len(hops) + 1 >= MAX_HOPS:
-            raise ValueError("too many hops")
-        for hop in hops:
-            if hop >= 0xff:
-                raise ValueError("all hops must be less than 255")
-
-        hops = hops + [0xff]*(MAX_HOPS-len(hops))
-        f.write(bytes(hops))
+    with open(filename, "rb") as f:
+        routes = decode(f.read())
+    routes[destination] = hops
+    data = encode(routes)
+    with open(filename, "wb") as f:
+        f.write(data)
 
 
 def main():
@@ -13,6 +13,8 @@ class Monitor(Module, AutoCSR):
         self.probe_sel = CSRStorage(bits_for(max_chan_probes-1))
         self.value_update = CSR()
         self.value = CSRStatus(max_probe_len)
+        self.n_channels = CSRStatus(16, reset=len(chan_probes))
+        self.n_probes = CSRStatus(8, reset=max_chan_probes)
 
         # # #
 
@@ -40,6 +42,7 @@ class Injector(Module, AutoCSR):
         self.chan_sel = CSRStorage(bits_for(len(chan_overrides)-1))
         self.override_sel = CSRStorage(bits_for(max_chan_overrides-1))
         self.value = CSR(max_override_len)
+        self.n_overrides = CSRStatus(8, reset=max_chan_overrides)
 
         # # #
 
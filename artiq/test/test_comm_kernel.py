@@ -0,0 +1,85 @@
+import struct
+import unittest
+from unittest import mock
+
+from artiq.coredevice import comm_kernel
+from artiq.coredevice.comm_kernel import CommKernel, KernelState, Reply
+
+
+class FakeSocket:
+    def __init__(self, data):
+        self.data = bytearray(data)
+        self.sent = bytearray()
+
+    def sendall(self, data):
+        self.sent += data
+
+    def recv(self, length, flags=0):
+        result = self.data[:length]
+        self.data = self.data[length:]
+        return bytes(result)
+
+    def close(self):
+        pass
+
+
+def reply(ty, payload=b""):
+    return b"\x5a\x5a\x5a\x5a" + bytes([ty.value]) + payload
+
+
+def string(s):
+    return struct.pack("<l", len(s)) + s
+
+
+def exception_payload():
+    return (struct.pack("<l", 1)           # exception count
+            + struct.pack("<l", 0)         # name
+            + struct.pack("<ll", -1, 1)    # message, by key
+            + struct.pack("<qqq", 1, 2, 3)
+            + string(b"kernel.py")         # filename
+            + struct.pack("<ll", 10, 4)    # line, column
+            + struct.pack("<ll", -1, 2)    # function, by key
+            + struct.pack("<lll", 0, 0, 0)
+            + struct.pack("<l", 1) + struct.pack("<ll", 0x1000, 0x2000)
+            + b"\x00")                     # async errors
+
+
+class TestAbort(unittest.TestCase):
+    def abort(self, replies):
+        socket = FakeSocket(b"e" + replies)
+        with mock.patch.object(comm_kernel, "create_connection",
+                               return_value=socket):
+            comm = CommKernel("::1")
+            comm.open()
+            state = comm.abort()
+        self.assertEqual(socket.data, b"")
+        self.assertEqual(comm.read_buffer, b"")
+        return state
+
+    def test_aborted(self):
+        state = self.abort(reply(Reply.KernelAborted, b"\x02"))
+        self.assertEqual(state, KernelState.Running)
+
+    def test_finished_concurrently(self):
+        state = self.abort(
+            reply(Reply.KernelFinished, b"\x00")
+            + reply(Reply.KernelAborted, b"\x00"))
+        self.assertEqual(state, KernelState.Absent)
+
+    def test_pending_messages(self):
+        rpc = (b"\x00"                          # is_async
+               + struct.pack("<l", 3)           # service_id
+               + b"i" + struct.pack("<l", 42)
+               + b"s" + string(b"\x5a\x5a\x5a\x5a")
+               + b"O" + struct.pack("<l", 7)
+               + b"\x00"
+               + string(b"n"))                  # return_tags
+        events = struct.pack("<ll", 0, 1) + struct.pack("<qBBBl", 5, 1, 1, 0, 0)
+        watchdog = struct.pack("<l", 1000) + string(b"")
+        state = self.abort(
+            reply(Reply.RPCRequest, rpc)
+            + reply(Reply.RTIOEvents, events)
+            + reply(Reply.KernelException, exception_payload())
+            + reply(Reply.WatchdogExpired, watchdog)
+            + reply(Reply.KernelAborted, b"\x00"))
+        self.assertEqual(state, KernelState.Absent)
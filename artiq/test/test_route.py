@@ -0,0 +1,71 @@
+import unittest
+
+from artiq.frontend.artiq_route import (
+    DEST_COUNT, MAX_HOPS, INVALID_HOP, encode, decode, routing_table_bytes)
+
+
+class TestRoutingTable(unittest.TestCase):
+    def test_roundtrip(self):
+        routes = {0: [0], 1: [1, 0], 2: [1, 1, 0], 5: [2, 3, 1, 0]}
+        data = encode(routes)
+        self.assertEqual(data[0], 1)
+        self.assertEqual(decode(data), routes)
+
+    def test_empty(self):
+        self.assertEqual(decode(encode(dict())), dict())
+
+    def test_legacy(self):
+        data = bytearray([INVALID_HOP]*(DEST_COUNT*MAX_HOPS))
+        data[0:1] = [0]
+        data[MAX_HOPS:MAX_HOPS+2] = [1, 0]
+        data[2*MAX_HOPS:2*MAX_HOPS+3] = [1, 2, 0]
+        self.assertEqual(decode(bytes(data)),
+                         {0: [0], 1: [1, 0], 2: [1, 2, 0]})
+
+    def test_legacy_starting_with_version(self):
+        data = bytearray([INVALID_HOP]*(DEST_COUNT*MAX_HOPS))
+        data[0:2] = [1, 0]
+        self.assertEqual(decode(bytes(data)), {0: [1, 0]})
+
+    def test_sparse_of_legacy_length(self):
+        routes = {destination: [1, destination + 1] + [1]*(MAX_HOPS - 3) + [0]
+                  for destination in range(240)}
+        routes[240] = [1, 241] + [1]*26 + [0]
+        data = encode(routes)
+        self.assertEqual(len(data), DEST_COUNT*MAX_HOPS)
+        self.assertEqual(decode(data), routes)
+
+    def test_topology(self):
+        self.assertEqual(decode(routing_table_bytes({0: [], 1: [1], 2: [1, 2]})),
+                         {0: [0], 1: [1, 0], 2: [1, 2, 0]})
+
+    def test_unterminated(self):
+        with self.assertRaises(ValueError):
+            encode({1: [1, 2]})
+        with self.assertRaises(ValueError):
+            encode({1: [1]*MAX_HOPS + [0]})
+        with self.assertRaises(ValueError):
+            decode(bytes([1, 1, 2, 1, 1]))
+
+    def test_unreachable(self):
+        with self.assertRaises(ValueError):
+            encode({1: [1, 0, 2, 0]})
+        with self.assertRaises(ValueError):
+            encode({1: [INVALID_HOP, 0]})
+
+    def test_duplicate_paths(self):
+        encode({0: [0], 1: [1, 0], 2: [1, 2, 0], 3: [1, 3, 0]})
+        with self.assertRaises(ValueError):
+            encode({0: [0], 2: [1, 2, 0], 4: [1, 2, 0]})
+        with self.assertRaises(ValueError):
+            encode({3: [0], 7: [0]})
+
+    def test_invalid(self):
+        with self.assertRaises(ValueError):
+            decode(bytes([2]))
+        with self.assertRaises(ValueError):
+            decode(bytes([1, 1, 3, 1, 0]))
+        with self.assertRaises(ValueError):
+            decode(bytes([1, 1, 2, 1, 0, 1, 2, 2, 0]))
+        with self.assertRaises(ValueError):
+            encode({DEST_COUNT: [0]})